@@ -0,0 +1,798 @@
+use crate::cache::NodeCache;
+use crate::codec::{encode, CollectionStore, NodeCodec};
+use crate::map::{get_bit, popcount_all, set_bit, Bit};
+use crate::tx::{Buffer, Links};
+use libipld::cbor::{DagCbor, DagCborCodec};
+use libipld::prelude::{Store, StoreParams};
+use libipld::DagCbor;
+use libipld::{Cid, Result};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::io::{Error, ErrorKind};
+use std::mem;
+use std::sync::Arc;
+
+// like go-amt, the last index is left unused
+pub const MAX_INDEX: u64 = u64::MAX - 1;
+
+// go-amt numbers the bits of the bitmap starting from the least significant
+// bit of each byte, the hamt bitmap functions from the most significant one
+fn bit(index: usize) -> u8 {
+    (index / 8 * 8 + 7 - index % 8) as u8
+}
+
+// the number of bits an index is shifted by to get its slot at height, None
+// once that's past the width of an u64
+fn shift(bit_width: u64, height: u64) -> Option<u32> {
+    let shift = bit_width.checked_mul(height)?;
+    u32::try_from(shift).ok().filter(|shift| *shift < 64)
+}
+
+// the tree covers all indices once the shift reaches the width of an u64
+fn covers(bit_width: u64, height: u64, index: u64) -> bool {
+    match shift(bit_width, height + 1) {
+        Some(shift) => index < 1 << shift,
+        None => true,
+    }
+}
+
+fn slot(bit_width: u64, height: u64, index: u64) -> usize {
+    let mask = (1 << bit_width) - 1;
+    match shift(bit_width, height) {
+        Some(shift) => (index >> shift & mask) as usize,
+        None => 0,
+    }
+}
+
+fn invalid(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+pub struct AmtConfig<S, C = DagCborCodec>
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+{
+    store: S,
+    codec: C,
+    cache_size: usize,
+    hash: <S::Params as StoreParams>::Hashes,
+    bit_width: u64,
+}
+
+impl<S> AmtConfig<S>
+where
+    S: CollectionStore<DagCborCodec>,
+{
+    pub fn new(store: S, hash: <S::Params as StoreParams>::Hashes) -> Self {
+        Self::with_codec(store, DagCborCodec, hash)
+    }
+}
+
+impl<S, C> AmtConfig<S, C>
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+{
+    pub fn with_codec(store: S, codec: C, hash: <S::Params as StoreParams>::Hashes) -> Self {
+        Self {
+            store,
+            codec,
+            cache_size: 64,
+            hash,
+            bit_width: 3,
+        }
+    }
+
+    pub fn set_cache_size(&mut self, cache_size: usize) {
+        self.cache_size = cache_size;
+    }
+
+    // only applies to new amts, opened ones use the bit width of their root.
+    // it has to be between 1 and 8, new fails otherwise
+    pub fn set_bit_width(&mut self, bit_width: u64) {
+        self.bit_width = bit_width;
+    }
+
+    fn cache<T>(self) -> NodeCache<S, C, Node<T>>
+    where
+        T: DagCbor + Clone + Send + Sync,
+    {
+        NodeCache::new(self.store, self.codec, self.hash, self.cache_size)
+    }
+}
+
+pub struct Amt<S: Store, T: DagCbor, C = DagCborCodec> {
+    cache: Arc<NodeCache<S, C, Node<T>>>,
+    hash: <S::Params as StoreParams>::Hashes,
+    root: Cid,
+    head: Root<T>,
+    tmp: S::TempPin,
+}
+
+impl<S, T, C> Amt<S, T, C>
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+    T: DagCbor + Clone + Send + Sync,
+{
+    pub async fn new(config: AmtConfig<S, C>) -> Result<Self> {
+        if !(1..=8).contains(&config.bit_width) {
+            let msg = format!("bit width {} isn't between 1 and 8", config.bit_width);
+            return Err(Error::new(ErrorKind::InvalidInput, msg).into());
+        }
+        let head = Root::new(config.bit_width);
+        let hash = config.hash;
+        let cache = Arc::new(config.cache());
+        let tmp = cache.temp_pin().await?;
+        let root = insert_root(&cache, hash, &tmp, &head).await?;
+        Ok(Self {
+            cache,
+            hash,
+            root,
+            head,
+            tmp,
+        })
+    }
+
+    pub async fn open(config: AmtConfig<S, C>, root: Cid) -> Result<Self> {
+        let hash = config.hash;
+        let cache = Arc::new(config.cache());
+        let tmp = cache.temp_pin().await?;
+        // the root isn't a node, so it's read from the store directly and
+        // reinserted to pin it
+        let block = Store::get(&**cache, &root).await?;
+        Store::insert(&**cache, &block, Some(&tmp)).await?;
+        let head = cache.decode::<Root<T>>(&block)?;
+        head.check()?;
+        Ok(Self {
+            cache,
+            hash,
+            root,
+            head,
+            tmp,
+        })
+    }
+
+    pub fn root(&self) -> &Cid {
+        &self.root
+    }
+
+    pub async fn from(config: AmtConfig<S, C>, btree: BTreeMap<u64, T>) -> Result<Self> {
+        let mut amt = Self::new(config).await?;
+        let mut tx = amt.transaction();
+        for (index, value) in btree {
+            tx.set(index, value).await?;
+        }
+        tx.commit().await?;
+        Ok(amt)
+    }
+
+    pub fn len(&self) -> u64 {
+        self.head.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head.count == 0
+    }
+
+    pub async fn get(&self, index: u64) -> Result<Option<T>> {
+        let bit_width = self.head.bit_width;
+        if !covers(bit_width, self.head.height, index) {
+            return Ok(None);
+        }
+        let mut node;
+        let mut node_ref = &self.head.node;
+        for height in (1..=self.head.height).rev() {
+            match node_ref.link(slot(bit_width, height, index)) {
+                Some(cid) => node = self.cache.get(cid, Some(&self.tmp)).await?,
+                None => return Ok(None),
+            }
+            node.check(bit_width, height - 1)?;
+            node_ref = &node;
+        }
+        Ok(node_ref.value(slot(bit_width, 0, index)).cloned())
+    }
+
+    pub fn iter(&self) -> AmtIter<'_, S, T, C> {
+        let mut iter = AmtIter {
+            amt: self,
+            stack: vec![],
+            values: vec![],
+        };
+        iter.expand(&self.head.node, self.head.height, 0);
+        iter
+    }
+
+    pub fn transaction(&mut self) -> AmtTransaction<'_, S, T, C> {
+        let head = self.head.clone();
+        let buffer = Buffer::new(self.cache.codec(), self.hash);
+        AmtTransaction {
+            amt: self,
+            head,
+            buffer,
+        }
+    }
+
+    pub async fn set(&mut self, index: u64, value: T) -> Result<()> {
+        let mut tx = self.transaction();
+        tx.set(index, value).await?;
+        tx.commit().await
+    }
+
+    pub async fn delete(&mut self, index: u64) -> Result<()> {
+        let mut tx = self.transaction();
+        tx.delete(index).await?;
+        tx.commit().await
+    }
+
+    pub async fn flush<A: AsRef<[u8]> + Send + Sync>(&mut self, alias: A) -> Result<()> {
+        self.cache.alias(alias, Some(self.root())).await?;
+        self.tmp = self.cache.temp_pin().await?;
+        self.cache.flush().await?;
+        Ok(())
+    }
+}
+
+async fn insert_root<S, T, C>(
+    cache: &NodeCache<S, C, Node<T>>,
+    hash: <S::Params as StoreParams>::Hashes,
+    tmp: &S::TempPin,
+    head: &Root<T>,
+) -> Result<Cid>
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+    T: DagCbor + Clone + Send + Sync,
+{
+    let block = encode::<S::Params, _, _>(cache.codec(), hash, head)?;
+    Store::insert(&**cache, &block, Some(tmp)).await?;
+    Ok(*block.cid())
+}
+
+pub struct AmtTransaction<'a, S: Store, T: DagCbor, C = DagCborCodec> {
+    amt: &'a mut Amt<S, T, C>,
+    head: Root<T>,
+    buffer: Buffer<S, C, Node<T>>,
+}
+
+impl<'a, S, T, C> AmtTransaction<'a, S, T, C>
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+    T: DagCbor + Clone + Send + Sync,
+{
+    pub fn len(&self) -> u64 {
+        self.head.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head.count == 0
+    }
+
+    async fn node(&self, cid: &Cid, height: u64) -> Result<Node<T>> {
+        if let Some(node) = self.buffer.get(cid) {
+            return Ok(node.clone());
+        }
+        let node = self.amt.cache.get(cid, Some(&self.amt.tmp)).await?;
+        node.check(self.head.bit_width, height)?;
+        Ok(node)
+    }
+
+    // loads the nodes from the root down to the leaf holding index, missing
+    // nodes are created empty
+    async fn path(&self, index: u64) -> Result<(Vec<(Node<T>, usize)>, Node<T>)> {
+        let bit_width = self.head.bit_width;
+        let mut path = Vec::with_capacity(self.head.height as usize);
+        let mut node = self.head.node.clone();
+        for height in (1..=self.head.height).rev() {
+            let slot = slot(bit_width, height, index);
+            let child = match node.link(slot) {
+                Some(cid) => self.node(cid, height - 1).await?,
+                None => Node::new(bit_width),
+            };
+            path.push((node, slot));
+            node = child;
+        }
+        Ok((path, node))
+    }
+
+    // retrace the path backwards, dropping the links to emptied nodes
+    fn bubble_up(&mut self, path: Vec<(Node<T>, usize)>, mut node: Node<T>) -> Result<()> {
+        for (mut parent, slot) in path.into_iter().rev() {
+            if node.is_empty() {
+                parent.unset_link(slot);
+            } else {
+                parent.set_link(slot, self.buffer.insert(node)?);
+            }
+            node = parent;
+        }
+        self.head.node = node;
+        Ok(())
+    }
+
+    pub async fn set(&mut self, index: u64, value: T) -> Result<()> {
+        if index > MAX_INDEX {
+            let msg = format!("index {} is past the last index {}", index, MAX_INDEX);
+            return Err(Error::new(ErrorKind::InvalidInput, msg).into());
+        }
+        let bit_width = self.head.bit_width;
+        while !covers(bit_width, self.head.height, index) {
+            if !self.head.node.is_empty() {
+                let node = mem::replace(&mut self.head.node, Node::new(bit_width));
+                let cid = self.buffer.insert(node)?;
+                self.head.node.set_link(0, cid);
+            }
+            self.head.height += 1;
+        }
+        let (path, mut node) = self.path(index).await?;
+        if node.set_value(slot(bit_width, 0, index), value) {
+            self.head.count += 1;
+        }
+        self.bubble_up(path, node)
+    }
+
+    pub async fn delete(&mut self, index: u64) -> Result<()> {
+        let bit_width = self.head.bit_width;
+        if !covers(bit_width, self.head.height, index) {
+            return Ok(());
+        }
+        let (path, mut node) = self.path(index).await?;
+        if !node.unset_value(slot(bit_width, 0, index)) {
+            return Ok(());
+        }
+        self.head.count -= 1;
+        self.bubble_up(path, node)?;
+        // a root that only links to its left-most child is replaced by that
+        // child, so every set of indices has exactly one representation
+        while self.head.height > 0 {
+            if self.head.node.links.is_empty() {
+                self.head.height = 0;
+            } else if self.head.node.links.len() == 1 && self.head.node.link(0).is_some() {
+                let cid = self.head.node.links[0];
+                self.head.node = self.node(&cid, self.head.height - 1).await?;
+                self.head.height -= 1;
+            } else {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn commit(self) -> Result<()> {
+        let Self { amt, head, buffer } = self;
+        buffer
+            .commit_all(&amt.cache, &amt.tmp, head.node.links.clone())
+            .await?;
+        amt.root = insert_root(&amt.cache, amt.hash, &amt.tmp, &head).await?;
+        amt.head = head;
+        Ok(())
+    }
+
+    pub fn rollback(self) {}
+}
+
+pub struct AmtIter<'a, S: Store, T: DagCbor, C = DagCborCodec> {
+    amt: &'a Amt<S, T, C>,
+    stack: Vec<(Cid, u64, u64)>,
+    values: Vec<(u64, T)>,
+}
+
+impl<'a, S, T, C> AmtIter<'a, S, T, C>
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+    T: DagCbor + Clone + Send + Sync,
+{
+    // pushes the children in reverse so they're popped in index order
+    fn expand(&mut self, node: &Node<T>, height: u64, offset: u64) {
+        let shift = self.amt.head.bit_width * height;
+        let slots = node.slots();
+        if height == 0 {
+            for (slot, value) in slots.iter().zip(node.values.iter()).rev() {
+                self.values.push((offset + *slot as u64, value.clone()));
+            }
+        } else {
+            for (slot, cid) in slots.iter().zip(node.links.iter()).rev() {
+                let offset = offset + ((*slot as u64) << shift);
+                self.stack.push((*cid, height - 1, offset));
+            }
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub async fn next(&mut self) -> Result<Option<(u64, T)>> {
+        while self.values.is_empty() {
+            let (cid, height, offset) = match self.stack.pop() {
+                Some(next) => next,
+                None => return Ok(None),
+            };
+            let node = self.amt.cache.get(&cid, Some(&self.amt.tmp)).await?;
+            node.check(self.amt.head.bit_width, height)?;
+            self.expand(&node, height, offset);
+        }
+        Ok(self.values.pop())
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, DagCbor)]
+#[ipld(repr = "tuple")]
+struct Root<T: DagCbor> {
+    bit_width: u64,
+    height: u64,
+    // number of values set
+    count: u64,
+    node: Node<T>,
+}
+
+impl<T: DagCbor> Root<T> {
+    fn new(bit_width: u64) -> Self {
+        Self {
+            bit_width,
+            height: 0,
+            count: 0,
+            node: Node::new(bit_width),
+        }
+    }
+
+    // roots are decoded from untrusted blocks, the bit width and height have
+    // to be in the range go-amt uses before they're used to compute slots
+    fn check(&self) -> Result<()> {
+        if !(1..=8).contains(&self.bit_width) {
+            let msg = format!("bit width {} isn't between 1 and 8", self.bit_width);
+            return Err(invalid(msg).into());
+        }
+        // the root covers all indices once its children are shifted by 64
+        if shift(self.bit_width, self.height).is_none() {
+            let msg = format!("height {} is too large for the amt", self.height);
+            return Err(invalid(msg).into());
+        }
+        self.node.check(self.bit_width, self.height)
+    }
+}
+
+// interior nodes only have links and leaves only values, the bitmap marks
+// which of the 2^bit_width slots are occupied. the field names happen to be
+// in wire order
+#[derive(Clone, Debug, Eq, PartialEq, DagCbor)]
+#[ipld(repr = "tuple")]
+struct Node<T: DagCbor> {
+    bmap: Box<[u8]>,
+    links: Vec<Cid>,
+    values: Vec<T>,
+}
+
+impl<T: DagCbor> Links for Node<T> {
+    fn links(&self) -> Vec<Cid> {
+        self.links.clone()
+    }
+}
+
+impl<T: DagCbor> Node<T> {
+    fn new(bit_width: u64) -> Self {
+        let len = usize::max((1 << bit_width) / 8, 1);
+        Self {
+            bmap: vec![0; len].into_boxed_slice(),
+            links: vec![],
+            values: vec![],
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.links.is_empty() && self.values.is_empty()
+    }
+
+    // the bitmap has to have a bit for each slot, and each set bit a link if
+    // the node is above the leaves or a value if it's a leaf
+    fn check(&self, bit_width: u64, height: u64) -> Result<()> {
+        if self.bmap.len() != Self::new(bit_width).bmap.len() {
+            let msg = format!("bitmap of {} bytes", self.bmap.len());
+            return Err(invalid(msg).into());
+        }
+        let bits: usize = self
+            .bmap
+            .iter()
+            .map(|byte| byte.count_ones() as usize)
+            .sum();
+        let (links, values) = if height == 0 { (0, bits) } else { (bits, 0) };
+        if self.links.len() != links || self.values.len() != values {
+            let msg = format!(
+                "node with {} bits set, {} links and {} values at height {}",
+                bits,
+                self.links.len(),
+                self.values.len(),
+                height
+            );
+            return Err(invalid(msg).into());
+        }
+        Ok(())
+    }
+
+    fn contains(&self, slot: usize) -> bool {
+        get_bit(&self.bmap, bit(slot)) == Bit::One
+    }
+
+    // number of occupied slots before slot
+    fn rank(&self, slot: usize) -> usize {
+        let byte = self.bmap[slot / 8] & ((1 << (slot % 8)) - 1);
+        (popcount_all(&self.bmap[..slot / 8]) + popcount_all(&[byte])) as usize
+    }
+
+    fn slots(&self) -> Vec<usize> {
+        (0..self.bmap.len() * 8)
+            .filter(|slot| self.contains(*slot))
+            .collect()
+    }
+
+    fn link(&self, slot: usize) -> Option<&Cid> {
+        if self.contains(slot) {
+            self.links.get(self.rank(slot))
+        } else {
+            None
+        }
+    }
+
+    fn value(&self, slot: usize) -> Option<&T> {
+        if self.contains(slot) {
+            self.values.get(self.rank(slot))
+        } else {
+            None
+        }
+    }
+
+    fn set_link(&mut self, slot: usize, cid: Cid) {
+        let rank = self.rank(slot);
+        if self.contains(slot) {
+            self.links[rank] = cid;
+        } else {
+            set_bit(&mut self.bmap, bit(slot), Bit::One);
+            self.links.insert(rank, cid);
+        }
+    }
+
+    fn unset_link(&mut self, slot: usize) {
+        if self.contains(slot) {
+            self.links.remove(self.rank(slot));
+            set_bit(&mut self.bmap, bit(slot), Bit::Zero);
+        }
+    }
+
+    // returns true if the slot was empty
+    fn set_value(&mut self, slot: usize, value: T) -> bool {
+        let rank = self.rank(slot);
+        if self.contains(slot) {
+            self.values[rank] = value;
+            false
+        } else {
+            set_bit(&mut self.bmap, bit(slot), Bit::One);
+            self.values.insert(rank, value);
+            true
+        }
+    }
+
+    // returns true if the slot was occupied
+    fn unset_value(&mut self, slot: usize) -> bool {
+        if self.contains(slot) {
+            self.values.remove(self.rank(slot));
+            set_bit(&mut self.bmap, bit(slot), Bit::Zero);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::task;
+    use libipld::block::Block;
+    use libipld::mem::MemStore;
+    use libipld::multihash::{Code, MultihashDigest};
+    use libipld::store::DefaultParams;
+    use proptest::prelude::*;
+
+    async fn bytes(store: &MemStore<DefaultParams>, cid: &Cid) -> Result<Vec<u8>> {
+        Ok(store.get(cid).await?.data().to_vec())
+    }
+
+    fn link(cid: &Cid) -> Vec<u8> {
+        let cid = cid.to_bytes();
+        let mut bytes = vec![0xd8, 0x2a, 0x58, cid.len() as u8 + 1, 0x00];
+        bytes.extend(cid);
+        bytes
+    }
+
+    // the expected encodings are written by hand from the go-amt-ipld v3
+    // schema, root = [bit_width, height, count, node] and
+    // node = [bmap, links, values]. they weren't produced by go-amt itself
+    #[async_std::test]
+    async fn test_amt_encoding() -> Result<()> {
+        let store = MemStore::<DefaultParams>::default();
+        let config = AmtConfig::new(store.clone(), Code::Blake2b256);
+        let mut amt = Amt::new(config).await?;
+        let empty = *amt.root();
+        assert_eq!(
+            bytes(&store, amt.root()).await?,
+            [0x84, 0x03, 0x00, 0x00, 0x83, 0x41, 0x00, 0x80, 0x80]
+        );
+
+        amt.set(0, 1u64).await?;
+        amt.set(7, 2).await?;
+        let leaf = *amt.root();
+        assert_eq!(
+            bytes(&store, amt.root()).await?,
+            [0x84, 0x03, 0x00, 0x02, 0x83, 0x41, 0x81, 0x80, 0x82, 0x01, 0x02]
+        );
+
+        amt.set(8, 3).await?;
+        let mut node = Node::new(3);
+        node.set_value(0, 1u64);
+        node.set_value(7, 2);
+        let left = Block::<DefaultParams>::encode(DagCborCodec, Code::Blake2b256, &node)?;
+        let mut node = Node::new(3);
+        node.set_value(0, 3u64);
+        let right = Block::<DefaultParams>::encode(DagCborCodec, Code::Blake2b256, &node)?;
+        assert_eq!(
+            bytes(&store, left.cid()).await?,
+            [0x83, 0x41, 0x81, 0x80, 0x82, 0x01, 0x02]
+        );
+        assert_eq!(
+            bytes(&store, right.cid()).await?,
+            [0x83, 0x41, 0x01, 0x80, 0x81, 0x03]
+        );
+        let mut expected = vec![0x84, 0x03, 0x01, 0x03, 0x83, 0x41, 0x03, 0x82];
+        expected.extend(link(left.cid()));
+        expected.extend(link(right.cid()));
+        expected.push(0x80);
+        assert_eq!(bytes(&store, amt.root()).await?, expected);
+
+        amt.delete(8).await?;
+        assert_eq!(amt.root(), &leaf);
+        amt.delete(0).await?;
+        amt.delete(7).await?;
+        assert_eq!(amt.root(), &empty);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_amt_bit_width() -> Result<()> {
+        let store = MemStore::<DefaultParams>::default();
+        let mut config = AmtConfig::new(store.clone(), Code::Blake2b256);
+        config.set_bit_width(5);
+        let mut amt = Amt::new(config).await?;
+        assert_eq!(
+            bytes(&store, amt.root()).await?,
+            [0x84, 0x05, 0x00, 0x00, 0x83, 0x44, 0x00, 0x00, 0x00, 0x00, 0x80, 0x80]
+        );
+        amt.set(9, 1u64).await?;
+        assert_eq!(
+            bytes(&store, amt.root()).await?,
+            [0x84, 0x05, 0x00, 0x01, 0x83, 0x44, 0x00, 0x02, 0x00, 0x00, 0x80, 0x81, 0x01]
+        );
+
+        let config = AmtConfig::new(store.clone(), Code::Blake2b256);
+        let amt = Amt::<_, u64>::open(config, *amt.root()).await?;
+        assert_eq!(amt.get(9).await?, Some(1));
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_amt_malformed() -> Result<()> {
+        let store = MemStore::<DefaultParams>::default();
+        let mut config = AmtConfig::new(store.clone(), Code::Blake2b256);
+        config.set_bit_width(9);
+        assert!(Amt::<_, u64>::new(config).await.is_err());
+        let config = AmtConfig::new(store.clone(), Code::Blake2b256);
+        let empty = *Amt::<_, u64>::new(config).await?.root();
+
+        let mut leaf_link = vec![0x84, 0x03, 0x00, 0x01, 0x83, 0x41, 0x01, 0x81];
+        leaf_link.extend(link(&empty));
+        leaf_link.push(0x80);
+        let roots = vec![
+            // bit width 9
+            vec![0x84, 0x09, 0x00, 0x00, 0x83, 0x42, 0x00, 0x00, 0x80, 0x80],
+            // height 22, the slots would be shifted past 64 bits
+            vec![0x84, 0x03, 0x16, 0x00, 0x83, 0x41, 0x00, 0x80, 0x80],
+            // a bitmap of two bytes for bit width 3
+            vec![0x84, 0x03, 0x00, 0x00, 0x83, 0x42, 0x00, 0x00, 0x80, 0x80],
+            // a bit set without a value
+            vec![0x84, 0x03, 0x00, 0x01, 0x83, 0x41, 0x01, 0x80, 0x80],
+            // a leaf with a link
+            leaf_link,
+        ];
+        for bytes in roots {
+            let cid = Cid::new_v1(DagCborCodec.into(), Code::Blake2b256.digest(&bytes));
+            store
+                .insert(&Block::new_unchecked(cid, bytes), None)
+                .await?;
+            let config = AmtConfig::new(store.clone(), Code::Blake2b256);
+            assert!(Amt::<_, u64>::open(config, cid).await.is_err());
+        }
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_amt_sparse() -> Result<()> {
+        let store = MemStore::<DefaultParams>::default();
+        let config = AmtConfig::new(store, Code::Blake2b256);
+        let mut amt = Amt::new(config).await?;
+        let indices = [0, 1 << 20, 3, u64::MAX - 1, 64, 1 << 40];
+        for (i, index) in indices.iter().enumerate() {
+            amt.set(*index, i as u64).await?;
+            assert_eq!(amt.len(), i as u64 + 1);
+        }
+        for (i, index) in indices.iter().enumerate() {
+            assert_eq!(amt.get(*index).await?, Some(i as u64));
+            assert_eq!(amt.get(*index + 1).await?, None);
+        }
+        let mut sorted: Vec<_> = indices
+            .iter()
+            .enumerate()
+            .map(|(i, j)| (*j, i as u64))
+            .collect();
+        sorted.sort();
+        let mut iter = amt.iter();
+        for entry in sorted {
+            assert_eq!(iter.next().await?, Some(entry));
+        }
+        assert_eq!(iter.next().await?, None);
+        for index in indices.iter() {
+            amt.delete(*index).await?;
+            assert_eq!(amt.get(*index).await?, None);
+        }
+        assert!(amt.is_empty());
+        assert_eq!(amt.head, Root::new(3));
+        assert!(amt.set(u64::MAX, 0).await.is_err());
+        assert!(amt.is_empty());
+        Ok(())
+    }
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        Set(u64, u64),
+        Delete(u64),
+    }
+
+    fn op() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            (0..2000u64, any::<u64>()).prop_map(|(i, v)| Op::Set(i, v)),
+            (0..2000u64).prop_map(Op::Delete),
+        ]
+    }
+
+    async fn model(ops: Vec<Op>) -> Result<()> {
+        let store = MemStore::<DefaultParams>::default();
+        let mut amt = Amt::new(AmtConfig::new(store.clone(), Code::Blake2b256)).await?;
+        let mut btree = BTreeMap::new();
+        for op in ops {
+            match op {
+                Op::Set(index, value) => {
+                    amt.set(index, value).await?;
+                    btree.insert(index, value);
+                }
+                Op::Delete(index) => {
+                    amt.delete(index).await?;
+                    btree.remove(&index);
+                }
+            }
+            assert_eq!(amt.len(), btree.len() as u64);
+        }
+        let mut iter = amt.iter();
+        for (index, value) in btree.iter() {
+            assert_eq!(iter.next().await?, Some((*index, *value)));
+        }
+        assert_eq!(iter.next().await?, None);
+        // the same indices always produce the same tree
+        let other = Amt::from(AmtConfig::new(store, Code::Blake2b256), btree).await?;
+        assert_eq!(amt.root(), other.root());
+        Ok(())
+    }
+
+    proptest! {
+        #[test]
+        fn test_amt_model(ops in prop::collection::vec(op(), 0..200)) {
+            task::block_on(model(ops)).unwrap();
+        }
+    }
+}
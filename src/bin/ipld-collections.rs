@@ -0,0 +1,542 @@
+use async_trait::async_trait;
+use clap::{App, AppSettings, Arg, ArgGroup, ArgMatches, SubCommand};
+use libipld::block::Block;
+use libipld::cbor::DagCborCodec;
+use libipld::cid::Cid;
+use libipld::error::{BlockNotFound, Error, Result, UnsupportedCodec};
+use libipld::ipld::Ipld;
+use libipld::json::DagJsonCodec;
+use libipld::store::{DefaultParams, Store};
+use libipld_collections::{
+    detect, read_car, Collection, CollectionStore, Hamt, List, NodeCodec, Stats,
+};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs::{self, File};
+use std::io::{self, BufReader, ErrorKind, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+// a directory holding one file per block named after its cid, or the blocks
+// of a car file in memory
+#[derive(Clone)]
+enum LocalStore {
+    Dir(PathBuf),
+    Mem(Arc<Mutex<HashMap<Cid, Vec<u8>>>>),
+}
+
+#[async_trait]
+impl Store for LocalStore {
+    type Params = DefaultParams;
+    type TempPin = ();
+
+    async fn temp_pin(&self) -> Result<Self::TempPin> {
+        Ok(())
+    }
+
+    async fn contains(&self, cid: &Cid) -> Result<bool> {
+        Ok(match self {
+            LocalStore::Dir(dir) => dir.join(cid.to_string()).exists(),
+            LocalStore::Mem(blocks) => blocks.lock().unwrap().contains_key(cid),
+        })
+    }
+
+    async fn get(&self, cid: &Cid) -> Result<Block<DefaultParams>> {
+        let data = match self {
+            LocalStore::Dir(dir) => {
+                fs::read(dir.join(cid.to_string())).map_err(|_| BlockNotFound(*cid))?
+            }
+            LocalStore::Mem(blocks) => blocks
+                .lock()
+                .unwrap()
+                .get(cid)
+                .cloned()
+                .ok_or(BlockNotFound(*cid))?,
+        };
+        Block::new(*cid, data)
+    }
+
+    async fn insert(&self, block: &Block<DefaultParams>, _: Option<&Self::TempPin>) -> Result<()> {
+        match self {
+            LocalStore::Dir(dir) => fs::write(dir.join(block.cid().to_string()), block.data())?,
+            LocalStore::Mem(blocks) => {
+                blocks
+                    .lock()
+                    .unwrap()
+                    .insert(*block.cid(), block.data().to_vec());
+            }
+        }
+        Ok(())
+    }
+
+    async fn alias<T: AsRef<[u8]> + Send + Sync>(&self, _: T, _: Option<&Cid>) -> Result<()> {
+        Ok(())
+    }
+
+    async fn resolve<T: AsRef<[u8]> + Send + Sync>(&self, _: T) -> Result<Option<Cid>> {
+        Ok(None)
+    }
+
+    async fn reverse_alias(&self, _: &Cid) -> Result<Option<Vec<Vec<u8>>>> {
+        Ok(None)
+    }
+
+    async fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+fn app() -> App<'static, 'static> {
+    let root = || {
+        Arg::with_name("root")
+            .required(true)
+            .help("Root cid of the collection")
+    };
+    App::new("ipld-collections")
+        .about("Inspects the roots of ipld collections")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(
+            Arg::with_name("dir")
+                .long("dir")
+                .value_name("DIR")
+                .help("Directory holding one file per block, named after its cid"),
+        )
+        .arg(
+            Arg::with_name("car")
+                .long("car")
+                .value_name("FILE")
+                .help("CARv1 file holding the blocks"),
+        )
+        .group(
+            ArgGroup::with_name("blocks")
+                .args(&["dir", "car"])
+                .required(true),
+        )
+        .subcommand(
+            SubCommand::with_name("list")
+                .about("Reads a list")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("get")
+                        .about("Prints the value at an index")
+                        .arg(root())
+                        .arg(Arg::with_name("index").required(true)),
+                )
+                .subcommand(
+                    SubCommand::with_name("len")
+                        .about("Prints the number of values")
+                        .arg(root()),
+                )
+                .subcommand(
+                    SubCommand::with_name("range")
+                        .about("Prints the values from start up to end, one per line")
+                        .arg(root())
+                        .arg(Arg::with_name("start").required(true))
+                        .arg(Arg::with_name("end").required(true)),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("hamt")
+                .about("Reads a hamt, keys starting with 0x are read as hex")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("get")
+                        .about("Prints the value of a key")
+                        .arg(root())
+                        .arg(Arg::with_name("key").required(true)),
+                )
+                .subcommand(
+                    SubCommand::with_name("keys")
+                        .about("Prints the keys, one per line")
+                        .arg(root()),
+                )
+                .subcommand(
+                    SubCommand::with_name("dump")
+                        .about("Prints the entries as a json array")
+                        .arg(root()),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("stats")
+                .about("Describes the shape of a collection")
+                .arg(root()),
+        )
+        .subcommand(
+            SubCommand::with_name("verify")
+                .about("Checks the invariants of a collection")
+                .arg(root()),
+        )
+        .subcommand(
+            SubCommand::with_name("diff")
+                .about("Prints how a collection differs from another one of the same kind")
+                .arg(root())
+                .arg(Arg::with_name("other").required(true)),
+        )
+}
+
+fn error(msg: String) -> Error {
+    io::Error::new(ErrorKind::InvalidInput, msg).into()
+}
+
+fn unsupported(root: &Cid) -> Error {
+    error(format!("{} is the root of a prolly tree", root))
+}
+
+fn arg<'a>(args: &'a ArgMatches, name: &str) -> Result<&'a str> {
+    args.value_of(name)
+        .ok_or_else(|| error(format!("missing {}", name)))
+}
+
+fn cid(args: &ArgMatches, name: &str) -> Result<Cid> {
+    let arg = arg(args, name)?;
+    Cid::try_from(arg).map_err(|err| error(format!("invalid cid {}: {}", arg, err)))
+}
+
+fn number(args: &ArgMatches, name: &str) -> Result<usize> {
+    let arg = arg(args, name)?;
+    arg.parse()
+        .map_err(|_| error(format!("invalid {} {}", name, arg)))
+}
+
+fn parse_key(key: &str) -> Result<Vec<u8>> {
+    let hex = match key.strip_prefix("0x") {
+        Some(hex) if hex.len() % 2 == 0 => hex,
+        Some(_) => return Err(error(format!("invalid key {}", key))),
+        None => return Ok(key.as_bytes().to_vec()),
+    };
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&hex[i..i + 2], 16)
+                .map_err(|_| error(format!("invalid key {}", key)))
+        })
+        .collect()
+}
+
+// keys that aren't printable are written as hex
+fn format_key(key: &[u8]) -> String {
+    match std::str::from_utf8(key) {
+        Ok(key) if !key.starts_with("0x") && !key.chars().any(char::is_control) => key.to_string(),
+        _ => {
+            let hex: String = key.iter().map(|byte| format!("{:02x}", byte)).collect();
+            format!("0x{}", hex)
+        }
+    }
+}
+
+fn json(value: &Ipld) -> Result<String> {
+    Ok(String::from_utf8(DagJsonCodec.encode_node(value)?)?)
+}
+
+async fn open_list<C>(store: LocalStore, root: Cid) -> Result<List<LocalStore, Ipld, C>>
+where
+    C: NodeCodec,
+    LocalStore: CollectionStore<C>,
+{
+    match detect(store, &root).await? {
+        Collection::List(config) => List::open(config, root).await,
+        Collection::Hamt(_) => Err(error(format!("{} is the root of a hamt", root))),
+        Collection::Prolly(_) => Err(unsupported(&root)),
+    }
+}
+
+async fn open_hamt<C>(store: LocalStore, root: Cid) -> Result<Hamt<LocalStore, Ipld, C>>
+where
+    C: NodeCodec,
+    LocalStore: CollectionStore<C>,
+{
+    match detect(store, &root).await? {
+        Collection::Hamt(config) => Hamt::open(config, root).await,
+        Collection::List(_) => Err(error(format!("{} is the root of a list", root))),
+        Collection::Prolly(_) => Err(unsupported(&root)),
+    }
+}
+
+fn print_stats<W: Write>(out: &mut W, stats: &Stats) -> Result<()> {
+    writeln!(out, "depth: {}", stats.depth())?;
+    writeln!(out, "nodes per level: {:?}", stats.nodes)?;
+    writeln!(out, "blocks: {}", stats.blocks)?;
+    writeln!(out, "bytes: {}", stats.bytes)?;
+    Ok(())
+}
+
+// runs cmd, writing what it prints to out
+async fn command<C, W>(
+    store: LocalStore,
+    cmd: &str,
+    args: &ArgMatches<'_>,
+    out: &mut W,
+) -> Result<()>
+where
+    C: NodeCodec,
+    LocalStore: CollectionStore<C>,
+    W: Write,
+{
+    let root = cid(args, "root")?;
+    match cmd {
+        "list get" => {
+            let index = number(args, "index")?;
+            match open_list::<C>(store, root).await?.get(index).await? {
+                Some(value) => writeln!(out, "{}", json(&value)?)?,
+                None => return Err(error(format!("no value at index {}", index))),
+            }
+        }
+        "list len" => writeln!(out, "{}", open_list::<C>(store, root).await?.len().await?)?,
+        "list range" => {
+            let list = open_list::<C>(store, root).await?;
+            let start = number(args, "start")?;
+            let end = number(args, "end")?;
+            let mut iter = list.iter_from(start);
+            for _ in start..end {
+                match iter.next().await? {
+                    Some(value) => writeln!(out, "{}", json(&value)?)?,
+                    None => break,
+                }
+            }
+        }
+        "hamt get" => {
+            let key = arg(args, "key")?;
+            match open_hamt::<C>(store, root)
+                .await?
+                .get(&parse_key(key)?)
+                .await?
+            {
+                Some(value) => writeln!(out, "{}", json(&value)?)?,
+                None => return Err(error(format!("no value for key {}", key))),
+            }
+        }
+        "hamt keys" => {
+            let hamt = open_hamt::<C>(store, root).await?;
+            let mut iter = hamt.iter();
+            while let Some((key, _)) = iter.next().await? {
+                writeln!(out, "{}", format_key(&key))?;
+            }
+        }
+        "hamt dump" => {
+            open_hamt::<C>(store, root)
+                .await?
+                .export_json(&mut *out)
+                .await?;
+            writeln!(out)?;
+        }
+        "stats" => match detect::<_, C>(store, &root).await? {
+            Collection::List(config) => {
+                let stats = List::<_, Ipld, C>::open(config, root)
+                    .await?
+                    .stats()
+                    .await?;
+                print_stats(out, &stats)?;
+                writeln!(out, "leaf fill: {:.2}", stats.leaf_fill)?;
+            }
+            Collection::Hamt(config) => {
+                let stats = Hamt::<_, Ipld, C>::open(config, root)
+                    .await?
+                    .stats()
+                    .await?;
+                print_stats(out, &stats)?;
+                writeln!(out, "buckets by entries: {:?}", stats.buckets)?;
+            }
+            Collection::Prolly(_) => return Err(unsupported(&root)),
+        },
+        "verify" => {
+            let report = match detect::<_, C>(store, &root).await? {
+                Collection::List(config) => {
+                    List::<_, Ipld, C>::open(config, root).await?.verify().await
+                }
+                Collection::Hamt(config) => {
+                    Hamt::<_, Ipld, C>::open(config, root).await?.verify().await
+                }
+                Collection::Prolly(_) => return Err(unsupported(&root)),
+            };
+            writeln!(out, "blocks: {}", report.blocks)?;
+            for problem in report.problems.iter() {
+                writeln!(out, "{:?}", problem)?;
+            }
+            if !report.is_ok() {
+                return Err(error(format!("{} problems found", report.problems.len())));
+            }
+        }
+        "diff" => {
+            let other = cid(args, "other")?;
+            match detect::<_, C>(store.clone(), &root).await? {
+                Collection::List(config) => {
+                    let list = List::<_, Ipld, C>::open(config, root).await?;
+                    let other = open_list::<C>(store, other).await?;
+                    for (index, a, b) in list.diff(&other).await? {
+                        match (a, b) {
+                            (Some(a), Some(b)) => {
+                                writeln!(out, "~ {}: {} -> {}", index, json(&a)?, json(&b)?)?
+                            }
+                            (Some(a), None) => writeln!(out, "- {}: {}", index, json(&a)?)?,
+                            (None, Some(b)) => writeln!(out, "+ {}: {}", index, json(&b)?)?,
+                            (None, None) => {}
+                        }
+                    }
+                }
+                Collection::Hamt(config) => {
+                    let hamt = Hamt::<_, Ipld, C>::open(config, root).await?;
+                    let other = open_hamt::<C>(store, other).await?;
+                    for (key, a, b) in hamt.diff(&other).await? {
+                        let key = format_key(&key);
+                        match (a, b) {
+                            (Some(a), Some(b)) => {
+                                writeln!(out, "~ {}: {} -> {}", key, json(&a)?, json(&b)?)?
+                            }
+                            (Some(a), None) => writeln!(out, "- {}: {}", key, json(&a)?)?,
+                            (None, Some(b)) => writeln!(out, "+ {}: {}", key, json(&b)?)?,
+                            (None, None) => {}
+                        }
+                    }
+                }
+                Collection::Prolly(_) => return Err(unsupported(&root)),
+            }
+        }
+        _ => return Err(error(format!("unknown command {}", cmd))),
+    }
+    Ok(())
+}
+
+// the blocks named by --dir or --car
+async fn store(matches: &ArgMatches<'_>) -> Result<LocalStore> {
+    match (matches.value_of("dir"), matches.value_of("car")) {
+        (Some(dir), _) => Ok(LocalStore::Dir(dir.into())),
+        (None, Some(car)) => {
+            let store = LocalStore::Mem(Default::default());
+            read_car(&store, BufReader::new(File::open(car)?)).await?;
+            Ok(store)
+        }
+        (None, None) => Err(error("either --dir or --car is needed".to_string())),
+    }
+}
+
+// the command's name and the arguments of the innermost subcommand
+fn subcommand<'a, 'b>(matches: &'a ArgMatches<'b>) -> (String, &'a ArgMatches<'b>) {
+    let (mut cmd, mut args) = (String::new(), matches);
+    while let (name, Some(sub)) = args.subcommand() {
+        if !cmd.is_empty() {
+            cmd.push(' ');
+        }
+        cmd.push_str(name);
+        args = sub;
+    }
+    (cmd, args)
+}
+
+#[async_std::main]
+async fn main() -> Result<()> {
+    let matches = app().get_matches();
+    let store = store(&matches).await?;
+    let (cmd, args) = subcommand(&matches);
+    let root = cid(args, "root")?;
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    match root.codec() {
+        code if code == u64::from(DagCborCodec) => {
+            command::<DagCborCodec, _>(store, &cmd, args, &mut out).await
+        }
+        code if code == u64::from(DagJsonCodec) => {
+            command::<DagJsonCodec, _>(store, &cmd, args, &mut out).await
+        }
+        code => Err(UnsupportedCodec(code).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libipld::multihash::Code;
+    use libipld_collections::{HamtConfig, ListConfig};
+    use tempdir::TempDir;
+
+    // returns what the command printed
+    async fn run(blocks: &[&str], args: &[&str]) -> Result<String> {
+        let mut argv = vec!["ipld-collections"];
+        argv.extend(blocks);
+        argv.extend(args);
+        let matches = app()
+            .get_matches_from_safe(argv)
+            .map_err(|err| error(err.to_string()))?;
+        let store = store(&matches).await?;
+        let (cmd, args) = subcommand(&matches);
+        let mut out = vec![];
+        command::<DagCborCodec, _>(store, &cmd, args, &mut out).await?;
+        Ok(String::from_utf8(out)?)
+    }
+
+    fn lines(values: impl Iterator<Item = impl ToString>) -> String {
+        values.map(|value| value.to_string() + "\n").collect()
+    }
+
+    #[async_std::test]
+    async fn test_command() -> Result<()> {
+        let dir = TempDir::new("ipld-collections")?;
+        let path = dir.path().join("blocks");
+        fs::create_dir(&path)?;
+        let store = LocalStore::Dir(path.clone());
+        let mut config = ListConfig::new(store.clone(), Code::Blake2b256);
+        config.set_width(4);
+        let list = List::from(config, (0..20).map(Ipld::Integer)).await?;
+        let car = dir.path().join("list.car");
+        list.export_car(File::create(&car)?).await?;
+        let root = list.root().to_string();
+        let root = root.as_str();
+
+        let car = ["--car", car.to_str().unwrap()];
+        assert_eq!(run(&car, &["list", "len", root]).await?, "20\n");
+        assert_eq!(run(&car, &["list", "get", root, "3"]).await?, "3\n");
+        assert_eq!(
+            run(&car, &["list", "range", root, "5", "30"]).await?,
+            lines(5..20)
+        );
+        assert_eq!(
+            run(&car, &["list", "range", root, "18", "19"]).await?,
+            "18\n"
+        );
+        assert!(run(&car, &["stats", root]).await?.contains("depth: "));
+        assert!(run(&car, &["verify", root]).await?.starts_with("blocks: "));
+        assert!(run(&car, &["list", "get", root, "20"]).await.is_err());
+        assert!(run(&car, &["list", "get", "root", "3"]).await.is_err());
+        assert!(run(&car, &["hamt", "keys", root]).await.is_err());
+
+        // the other list and the hamts are only in the directory
+        let mut config = ListConfig::new(store.clone(), Code::Blake2b256);
+        config.set_width(4);
+        let values = (0..21).map(|i| Ipld::Integer(if i == 7 { 70 } else { i }));
+        let other = List::from(config, values).await?.root().to_string();
+        let other = other.as_str();
+        let mut hamt = Hamt::new(HamtConfig::new(store, Code::Blake2b256)).await?;
+        for i in 0..20u8 {
+            hamt.insert(Box::new([i]), Ipld::Integer(i.into())).await?;
+        }
+        let hamt_root = hamt.root().to_string();
+        let hamt_root = hamt_root.as_str();
+        hamt.remove(&[3]).await?;
+        let other_hamt = hamt.root().to_string();
+        let other_hamt = other_hamt.as_str();
+
+        let dir = ["--dir", path.to_str().unwrap()];
+        assert_eq!(
+            run(&dir, &["diff", root, other]).await?,
+            "~ 7: 7 -> 70\n+ 20: 20\n"
+        );
+        assert_eq!(
+            run(&dir, &["diff", hamt_root, other_hamt]).await?,
+            "- 0x03: 3\n"
+        );
+        assert_eq!(run(&dir, &["hamt", "get", hamt_root, "0x03"]).await?, "3\n");
+        // the keys come in hash order
+        let keys = run(&dir, &["hamt", "keys", other_hamt]).await?;
+        let mut keys: Vec<_> = keys.lines().collect();
+        keys.sort_unstable();
+        let expected: Vec<_> = (0..20u8)
+            .filter(|i| *i != 3)
+            .map(|i| format!("0x{:02x}", i))
+            .collect();
+        assert_eq!(keys, expected);
+        assert!(run(&dir, &["hamt", "get", other_hamt, "0x03"])
+            .await
+            .is_err());
+        assert!(run(&dir, &["diff", root, hamt_root]).await.is_err());
+        Ok(())
+    }
+}
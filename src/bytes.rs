@@ -0,0 +1,669 @@
+use crate::cache::NodeCache;
+use crate::codec::{CollectionStore, NodeCodec};
+use crate::tx::{Buffer, Links};
+use libipld::cbor::DagCborCodec;
+use libipld::prelude::{Store, StoreParams};
+use libipld::DagCbor;
+use libipld::{Cid, Result};
+use std::cmp::{max, min};
+use std::future::Future;
+use std::io::{Error, ErrorKind};
+use std::pin::Pin;
+use std::sync::Arc;
+
+type ChildrenFuture<'a> = Pin<Box<dyn Future<Output = Result<Vec<Child>>> + Send + 'a>>;
+
+pub struct BytesConfig<S, C = DagCborCodec>
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+{
+    store: S,
+    codec: C,
+    cache_size: usize,
+    hash: <S::Params as StoreParams>::Hashes,
+    chunk_size: Option<usize>,
+    width: Option<usize>,
+}
+
+impl<S> BytesConfig<S>
+where
+    S: CollectionStore<DagCborCodec>,
+{
+    pub fn new(store: S, hash: <S::Params as StoreParams>::Hashes) -> Self {
+        Self::with_codec(store, DagCborCodec, hash)
+    }
+}
+
+impl<S, C> BytesConfig<S, C>
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+{
+    pub fn with_codec(store: S, codec: C, hash: <S::Params as StoreParams>::Hashes) -> Self {
+        Self {
+            store,
+            codec,
+            cache_size: 64,
+            hash,
+            chunk_size: None,
+            width: None,
+        }
+    }
+
+    pub fn set_cache_size(&mut self, cache_size: usize) {
+        self.cache_size = cache_size;
+    }
+
+    pub fn set_chunk_size(&mut self, chunk_size: usize) {
+        assert!(chunk_size > 0, "chunk size has to be at least 1");
+        self.chunk_size = Some(chunk_size);
+    }
+
+    pub fn set_width(&mut self, width: usize) {
+        assert!(width > 1, "width has to be at least 2");
+        self.width = Some(width);
+    }
+
+    fn chunk_size(&self) -> usize {
+        if let Some(chunk_size) = self.chunk_size {
+            chunk_size
+        } else {
+            // the codec decides how many bytes fit into a leaf
+            self.codec
+                .max_bytes(<S::Params as StoreParams>::MAX_BLOCK_SIZE)
+        }
+    }
+
+    fn width(&self) -> usize {
+        if let Some(width) = self.width {
+            width
+        } else {
+            let elem_size = std::mem::size_of::<Cid>() + std::mem::size_of::<u64>();
+            <S::Params as StoreParams>::MAX_BLOCK_SIZE / elem_size
+        }
+    }
+
+    fn cache(self) -> NodeCache<S, C, Node> {
+        NodeCache::new(self.store, self.codec, self.hash, self.cache_size)
+    }
+}
+
+// a byte sequence split into leaves of at most chunk_size bytes. all leaves
+// are at the same depth and branches have at most width children. besides the
+// root no node is less than half full, so the tree stays balanced while it's
+// edited
+pub struct Bytes<S: Store, C = DagCborCodec> {
+    cache: Arc<NodeCache<S, C, Node>>,
+    hash: <S::Params as StoreParams>::Hashes,
+    root: Cid,
+    tmp: S::TempPin,
+    chunk_size: usize,
+    width: usize,
+}
+
+impl<S, C> Bytes<S, C>
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+{
+    pub async fn new(config: BytesConfig<S, C>) -> Result<Self> {
+        let chunk_size = config.chunk_size();
+        let width = config.width();
+        let hash = config.hash;
+        let cache = Arc::new(config.cache());
+        let tmp = cache.temp_pin().await?;
+        let root = cache.insert(Node::Leaf(Box::new([])), Some(&tmp)).await?;
+        Ok(Self {
+            cache,
+            hash,
+            root,
+            tmp,
+            chunk_size,
+            width,
+        })
+    }
+
+    pub async fn open(config: BytesConfig<S, C>, root: Cid) -> Result<Self> {
+        let chunk_size = config.chunk_size();
+        let width = config.width();
+        let hash = config.hash;
+        let cache = Arc::new(config.cache());
+        let tmp = cache.temp_pin().await?;
+        // warm up the cache and make sure it's available
+        cache.get(&root, Some(&tmp)).await?;
+        Ok(Self {
+            cache,
+            hash,
+            root,
+            tmp,
+            chunk_size,
+            width,
+        })
+    }
+
+    pub fn root(&self) -> &Cid {
+        &self.root
+    }
+
+    pub async fn from(config: BytesConfig<S, C>, data: &[u8]) -> Result<Self> {
+        let mut bytes = Self::new(config).await?;
+        bytes.append(data).await?;
+        Ok(bytes)
+    }
+
+    pub async fn len(&self) -> Result<u64> {
+        let root = self.cache.get(&self.root, Some(&self.tmp)).await?;
+        Ok(root.len())
+    }
+
+    pub async fn is_empty(&self) -> Result<bool> {
+        Ok(self.len().await? == 0)
+    }
+
+    // reads up to len bytes starting at offset, only loading the leaves that
+    // overlap the range
+    pub async fn read_at(&self, offset: u64, len: u64) -> Result<Vec<u8>> {
+        let end = offset.saturating_add(len);
+        let mut data = vec![];
+        let mut stack = vec![(self.root, 0)];
+        while let Some((cid, start)) = stack.pop() {
+            match self.cache.get(&cid, Some(&self.tmp)).await? {
+                Node::Leaf(bytes) => {
+                    let to = min(end - start, bytes.len() as u64) as usize;
+                    let from = min(offset.saturating_sub(start) as usize, to);
+                    data.extend_from_slice(&bytes[from..to]);
+                }
+                Node::Branch(children) => {
+                    let mut start = start;
+                    let mut overlapping = vec![];
+                    for child in children {
+                        if start < end && offset < start + child.len {
+                            overlapping.push((child.link, start));
+                        }
+                        start += child.len;
+                    }
+                    stack.extend(overlapping.into_iter().rev());
+                }
+            }
+        }
+        Ok(data)
+    }
+
+    pub fn transaction(&mut self) -> BytesTransaction<'_, S, C> {
+        let root = self.root;
+        let buffer = Buffer::new(self.cache.codec(), self.hash);
+        BytesTransaction {
+            bytes: self,
+            root,
+            buffer,
+        }
+    }
+
+    pub async fn append(&mut self, data: &[u8]) -> Result<()> {
+        let mut tx = self.transaction();
+        tx.append(data).await?;
+        tx.commit().await
+    }
+
+    pub async fn splice(&mut self, offset: u64, delete: u64, insert: &[u8]) -> Result<()> {
+        let mut tx = self.transaction();
+        tx.splice(offset, delete, insert).await?;
+        tx.commit().await
+    }
+
+    pub async fn flush<A: AsRef<[u8]> + Send + Sync>(&mut self, alias: A) -> Result<()> {
+        self.cache.alias(alias, Some(self.root())).await?;
+        self.tmp = self.cache.temp_pin().await?;
+        self.cache.flush().await?;
+        Ok(())
+    }
+}
+
+pub struct BytesTransaction<'a, S: Store, C = DagCborCodec> {
+    bytes: &'a mut Bytes<S, C>,
+    root: Cid,
+    buffer: Buffer<S, C, Node>,
+}
+
+impl<'a, S, C> BytesTransaction<'a, S, C>
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+{
+    pub fn root(&self) -> &Cid {
+        &self.root
+    }
+
+    async fn node(&self, cid: &Cid) -> Result<Node> {
+        if let Some(node) = self.buffer.get(cid) {
+            return Ok(node.clone());
+        }
+        self.bytes.cache.get(cid, Some(&self.bytes.tmp)).await
+    }
+
+    pub async fn len(&self) -> Result<u64> {
+        Ok(self.node(&self.root).await?.len())
+    }
+
+    fn leaves(&mut self, data: &[u8]) -> Result<Vec<Child>> {
+        let mut children = vec![];
+        for chunk in split(data, self.bytes.chunk_size) {
+            let link = self.buffer.insert(Node::Leaf(chunk.into()))?;
+            children.push(Child::new(chunk.len() as u64, link));
+        }
+        Ok(children)
+    }
+
+    fn branches(&mut self, children: Vec<Child>) -> Result<Vec<Child>> {
+        let mut branches = vec![];
+        for chunk in split(&children, self.bytes.width) {
+            let len = chunk.iter().map(|child| child.len).sum();
+            let link = self.buffer.insert(Node::Branch(chunk.to_vec()))?;
+            branches.push(Child::new(len, link));
+        }
+        Ok(branches)
+    }
+
+    // replaces the node with the nodes of the same height holding the spliced
+    // range, there can be none or several of them if it overflowed
+    fn splice_node<'b>(
+        &'b mut self,
+        cid: Cid,
+        offset: u64,
+        delete: u64,
+        insert: Option<&'b [u8]>,
+    ) -> ChildrenFuture<'b> {
+        Box::pin(async move {
+            let children = match self.node(&cid).await? {
+                Node::Leaf(bytes) => {
+                    let (offset, delete) = (offset as usize, delete as usize);
+                    let mut data = bytes[..offset].to_vec();
+                    data.extend_from_slice(insert.unwrap_or_default());
+                    data.extend_from_slice(&bytes[offset + delete..]);
+                    return self.leaves(&data);
+                }
+                Node::Branch(children) => children,
+            };
+            let end = offset + delete;
+            let last = children.len() - 1;
+            let mut insert = insert;
+            let mut spliced = Vec::with_capacity(children.len());
+            // the range of spliced holding the nodes returned by the children
+            let (mut lo, mut hi) = (None, 0);
+            let mut start = 0;
+            for (i, child) in children.into_iter().enumerate() {
+                let child_end = start + child.len;
+                let overlaps = start < end && offset < child_end;
+                // inserted bytes go into the child containing offset, or
+                // the last one when appending
+                let hosts =
+                    insert.is_some() && offset >= start && (offset < child_end || i == last);
+                if !overlaps && !hosts {
+                    spliced.push(child);
+                } else {
+                    let local_offset = offset.saturating_sub(start);
+                    let local_delete = if overlaps {
+                        min(end, child_end) - max(offset, start)
+                    } else {
+                        0
+                    };
+                    // subtrees that are deleted entirely aren't even loaded
+                    if hosts || local_delete < child.len {
+                        let insert = if hosts { insert.take() } else { None };
+                        let children = self
+                            .splice_node(child.link, local_offset, local_delete, insert)
+                            .await?;
+                        lo.get_or_insert(spliced.len());
+                        spliced.extend(children);
+                        hi = spliced.len();
+                    }
+                }
+                start = child_end;
+            }
+            if spliced.is_empty() {
+                Ok(spliced)
+            } else {
+                let spliced = self.rebalance(spliced, lo.unwrap_or_default(), hi).await?;
+                self.branches(spliced)
+            }
+        })
+    }
+
+    async fn underfull(&self, child: &Child) -> Result<bool> {
+        Ok(match self.node(&child.link).await? {
+            Node::Leaf(bytes) => bytes.len() * 2 < self.bytes.chunk_size,
+            Node::Branch(children) => children.len() * 2 < self.bytes.width,
+        })
+    }
+
+    // merges the nodes in lo..hi that are less than half full with one of
+    // their siblings, so small splices don't leave a trail of small nodes
+    fn rebalance(&mut self, children: Vec<Child>, lo: usize, hi: usize) -> ChildrenFuture<'_> {
+        Box::pin(async move {
+            let mut children = children;
+            let (mut i, mut hi) = (lo, hi);
+            while i < hi && children.len() > 1 {
+                if !self.underfull(&children[i]).await? {
+                    i += 1;
+                    continue;
+                }
+                // the last node is merged with the one before it
+                let j = if i + 1 < children.len() { i } else { i - 1 };
+                let merged = self
+                    .merge(children[j].clone(), children[j + 1].clone())
+                    .await?;
+                let n = merged.len();
+                children.splice(j..j + 2, merged);
+                if n == 1 {
+                    // a single node can still be underfull
+                    i = j;
+                    hi = max(hi - 1, j + 1);
+                } else {
+                    // the nodes are split evenly, so they're at least half full
+                    i = j + n;
+                    hi += n - 2;
+                }
+            }
+            Ok(children)
+        })
+    }
+
+    // replaces two neighbouring nodes of the same height with one, or with
+    // two that split their contents evenly if they don't fit into one
+    fn merge(&mut self, a: Child, b: Child) -> ChildrenFuture<'_> {
+        Box::pin(async move {
+            match (self.node(&a.link).await?, self.node(&b.link).await?) {
+                (Node::Leaf(a), Node::Leaf(b)) => {
+                    let mut data = a.to_vec();
+                    data.extend_from_slice(&b);
+                    self.leaves(&data)
+                }
+                (Node::Branch(mut a), Node::Branch(b)) => {
+                    // an underfull branch can have an underfull child left
+                    // that couldn't be merged, it's next to its new siblings
+                    let i = a.len();
+                    a.extend(b);
+                    let children = self.rebalance(a, i.saturating_sub(1), i + 1).await?;
+                    self.branches(children)
+                }
+                _ => {
+                    let msg = "bytes with leaves at different depths";
+                    Err(Error::new(ErrorKind::InvalidData, msg).into())
+                }
+            }
+        })
+    }
+
+    pub async fn splice(&mut self, offset: u64, delete: u64, insert: &[u8]) -> Result<()> {
+        let len = self.len().await?;
+        if offset > len {
+            let msg = format!("splice offset {} is past the end at {}", offset, len);
+            return Err(Error::new(ErrorKind::InvalidInput, msg).into());
+        }
+        let delete = min(delete, len - offset);
+        if delete == 0 && insert.is_empty() {
+            return Ok(());
+        }
+        let mut children = self
+            .splice_node(self.root, offset, delete, Some(insert))
+            .await?;
+        while children.len() > 1 {
+            children = self.branches(children)?;
+        }
+        self.root = match children.pop() {
+            Some(child) => child.link,
+            None => self.buffer.insert(Node::Leaf(Box::new([])))?,
+        };
+        // deletions can leave a chain of branches with a single child
+        while let Node::Branch(children) = self.node(&self.root).await? {
+            if children.len() > 1 {
+                break;
+            }
+            self.root = children[0].link;
+        }
+        Ok(())
+    }
+
+    pub async fn append(&mut self, data: &[u8]) -> Result<()> {
+        let len = self.len().await?;
+        self.splice(len, 0, data).await
+    }
+
+    pub async fn commit(self) -> Result<()> {
+        let Self {
+            bytes,
+            root,
+            buffer,
+        } = self;
+        buffer.commit(&bytes.cache, &bytes.tmp, &root).await?;
+        bytes.root = root;
+        Ok(())
+    }
+
+    pub fn rollback(self) {}
+}
+
+// splits items into as few chunks of at most size items as possible, with
+// lengths that differ by at most one
+fn split<T>(items: &[T], size: usize) -> Vec<&[T]> {
+    let n = (items.len() + size - 1) / size;
+    let mut chunks = Vec::with_capacity(n);
+    let mut rest = items;
+    for i in 0..n {
+        let (chunk, tail) = rest.split_at(rest.len() / (n - i));
+        chunks.push(chunk);
+        rest = tail;
+    }
+    chunks
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, DagCbor)]
+struct Child {
+    len: u64,
+    link: Cid,
+}
+
+impl Child {
+    fn new(len: u64, link: Cid) -> Self {
+        Self { len, link }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, DagCbor)]
+enum Node {
+    Leaf(Box<[u8]>),
+    Branch(Vec<Child>),
+}
+
+impl Links for Node {
+    fn links(&self) -> Vec<Cid> {
+        match self {
+            Node::Leaf(_) => vec![],
+            Node::Branch(children) => children.iter().map(|child| child.link).collect(),
+        }
+    }
+}
+
+impl Node {
+    fn len(&self) -> u64 {
+        match self {
+            Node::Leaf(bytes) => bytes.len() as u64,
+            Node::Branch(children) => children.iter().map(|child| child.len).sum(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::{Hamt, HamtConfig};
+    use async_std::task;
+    use libipld::mem::MemStore;
+    use libipld::multihash::Code;
+    use libipld::store::DefaultParams;
+    use proptest::prelude::*;
+
+    fn config(store: &MemStore<DefaultParams>) -> BytesConfig<MemStore<DefaultParams>> {
+        let mut config = BytesConfig::new(store.clone(), Code::Blake2b256);
+        config.set_chunk_size(4);
+        config.set_width(3);
+        config
+    }
+
+    // returns the depth and the number of the leaves, checking that all
+    // leaves are at the same depth and that all nodes but the root are at
+    // least half full
+    async fn shape(bytes: &Bytes<MemStore<DefaultParams>>, cid: &Cid) -> Result<(usize, usize)> {
+        let mut depths = vec![];
+        let mut stack = vec![(*cid, 0)];
+        while let Some((cid, depth)) = stack.pop() {
+            match bytes.cache.get(&cid, None).await? {
+                Node::Leaf(leaf) => {
+                    assert!(leaf.len() <= 4);
+                    assert!(depth == 0 || leaf.len() >= 2);
+                    depths.push(depth);
+                }
+                Node::Branch(children) => {
+                    assert!(!children.is_empty() && children.len() <= 3);
+                    assert!(depth == 0 || children.len() >= 2);
+                    stack.extend(children.iter().map(|child| (child.link, depth + 1)));
+                }
+            }
+        }
+        assert!(depths.iter().all(|d| *d == depths[0]));
+        Ok((depths[0], depths.len()))
+    }
+
+    #[async_std::test]
+    async fn test_bytes() -> Result<()> {
+        let store = MemStore::<DefaultParams>::default();
+        let data: Vec<u8> = (0..100).collect();
+        let mut bytes = Bytes::from(config(&store), &data).await?;
+        assert_eq!(bytes.len().await?, 100);
+        assert_eq!(shape(&bytes, bytes.root()).await?, (3, 25));
+        assert_eq!(bytes.read_at(0, 100).await?, data);
+        assert_eq!(bytes.read_at(10, 7).await?, &data[10..17]);
+        assert_eq!(bytes.read_at(95, 10).await?, &data[95..]);
+        assert_eq!(bytes.read_at(200, 10).await?, Vec::<u8>::new());
+
+        bytes.splice(10, 80, b"hello").await?;
+        let mut expected = data[..10].to_vec();
+        expected.extend_from_slice(b"hello");
+        expected.extend_from_slice(&data[90..]);
+        assert_eq!(bytes.read_at(0, 100).await?, expected);
+        assert_eq!(shape(&bytes, bytes.root()).await?, (2, 7));
+
+        // small splices merge the leaves they leave behind
+        for i in 0..20 {
+            bytes.splice(i, 1, &[]).await?;
+            bytes.splice(i, 0, b"y").await?;
+        }
+        assert_eq!(bytes.len().await?, 25);
+        let (_, leaves) = shape(&bytes, bytes.root()).await?;
+        assert!(leaves <= 12);
+        bytes.splice(0, 20, &[]).await?;
+        assert_eq!(bytes.read_at(0, 100).await?, &expected[20..]);
+        assert_eq!(shape(&bytes, bytes.root()).await?, (1, 2));
+
+        // the data is 25 bytes long now
+        assert!(bytes.splice(26, 0, b"x").await.is_err());
+        assert_eq!(bytes.read_at(0, 100).await?, expected);
+        bytes.splice(0, 100, &[]).await?;
+        assert!(bytes.is_empty().await?);
+        assert_eq!(bytes.read_at(10, 10).await?, Vec::<u8>::new());
+        let empty = Bytes::new(config(&store)).await?;
+        assert_eq!(bytes.root(), empty.root());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_bytes_dag_json() -> Result<()> {
+        use libipld::json::DagJsonCodec;
+
+        // the default chunk size leaves room for the base64 encoding
+        let store = MemStore::<DefaultParams>::default();
+        let data: Vec<u8> = (0..DefaultParams::MAX_BLOCK_SIZE * 3 / 2)
+            .map(|i| i as u8)
+            .collect();
+        let config = BytesConfig::with_codec(store.clone(), DagJsonCodec, Code::Blake2b256);
+        let bytes = Bytes::from(config, &data).await?;
+        assert_eq!(bytes.root().codec(), u64::from(DagJsonCodec));
+        let config = BytesConfig::with_codec(store, DagJsonCodec, Code::Blake2b256);
+        let bytes = Bytes::open(config, *bytes.root()).await?;
+        assert_eq!(bytes.len().await?, data.len() as u64);
+        assert_eq!(bytes.read_at(0, data.len() as u64).await?, data);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_bytes_by_reference() -> Result<()> {
+        let store = MemStore::<DefaultParams>::default();
+        let data: Vec<u8> = (0..100).collect();
+        let bytes = Bytes::from(config(&store), &data).await?;
+        let mut hamt = Hamt::new(HamtConfig::new(store.clone(), Code::Blake2b256)).await?;
+        hamt.insert(b"blob".to_vec().into(), *bytes.root()).await?;
+        drop(bytes);
+
+        let root = hamt.get(b"blob").await?.unwrap();
+        let bytes = Bytes::open(config(&store), root).await?;
+        assert_eq!(bytes.read_at(0, 100).await?, data);
+        Ok(())
+    }
+
+    #[derive(Debug, Clone)]
+    struct Splice {
+        offset: usize,
+        delete: usize,
+        insert: Vec<u8>,
+    }
+
+    fn splice() -> impl Strategy<Value = Splice> {
+        (
+            0..200usize,
+            0..40usize,
+            prop::collection::vec(any::<u8>(), 0..40),
+        )
+            .prop_map(|(offset, delete, insert)| Splice {
+                offset,
+                delete,
+                insert,
+            })
+    }
+
+    async fn model(splices: Vec<Splice>) -> Result<()> {
+        let store = MemStore::<DefaultParams>::default();
+        let mut bytes = Bytes::new(config(&store)).await?;
+        let mut vec = vec![];
+        for Splice {
+            offset,
+            delete,
+            insert,
+        } in splices
+        {
+            let offset = offset % (vec.len() + 1);
+            let delete = min(delete, vec.len() - offset);
+            bytes.splice(offset as u64, delete as u64, &insert).await?;
+            vec.splice(offset..offset + delete, insert);
+            assert_eq!(bytes.len().await?, vec.len() as u64);
+            // leaves are at least half full and branches have at least two
+            // children, apart from the root
+            let (depth, leaves) = shape(&bytes, bytes.root()).await?;
+            assert!(leaves <= max(1, vec.len() / 2));
+            assert!(1 << depth <= leaves);
+        }
+        assert_eq!(bytes.read_at(0, vec.len() as u64).await?, vec);
+        for start in (0..vec.len()).step_by(7) {
+            let end = min(start + 13, vec.len());
+            assert_eq!(bytes.read_at(start as u64, 13).await?, &vec[start..end]);
+        }
+        Ok(())
+    }
+
+    proptest! {
+        #[test]
+        fn test_bytes_model(splices in prop::collection::vec(splice(), 0..50)) {
+            task::block_on(model(splices)).unwrap();
+        }
+    }
+}
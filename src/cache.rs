@@ -0,0 +1,486 @@
+use crate::codec::{encode, CollectionStore, NodeCodec};
+use cached::{Cached, SizedCache};
+use futures::future::{join_all, BoxFuture, FutureExt};
+use futures::stream::{self, FuturesUnordered, StreamExt};
+use libipld::block::Block;
+use libipld::cbor::DagCbor;
+use libipld::cid::Cid;
+use libipld::error::Result;
+use libipld::store::{Store, StoreParams};
+use std::collections::{HashMap, HashSet};
+use std::ops::Deref;
+use std::sync::Mutex;
+
+// like libipld's IpldCache, evicting the least recently used nodes, but it
+// encodes the nodes with any NodeCodec instead of requiring them to implement
+// the codec
+pub(crate) struct NodeCache<S: Store, C, N> {
+    store: S,
+    codec: C,
+    hash: <S::Params as StoreParams>::Hashes,
+    nodes: Mutex<SizedCache<Cid, N>>,
+}
+
+impl<S: Store, C, N> Deref for NodeCache<S, C, N> {
+    type Target = S;
+
+    fn deref(&self) -> &Self::Target {
+        &self.store
+    }
+}
+
+impl<S, C, N> NodeCache<S, C, N>
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+    N: DagCbor + Clone + Send + Sync,
+{
+    pub fn new(store: S, codec: C, hash: <S::Params as StoreParams>::Hashes, size: usize) -> Self {
+        Self {
+            store,
+            codec,
+            hash,
+            nodes: Mutex::new(SizedCache::with_size(size.max(1))),
+        }
+    }
+
+    pub fn codec(&self) -> C {
+        self.codec
+    }
+
+    pub fn encode<V: DagCbor>(&self, value: &V) -> Result<Block<S::Params>> {
+        encode(self.codec, self.hash, value)
+    }
+
+    pub fn decode<V: DagCbor>(&self, block: &Block<S::Params>) -> Result<V> {
+        self.codec.decode_node(block.data())
+    }
+
+    // reads a value that isn't a node, like one spilled out of a node
+    pub async fn load<V: DagCbor>(&self, cid: &Cid) -> Result<V> {
+        let block = self.store.get(cid).await?;
+        self.decode(&block)
+    }
+
+    // gets the blocks concurrently, so a store backed by the network doesn't
+    // wait for each of them in turn. they're kept under tmp
+    pub async fn fetch(&self, cids: &[Cid], tmp: &S::TempPin) -> Result<()> {
+        for block in get_blocks(&self.store, cids).await {
+            self.store.insert(&block?, Some(tmp)).await?;
+        }
+        Ok(())
+    }
+
+    // gets the blocks with up to concurrency of them in flight. the next one
+    // is requested as soon as any of them arrives
+    pub async fn get_many(
+        &self,
+        cids: &[Cid],
+        concurrency: usize,
+    ) -> Vec<Result<Block<S::Params>>> {
+        stream::iter(cids)
+            .map(|cid| self.store.get(cid))
+            .buffered(concurrency.max(1))
+            .collect()
+            .await
+    }
+
+    // like load, but gets up to concurrency of the values at once
+    pub async fn load_many<V: DagCbor>(&self, cids: &[Cid], concurrency: usize) -> Vec<Result<V>> {
+        self.get_many(cids, concurrency)
+            .await
+            .into_iter()
+            .map(|block| block.and_then(|block| self.decode(&block)))
+            .collect()
+    }
+
+    fn cache(&self, cid: Cid, node: N) {
+        self.nodes.lock().unwrap().cache_set(cid, node);
+    }
+
+    fn cached(&self, cid: &Cid) -> Option<N> {
+        self.nodes.lock().unwrap().cache_get(cid).cloned()
+    }
+
+    // like Traversal::next, but it decodes the nodes, and the ones in the
+    // cache aren't fetched from the store
+    pub async fn next<X>(
+        &self,
+        nodes: &mut Traversal<S::Params, X>,
+    ) -> Option<(Cid, X, Result<N>)> {
+        let (cid, _) = nodes.stack.last()?;
+        if let Some(node) = self.cached(cid) {
+            let (cid, state) = nodes.stack.pop()?;
+            nodes.ready.remove(&cid);
+            return Some((cid, state, Ok(node)));
+        }
+        let is_cached = |cid: &Cid| self.nodes.lock().unwrap().cache_get(cid).is_some();
+        let (cid, state, block) = nodes.next_with(&self.store, is_cached).await?;
+        let node = block.and_then(|block| self.decode::<N>(&block));
+        if let Ok(node) = &node {
+            self.cache(cid, node.clone());
+        }
+        Some((cid, state, node))
+    }
+
+    pub async fn get(&self, cid: &Cid, tmp: Option<&S::TempPin>) -> Result<N> {
+        if let Some(node) = self.cached(cid) {
+            return Ok(node);
+        }
+        let block = self.store.get(cid).await?;
+        if let Some(tmp) = tmp {
+            self.store.insert(&block, Some(tmp)).await?;
+        }
+        let node: N = self.decode(&block)?;
+        self.cache(*cid, node.clone());
+        Ok(node)
+    }
+
+    pub async fn insert(&self, node: N, tmp: Option<&S::TempPin>) -> Result<Cid> {
+        let block = self.encode(&node)?;
+        self.store.insert(&block, tmp).await?;
+        self.cache(*block.cid(), node);
+        Ok(*block.cid())
+    }
+}
+
+pub(crate) async fn get_blocks<S: Store>(store: &S, cids: &[Cid]) -> Vec<Result<Block<S::Params>>> {
+    join_all(cids.iter().map(|cid| store.get(cid))).await
+}
+
+type Fetch<P> = BoxFuture<'static, (Cid, Result<Block<P>>)>;
+
+// walks a tree of blocks depth first, with some state for each of them. the
+// blocks on top of the stack, the ones visited next, are fetched with up to
+// concurrency of them in flight, and they're still visited in order. whenever
+// a block arrives the next one on the stack is requested, so a slow block
+// doesn't hold up the others
+pub(crate) struct Traversal<P: StoreParams, X> {
+    concurrency: usize,
+    stack: Vec<(Cid, X)>,
+    // the blocks that arrived before they're visited
+    ready: HashMap<Cid, Result<Block<P>>>,
+    // only ever used through &mut, the mutex keeps the traversal Sync
+    fetches: Mutex<FuturesUnordered<Fetch<P>>>,
+    fetching: HashSet<Cid>,
+}
+
+impl<P: StoreParams, X> Traversal<P, X> {
+    pub fn new(concurrency: usize, root: Cid, state: X) -> Self {
+        Self {
+            concurrency: concurrency.max(1),
+            stack: vec![(root, state)],
+            ready: HashMap::new(),
+            fetches: Mutex::new(FuturesUnordered::new()),
+            fetching: HashSet::new(),
+        }
+    }
+
+    // children have to be pushed in reverse to be visited in order
+    pub fn push(&mut self, cid: Cid, state: X) {
+        self.stack.push((cid, state));
+    }
+
+    // the block next will return, without fetching it
+    pub fn peek(&self) -> Option<(&Cid, &X)> {
+        self.stack.last().map(|(cid, state)| (cid, state))
+    }
+
+    // drops the block next would return
+    pub fn skip(&mut self) {
+        if let Some((cid, _)) = self.stack.pop() {
+            self.ready.remove(&cid);
+        }
+    }
+
+    pub async fn next<S>(&mut self, store: &S) -> Option<(Cid, X, Result<Block<P>>)>
+    where
+        S: Store<Params = P> + 'static,
+    {
+        self.next_with(store, |_| false).await
+    }
+
+    // the blocks skip returns true for aren't fetched ahead of the next one,
+    // they're taken from somewhere else when they're visited
+    async fn next_with<S, F>(&mut self, store: &S, skip: F) -> Option<(Cid, X, Result<Block<P>>)>
+    where
+        S: Store<Params = P> + 'static,
+        F: Fn(&Cid) -> bool,
+    {
+        let (next, _) = self.stack.last()?;
+        let next = *next;
+        while !self.ready.contains_key(&next) {
+            // the block visited next is requested even if that's one more
+            // than concurrency, the others fill up the free slots
+            let mut cids = vec![];
+            if !self.fetching.contains(&next) {
+                cids.push(next);
+            }
+            for (cid, _) in self.stack.iter().rev().skip(1) {
+                if self.fetching.len() + cids.len() >= self.concurrency {
+                    break;
+                }
+                if !self.ready.contains_key(cid)
+                    && !self.fetching.contains(cid)
+                    && !cids.contains(cid)
+                    && !skip(cid)
+                {
+                    cids.push(*cid);
+                }
+            }
+            let fetches = self.fetches.get_mut().unwrap();
+            for cid in cids {
+                let store = store.clone();
+                self.fetching.insert(cid);
+                fetches.push(Box::pin(async move {
+                    let block = store.get(&cid).await;
+                    (cid, block)
+                }));
+            }
+            let fetched = fetches.next().await.expect("the next block is fetched");
+            let mut arrived = vec![fetched];
+            // the fetches that weren't polled yet are started while taking
+            // the blocks that are already there
+            while let Some(Some(fetched)) = fetches.next().now_or_never() {
+                arrived.push(fetched);
+            }
+            for (cid, block) in arrived {
+                self.fetching.remove(&cid);
+                // blocks that were skipped while they were in flight are dropped
+                if self.stack.iter().any(|(other, _)| *other == cid) {
+                    self.ready.insert(cid, block);
+                }
+            }
+        }
+        let (cid, state) = self.stack.pop()?;
+        let block = self.ready.remove(&cid).expect("fetched above");
+        Some((cid, state, block))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use libipld::cbor::DagCborCodec;
+    use libipld::codec::Codec;
+    use libipld::mem::MemStore;
+    use libipld::multihash::Code;
+    use libipld::store::DefaultParams;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    // a store that gets the blocks it doesn't have from a remote store,
+    // counting how often it had to
+    #[derive(Clone, Default)]
+    pub(crate) struct RemoteStore {
+        local: MemStore<DefaultParams>,
+        pub remote: MemStore<DefaultParams>,
+        fetched: Arc<AtomicUsize>,
+        forgetful: Arc<AtomicBool>,
+        delays: Arc<Mutex<HashMap<Cid, Duration>>>,
+    }
+
+    impl RemoteStore {
+        pub fn new(remote: MemStore<DefaultParams>) -> Self {
+            Self {
+                remote,
+                ..Default::default()
+            }
+        }
+
+        pub fn fetched(&self) -> usize {
+            self.fetched.swap(0, Ordering::SeqCst)
+        }
+
+        // drops the blocks inserted from then on
+        pub fn forget(&self) {
+            self.forgetful.store(true, Ordering::SeqCst);
+        }
+
+        // makes fetching the block take that much longer
+        pub fn delay(&self, cid: Cid, delay: Duration) {
+            self.delays.lock().unwrap().insert(cid, delay);
+        }
+    }
+
+    #[async_trait]
+    impl Store for RemoteStore {
+        type Params = DefaultParams;
+        type TempPin = <MemStore<DefaultParams> as Store>::TempPin;
+
+        async fn temp_pin(&self) -> Result<Self::TempPin> {
+            self.local.temp_pin().await
+        }
+
+        async fn contains(&self, cid: &Cid) -> Result<bool> {
+            self.local.contains(cid).await
+        }
+
+        async fn get(&self, cid: &Cid) -> Result<Block<DefaultParams>> {
+            if self.local.contains(cid).await? {
+                return self.local.get(cid).await;
+            }
+            self.fetched.fetch_add(1, Ordering::SeqCst);
+            let delay = self.delays.lock().unwrap().get(cid).copied();
+            if let Some(delay) = delay {
+                async_std::task::sleep(delay).await;
+            }
+            self.remote.get(cid).await
+        }
+
+        async fn insert(
+            &self,
+            block: &Block<DefaultParams>,
+            tmp: Option<&Self::TempPin>,
+        ) -> Result<()> {
+            if self.forgetful.load(Ordering::SeqCst) {
+                return Ok(());
+            }
+            self.local.insert(block, tmp).await
+        }
+
+        async fn alias<T: AsRef<[u8]> + Send + Sync>(
+            &self,
+            alias: T,
+            cid: Option<&Cid>,
+        ) -> Result<()> {
+            self.local.alias(alias, cid).await
+        }
+
+        async fn resolve<T: AsRef<[u8]> + Send + Sync>(&self, alias: T) -> Result<Option<Cid>> {
+            self.local.resolve(alias).await
+        }
+
+        async fn reverse_alias(&self, cid: &Cid) -> Result<Option<Vec<Vec<u8>>>> {
+            self.local.reverse_alias(cid).await
+        }
+
+        async fn flush(&self) -> Result<()> {
+            self.local.flush().await
+        }
+    }
+
+    #[async_std::test]
+    async fn test_fetch() -> Result<()> {
+        let remote = MemStore::<DefaultParams>::default();
+        let mut cids = vec![];
+        for i in 0..10u64 {
+            let block = Block::encode(DagCborCodec, Code::Blake2b256, &i)?;
+            remote.insert(&block, None).await?;
+            cids.push(*block.cid());
+        }
+        let store = RemoteStore::new(remote);
+        let cache = NodeCache::<_, _, u64>::new(store.clone(), DagCborCodec, Code::Blake2b256, 4);
+        let tmp = store.temp_pin().await?;
+        cache.fetch(&cids, &tmp).await?;
+        assert_eq!(store.fetched(), 10);
+        for cid in cids.iter() {
+            assert!(store.contains(cid).await?);
+        }
+        assert_eq!(cache.get(&cids[3], None).await?, 3);
+        assert_eq!(store.fetched(), 0);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_cache_lru() -> Result<()> {
+        let remote = MemStore::<DefaultParams>::default();
+        let mut cids = vec![];
+        for i in 0..3u64 {
+            let block = Block::encode(DagCborCodec, Code::Blake2b256, &i)?;
+            remote.insert(&block, None).await?;
+            cids.push(*block.cid());
+        }
+        let store = RemoteStore::new(remote);
+        let cache = NodeCache::<_, _, u64>::new(store.clone(), DagCborCodec, Code::Blake2b256, 2);
+        cache.get(&cids[0], None).await?;
+        cache.get(&cids[1], None).await?;
+        // the hit makes the first node the most recently used one
+        cache.get(&cids[0], None).await?;
+        cache.get(&cids[2], None).await?;
+        assert_eq!(store.fetched(), 3);
+        cache.get(&cids[0], None).await?;
+        assert_eq!(store.fetched(), 0);
+        cache.get(&cids[1], None).await?;
+        assert_eq!(store.fetched(), 1);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_traversal() -> Result<()> {
+        let remote = MemStore::<DefaultParams>::default();
+        let block = |links: Vec<Cid>| Block::encode(DagCborCodec, Code::Blake2b256, &links);
+        let mut leaves = vec![];
+        for _ in 0..4 {
+            let leaf = block(leaves.clone())?;
+            remote.insert(&leaf, None).await?;
+            leaves.push(*leaf.cid());
+        }
+        let inner = block(vec![leaves[2], leaves[3]])?;
+        remote.insert(&inner, None).await?;
+        let root = block(vec![leaves[0], *inner.cid(), leaves[1]])?;
+        remote.insert(&root, None).await?;
+        let order = vec![
+            *root.cid(),
+            leaves[0],
+            *inner.cid(),
+            leaves[2],
+            leaves[3],
+            leaves[1],
+        ];
+
+        for concurrency in 1..5 {
+            let store = RemoteStore::new(remote.clone());
+            let mut nodes = Traversal::new(concurrency, *root.cid(), ());
+            let mut visited = vec![];
+            while let Some((cid, (), block)) = nodes.next(&store).await {
+                if cid == order[1] {
+                    // the siblings after the first leaf were fetched with it
+                    assert_eq!(store.fetched(), 1 + concurrency.min(3));
+                }
+                let links: Vec<Cid> = DagCborCodec.decode(block?.data())?;
+                if cid == *root.cid() || cid == *inner.cid() {
+                    for link in links.into_iter().rev() {
+                        nodes.push(link, ());
+                    }
+                }
+                visited.push(cid);
+            }
+            assert_eq!(visited, order);
+        }
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_traversal_slow_block() -> Result<()> {
+        let remote = MemStore::<DefaultParams>::default();
+        let mut leaves = vec![];
+        for i in 0..5u64 {
+            let leaf = Block::encode(DagCborCodec, Code::Blake2b256, &vec![i])?;
+            remote.insert(&leaf, None).await?;
+            leaves.push(*leaf.cid());
+        }
+        let delay = Duration::from_millis(500);
+        let store = RemoteStore::new(remote);
+        store.delay(leaves[1], delay);
+
+        let start = Instant::now();
+        let mut nodes = Traversal::new(2, leaves[4], ());
+        for leaf in leaves[..4].iter().rev() {
+            nodes.push(*leaf, ());
+        }
+        let (cid, (), _) = nodes.next(&store).await.unwrap();
+        assert_eq!(cid, leaves[0]);
+        // the first leaf doesn't wait for the slow one fetched along with it
+        assert!(start.elapsed() < delay / 2);
+        let (cid, (), _) = nodes.next(&store).await.unwrap();
+        assert_eq!(cid, leaves[1]);
+        // the others were fetched one after the other while it was in flight
+        assert_eq!(store.fetched(), 5);
+        while nodes.next(&store).await.is_some() {}
+        assert_eq!(store.fetched(), 0);
+        Ok(())
+    }
+}
@@ -0,0 +1,191 @@
+use crate::cache::NodeCache;
+use crate::codec::{CollectionStore, NodeCodec};
+use crate::visit::{self, Item, Walker};
+use libipld::block::Block;
+use libipld::cbor::{DagCbor, DagCborCodec};
+use libipld::codec::Codec;
+use libipld::prelude::{Store, StoreParams};
+use libipld::DagCbor;
+use libipld::{Cid, Result};
+use std::collections::HashSet;
+use std::io::{Error, ErrorKind, Read, Write};
+
+// the header of a CARv1 file, it's always encoded as DagCbor
+#[derive(Debug, DagCbor)]
+struct Header {
+    roots: Vec<Cid>,
+    version: u64,
+}
+
+fn write_varint<W: Write>(writer: &mut W, mut n: u64) -> Result<()> {
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            writer.write_all(&[byte])?;
+            return Ok(());
+        }
+        writer.write_all(&[byte | 0x80])?;
+    }
+}
+
+// returns None if the reader ended before the first byte
+fn read_varint<R: Read>(reader: &mut R) -> Result<Option<u64>> {
+    let mut n = 0;
+    for i in 0..10 {
+        let mut byte = [0];
+        if reader.read(&mut byte)? == 0 {
+            if i == 0 {
+                return Ok(None);
+            }
+            return Err(Error::new(ErrorKind::UnexpectedEof, "truncated varint").into());
+        }
+        n |= u64::from(byte[0] & 0x7f) << (i * 7);
+        if byte[0] & 0x80 == 0 {
+            return Ok(Some(n));
+        }
+    }
+    Err(Error::new(ErrorKind::InvalidData, "varint too long").into())
+}
+
+pub(crate) fn write_section<W: Write>(writer: &mut W, bytes: &[u8]) -> Result<()> {
+    write_varint(writer, bytes.len() as u64)?;
+    writer.write_all(bytes)?;
+    Ok(())
+}
+
+pub(crate) fn read_section<R: Read>(reader: &mut R) -> Result<Option<Vec<u8>>> {
+    let len = match read_varint(reader)? {
+        Some(len) => len,
+        None => return Ok(None),
+    };
+    let mut bytes = vec![];
+    reader.take(len).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != len {
+        return Err(Error::new(ErrorKind::UnexpectedEof, "truncated section").into());
+    }
+    Ok(Some(bytes))
+}
+
+struct Exporter<'a, W> {
+    writer: &'a mut W,
+    written: HashSet<Cid>,
+}
+
+impl<'a, N, T, P, W> Walker<N, T, P> for Exporter<'a, W>
+where
+    P: StoreParams,
+    W: Write,
+{
+    fn prefetch(&self) -> bool {
+        true
+    }
+
+    // subtrees that occur more than once are only written once
+    fn enter(&mut self, cid: &Cid, _depth: usize) -> bool {
+        !self.written.contains(cid)
+    }
+
+    fn node(&mut self, block: &Block<P>, _node: &N, _depth: usize) -> Result<bool> {
+        self.written.insert(*block.cid());
+        write_block(self.writer, block)?;
+        Ok(true)
+    }
+
+    fn spilled(&mut self, _key: Option<&[u8]>, block: &Block<P>) -> Result<()> {
+        if self.written.insert(*block.cid()) {
+            write_block(self.writer, block)?;
+        }
+        Ok(())
+    }
+}
+
+// writes the nodes reachable from root and the values spilled out of them,
+// fetching up to concurrency blocks at once
+pub(crate) async fn export<S, C, N, T, I, W>(
+    cache: &NodeCache<S, C, N>,
+    root: &Cid,
+    concurrency: usize,
+    items: I,
+    writer: &mut W,
+) -> Result<()>
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+    N: DagCbor + Clone + Send + Sync,
+    I: FnMut(N) -> Vec<Item<T>>,
+    W: Write,
+{
+    let header = Header {
+        roots: vec![*root],
+        version: 1,
+    };
+    write_section(writer, &DagCborCodec.encode(&header)?)?;
+    let mut exporter = Exporter {
+        writer,
+        written: HashSet::new(),
+    };
+    visit::traverse(cache, root, concurrency, &mut exporter, items).await
+}
+
+fn write_block<P: StoreParams, W: Write>(writer: &mut W, block: &Block<P>) -> Result<()> {
+    let mut bytes = block.cid().to_bytes();
+    bytes.extend_from_slice(block.data());
+    write_section(writer, &bytes)
+}
+
+// inserts every block of a CARv1 file into the store and returns its root,
+// whatever collection it holds. the blocks are checked against their cids
+pub async fn read_car<S: Store, R: Read>(store: &S, mut reader: R) -> Result<Cid> {
+    let (root, blocks) = read::<S::Params, _>(&mut reader)?;
+    insert(store, None, &blocks).await?;
+    Ok(root)
+}
+
+// reads the root and the blocks of a CARv1 file without inserting them, so
+// the root can be checked first. the blocks are checked against their cids
+pub(crate) fn read<P, R>(reader: &mut R) -> Result<(Cid, Vec<Block<P>>)>
+where
+    P: StoreParams,
+    R: Read,
+{
+    let header = match read_section(reader)? {
+        Some(bytes) => DagCborCodec.decode::<Header>(&bytes)?,
+        None => return Err(Error::new(ErrorKind::UnexpectedEof, "missing car header").into()),
+    };
+    if header.version != 1 || header.roots.len() != 1 {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "expected a CARv1 file with one root",
+        )
+        .into());
+    }
+    let mut blocks = vec![];
+    while let Some(bytes) = read_section(reader)? {
+        let mut data = &bytes[..];
+        let cid = Cid::read_bytes(&mut data)?;
+        blocks.push(Block::<P>::new(cid, data.to_vec())?);
+    }
+    Ok((header.roots[0], blocks))
+}
+
+pub(crate) fn root_block<'a, P: StoreParams>(
+    root: &Cid,
+    blocks: &'a [Block<P>],
+) -> Result<&'a Block<P>> {
+    match blocks.iter().find(|block| block.cid() == root) {
+        Some(block) => Ok(block),
+        None => Err(Error::new(ErrorKind::InvalidData, "the car doesn't contain its root").into()),
+    }
+}
+
+pub(crate) async fn insert<S: Store>(
+    store: &S,
+    tmp: Option<&S::TempPin>,
+    blocks: &[Block<S::Params>],
+) -> Result<()> {
+    for block in blocks {
+        store.insert(block, tmp).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,172 @@
+use libipld::block::Block;
+use libipld::cbor::{DagCbor, DagCborCodec};
+use libipld::cid::Cid;
+use libipld::codec::Codec;
+use libipld::error::{BlockTooLarge, Result, UnsupportedCodec};
+use libipld::ipld::Ipld;
+use libipld::json::DagJsonCodec;
+use libipld::multihash::MultihashDigest;
+use libipld::store::{Store, StoreParams};
+use std::collections::BTreeMap;
+
+// codecs the collections can be stored with. the nodes are derived for
+// DagCbor, so that codec encodes them directly and any other one converts them
+// through Ipld
+pub trait NodeCodec: Codec {
+    fn encode_node<N: DagCbor>(&self, node: &N) -> Result<Vec<u8>>;
+    fn decode_node<N: DagCbor>(&self, bytes: &[u8]) -> Result<N>;
+    // how many bytes fit into a node of at most block_size once encoded,
+    // leaving some room for the encoding around them
+    fn max_bytes(&self, block_size: usize) -> usize;
+}
+
+impl NodeCodec for DagCborCodec {
+    fn encode_node<N: DagCbor>(&self, node: &N) -> Result<Vec<u8>> {
+        self.encode(node)
+    }
+
+    fn decode_node<N: DagCbor>(&self, bytes: &[u8]) -> Result<N> {
+        self.decode(bytes)
+    }
+
+    fn max_bytes(&self, block_size: usize) -> usize {
+        block_size.saturating_sub(64)
+    }
+}
+
+impl NodeCodec for DagJsonCodec {
+    fn encode_node<N: DagCbor>(&self, node: &N) -> Result<Vec<u8>> {
+        let ipld: Ipld = DagCborCodec.decode(&DagCborCodec.encode(node)?)?;
+        self.encode(&bytes_to_json(ipld))
+    }
+
+    fn decode_node<N: DagCbor>(&self, bytes: &[u8]) -> Result<N> {
+        let ipld = bytes_from_json(self.decode(bytes)?)?;
+        DagCborCodec.decode(&DagCborCodec.encode(&ipld)?)
+    }
+
+    // base64 takes 4 characters for every 3 bytes
+    fn max_bytes(&self, block_size: usize) -> usize {
+        block_size.saturating_sub(64) / 4 * 3
+    }
+}
+
+// the json codec writes bytes as a list of numbers, which reads back as a
+// list. they're written as {"/": {"bytes": base64}} instead, like the dag-json
+// spec has it, in standard base64 without padding
+fn bytes_to_json(ipld: Ipld) -> Ipld {
+    match ipld {
+        Ipld::Bytes(bytes) => {
+            let mut inner = BTreeMap::new();
+            inner.insert(
+                "bytes".to_string(),
+                Ipld::String(base64::encode_config(&bytes, base64::STANDARD_NO_PAD)),
+            );
+            let mut outer = BTreeMap::new();
+            outer.insert("/".to_string(), Ipld::StringMap(inner));
+            Ipld::StringMap(outer)
+        }
+        Ipld::List(list) => Ipld::List(list.into_iter().map(bytes_to_json).collect()),
+        Ipld::StringMap(map) => Ipld::StringMap(
+            map.into_iter()
+                .map(|(key, value)| (key, bytes_to_json(value)))
+                .collect(),
+        ),
+        ipld => ipld,
+    }
+}
+
+fn bytes_from_json(ipld: Ipld) -> Result<Ipld> {
+    Ok(match ipld {
+        Ipld::StringMap(map) => {
+            if let Some(Ipld::StringMap(inner)) = map.get("/").filter(|_| map.len() == 1) {
+                if let Some(Ipld::String(bytes)) = inner.get("bytes").filter(|_| inner.len() == 1) {
+                    // padding is accepted but not required. the no pad
+                    // config rejects it, so it's stripped first
+                    let bytes = bytes.trim_end_matches('=');
+                    let bytes = base64::decode_config(bytes, base64::STANDARD_NO_PAD)?;
+                    return Ok(Ipld::Bytes(bytes));
+                }
+            }
+            let mut decoded = BTreeMap::new();
+            for (key, value) in map {
+                decoded.insert(key, bytes_from_json(value)?);
+            }
+            Ipld::StringMap(decoded)
+        }
+        Ipld::List(list) => Ipld::List(
+            list.into_iter()
+                .map(bytes_from_json)
+                .collect::<Result<_>>()?,
+        ),
+        ipld => ipld,
+    })
+}
+
+// decodes a node stored with any of the codecs the crate knows about, for
+// when it isn't known which codec the collection uses
+pub(crate) fn decode_by_cid<N: DagCbor>(cid: &Cid, bytes: &[u8]) -> Result<N> {
+    match cid.codec() {
+        code if code == u64::from(DagCborCodec) => DagCborCodec.decode_node(bytes),
+        code if code == u64::from(DagJsonCodec) => DagJsonCodec.decode_node(bytes),
+        code => Err(UnsupportedCodec(code).into()),
+    }
+}
+
+// shorthand for the bounds the collections put on their store, it has to
+// support the codec C they're encoded with. rust doesn't imply where clauses
+// on associated types, but it does imply the bounds of associated types. the
+// store is cloned into the fetches that outlive a single call, so it has to
+// be 'static
+pub trait CollectionStore<C>: Store<Params = <Self as CollectionStore<C>>::P> + 'static {
+    type P: StoreParams<Codecs = <Self as CollectionStore<C>>::Codecs>;
+    type Codecs: Codec + Into<C> + From<C>;
+}
+
+impl<S, C> CollectionStore<C> for S
+where
+    S: Store + 'static,
+    <S::Params as StoreParams>::Codecs: Into<C> + From<C>,
+{
+    type P = S::Params;
+    type Codecs = <S::Params as StoreParams>::Codecs;
+}
+
+pub(crate) fn encode<P, C, N>(codec: C, hash: P::Hashes, node: &N) -> Result<Block<P>>
+where
+    P: StoreParams,
+    C: NodeCodec,
+    N: DagCbor,
+{
+    let data = codec.encode_node(node)?;
+    if data.len() > P::MAX_BLOCK_SIZE {
+        return Err(BlockTooLarge(data.len()).into());
+    }
+    let cid = Cid::new_v1(codec.into(), hash.digest(&data));
+    Ok(Block::new_unchecked(cid, data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dag_json_bytes() -> Result<()> {
+        // five bytes don't fill the last base64 group
+        let ipld = Ipld::List(vec![Ipld::Bytes(vec![1, 2, 3, 4, 5])]);
+        let json = DagJsonCodec.encode_node(&ipld)?;
+        let text = String::from_utf8(json.clone())?;
+        assert!(text.contains(r#""AQIDBAU""#));
+        assert!(!text.contains('='));
+        assert_eq!(DagJsonCodec.decode_node::<Ipld>(&json)?, ipld);
+        // padded input decodes to the same bytes
+        let padded = br#"[{"/":{"bytes":"AQIDBAU="}}]"#;
+        assert_eq!(DagJsonCodec.decode_node::<Ipld>(padded)?, ipld);
+        let ipld = Ipld::List(vec![Ipld::Bytes(vec![1, 2, 3, 4])]);
+        let padded = br#"[{"/":{"bytes":"AQIDBA=="}}]"#;
+        assert_eq!(DagJsonCodec.decode_node::<Ipld>(padded)?, ipld);
+        let unpadded = br#"[{"/":{"bytes":"AQIDBA"}}]"#;
+        assert_eq!(DagJsonCodec.decode_node::<Ipld>(unpadded)?, ipld);
+        Ok(())
+    }
+}
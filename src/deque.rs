@@ -0,0 +1,771 @@
+use crate::cache::{NodeCache, Traversal};
+use crate::codec::{CollectionStore, NodeCodec};
+use crate::list::{spill, Data};
+use crate::tx::{Buffer, Links, Spill};
+use libipld::block::Block;
+use libipld::cbor::{DagCbor, DagCborCodec};
+use libipld::prelude::{Store, StoreParams};
+use libipld::DagCbor;
+use libipld::{Cid, Result};
+use std::sync::Arc;
+
+pub struct DequeConfig<S, C = DagCborCodec>
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+{
+    store: S,
+    codec: C,
+    cache_size: usize,
+    hash: <S::Params as StoreParams>::Hashes,
+    width: Option<usize>,
+}
+
+impl<S> DequeConfig<S>
+where
+    S: CollectionStore<DagCborCodec>,
+{
+    pub fn new(store: S, hash: <S::Params as StoreParams>::Hashes) -> Self {
+        Self::with_codec(store, DagCborCodec, hash)
+    }
+}
+
+impl<S, C> DequeConfig<S, C>
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+{
+    pub fn with_codec(store: S, codec: C, hash: <S::Params as StoreParams>::Hashes) -> Self {
+        Self {
+            store,
+            codec,
+            cache_size: 64,
+            hash,
+            width: None,
+        }
+    }
+
+    pub fn set_cache_size(&mut self, cache_size: usize) {
+        self.cache_size = cache_size;
+    }
+
+    pub fn set_width(&mut self, width: usize) {
+        assert!(width > 1, "width has to be at least 2");
+        self.width = Some(width);
+    }
+
+    fn width<T>(&self) -> usize {
+        if let Some(width) = self.width {
+            width
+        } else {
+            let elem_size = usize::max(std::mem::size_of::<T>(), std::mem::size_of::<Cid>());
+            <S::Params as StoreParams>::MAX_BLOCK_SIZE / elem_size
+        }
+    }
+
+    fn cache<T>(self) -> NodeCache<S, C, Node<T>>
+    where
+        T: DagCbor + Clone + Send + Sync,
+    {
+        NodeCache::new(self.store, self.codec, self.hash, self.cache_size)
+    }
+}
+
+pub struct Deque<S: Store, T: DagCbor, C = DagCborCodec> {
+    cache: Arc<NodeCache<S, C, Node<T>>>,
+    hash: <S::Params as StoreParams>::Hashes,
+    root: Cid,
+    tmp: S::TempPin,
+}
+
+impl<S, T, C> Deque<S, T, C>
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+    T: DagCbor + Clone + Send + Sync,
+{
+    pub async fn new(config: DequeConfig<S, C>) -> Result<Self> {
+        let width = config.width::<T>();
+        let hash = config.hash;
+        let cache = Arc::new(config.cache());
+        let tmp = cache.temp_pin().await?;
+        let root = cache
+            .insert(Node::new(width as _, 0, 0, vec![]), Some(&tmp))
+            .await?;
+        Ok(Self {
+            cache,
+            hash,
+            root,
+            tmp,
+        })
+    }
+
+    pub async fn open(config: DequeConfig<S, C>, root: Cid) -> Result<Self> {
+        let hash = config.hash;
+        let cache = Arc::new(config.cache());
+        let tmp = cache.temp_pin().await?;
+        // warm up the cache and make sure it's available
+        cache.get(&root, Some(&tmp)).await?;
+        Ok(Self {
+            cache,
+            hash,
+            root,
+            tmp,
+        })
+    }
+
+    pub fn root(&self) -> &Cid {
+        &self.root
+    }
+
+    pub async fn from(config: DequeConfig<S, C>, items: impl Iterator<Item = T>) -> Result<Self> {
+        let mut deque = Self::new(config).await?;
+        let mut tx = deque.transaction();
+        for item in items {
+            tx.push_back(item).await?;
+        }
+        tx.commit().await?;
+        Ok(deque)
+    }
+
+    pub fn transaction(&mut self) -> DequeTransaction<'_, S, T, C> {
+        let root = self.root;
+        let buffer = Buffer::new(self.cache.codec(), self.hash);
+        DequeTransaction {
+            deque: self,
+            root,
+            buffer,
+        }
+    }
+
+    pub async fn push_back(&mut self, value: T) -> Result<()> {
+        let mut tx = self.transaction();
+        tx.push_back(value).await?;
+        tx.commit().await
+    }
+
+    pub async fn push_front(&mut self, value: T) -> Result<()> {
+        let mut tx = self.transaction();
+        tx.push_front(value).await?;
+        tx.commit().await
+    }
+
+    pub async fn pop_back(&mut self) -> Result<Option<T>> {
+        let mut tx = self.transaction();
+        let value = tx.pop_back().await?;
+        tx.commit().await?;
+        Ok(value)
+    }
+
+    pub async fn pop_front(&mut self) -> Result<Option<T>> {
+        let mut tx = self.transaction();
+        let value = tx.pop_front().await?;
+        tx.commit().await?;
+        Ok(value)
+    }
+
+    pub async fn front(&self) -> Result<Option<T>> {
+        edge(&self.cache, &self.tmp, &self.root, End::Front).await
+    }
+
+    pub async fn back(&self) -> Result<Option<T>> {
+        edge(&self.cache, &self.tmp, &self.root, End::Back).await
+    }
+
+    pub async fn get(&self, index: u64) -> Result<Option<T>> {
+        get(&self.cache, &self.tmp, &self.root, index).await
+    }
+
+    pub async fn len(&self) -> Result<u64> {
+        len(&self.cache, &self.tmp, &self.root).await
+    }
+
+    pub async fn is_empty(&self) -> Result<bool> {
+        let root = self.cache.get(&self.root, Some(&self.tmp)).await?;
+        Ok(root.data.is_empty())
+    }
+
+    pub fn iter(&self) -> DequeIter<'_, S, T, C> {
+        DequeIter {
+            deque: self,
+            nodes: Traversal::new(1, self.root, ()),
+            values: vec![],
+        }
+    }
+
+    pub async fn flush<A: AsRef<[u8]> + Send + Sync>(&mut self, alias: A) -> Result<()> {
+        self.cache.alias(alias, Some(self.root())).await?;
+        self.tmp = self.cache.temp_pin().await?;
+        self.cache.flush().await?;
+        Ok(())
+    }
+}
+
+pub struct DequeTransaction<'a, S: Store, T: DagCbor, C = DagCborCodec> {
+    deque: &'a mut Deque<S, T, C>,
+    root: Cid,
+    buffer: Buffer<S, C, Node<T>>,
+}
+
+impl<'a, S, T, C> DequeTransaction<'a, S, T, C>
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+    T: DagCbor + Clone + Send + Sync,
+{
+    pub fn root(&self) -> &Cid {
+        &self.root
+    }
+
+    async fn node(&self, cid: &Cid) -> Result<Node<T>> {
+        if let Some(node) = self.buffer.get(cid) {
+            return Ok(node.clone());
+        }
+        self.deque.cache.get(cid, Some(&self.deque.tmp)).await
+    }
+
+    // the nodes along the front or back edge, from the root down to a leaf
+    async fn path(&self, end: End) -> Result<Vec<Node<T>>> {
+        let mut node = self.node(&self.root).await?;
+        let mut path = Vec::with_capacity(node.height as usize + 1);
+        while node.height > 0 {
+            let cid = *node
+                .edge(end)
+                .cid()
+                .expect("height > 0, payload must be a cid");
+            path.push(node);
+            node = self.node(&cid).await?;
+        }
+        path.push(node);
+        Ok(path)
+    }
+
+    // wraps value in a chain of single element nodes up to height
+    fn chain(&mut self, end: End, width: u32, height: u32, value: T) -> Result<Data<T>> {
+        let offset = match end {
+            End::Front => width - 1,
+            End::Back => 0,
+        };
+        let mut data = Data::Value(value);
+        for height in 0..height {
+            let node = Node::new(width, height, offset, vec![data]);
+            data = Data::Link(self.buffer.insert_spilling(node)?);
+        }
+        Ok(data)
+    }
+
+    async fn push(&mut self, end: End, value: T) -> Result<()> {
+        let mut path = self.path(end).await?;
+        let width = path[0].width;
+        let height = path[0].height;
+        if path[0].data.is_empty() {
+            path[0].offset = match end {
+                End::Front => width,
+                End::Back => 0,
+            };
+        }
+        match path.iter().rposition(|node| node.has_room(end)) {
+            Some(depth) => {
+                path.truncate(depth + 1);
+                let height = path[depth].height;
+                let data = self.chain(end, width, height, value)?;
+                path[depth].push(end, data);
+                let mut cid = None;
+                for mut node in path.into_iter().rev() {
+                    if let Some(cid) = cid {
+                        node.set_edge(end, Data::Link(cid));
+                    }
+                    cid = Some(self.buffer.insert_spilling(node)?);
+                }
+                self.root = cid.expect("at least one node");
+            }
+            None => {
+                // the tree is full towards this end, so it grows a new root
+                // with the old one on the opposite side
+                let data = self.chain(end, width, height + 1, value)?;
+                let node = match end {
+                    End::Front => Node::new(
+                        width,
+                        height + 1,
+                        width - 2,
+                        vec![data, Data::Link(self.root)],
+                    ),
+                    End::Back => Node::new(width, height + 1, 0, vec![Data::Link(self.root), data]),
+                };
+                self.root = self.buffer.insert_spilling(node)?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn pop(&mut self, end: End) -> Result<Option<T>> {
+        let mut path = self.path(end).await?;
+        let width = path[0].width;
+        let value = match path.last_mut().expect("at least one node").pop(end) {
+            Some(Data::Value(value)) => value,
+            Some(Data::Spilled(cid)) => self.buffer.load(&self.deque.cache, &cid).await?,
+            Some(Data::Link(_)) => unreachable!(),
+            None => return Ok(None),
+        };
+        // emptied nodes are dropped from their parent, which releases whole
+        // subtrees at the edge
+        let mut data = None;
+        for mut node in path.into_iter().rev() {
+            match data.take() {
+                Some(Some(link)) => node.set_edge(end, link),
+                Some(None) => {
+                    node.pop(end);
+                }
+                None => {}
+            }
+            data = Some(if node.data.is_empty() {
+                None
+            } else {
+                Some(Data::Link(self.buffer.insert_spilling(node)?))
+            });
+        }
+        self.root = match data {
+            Some(Some(Data::Link(cid))) => cid,
+            _ => self.buffer.insert(Node::new(width, 0, 0, vec![]))?,
+        };
+        // a root with a single child is replaced by that child
+        loop {
+            let root = self.node(&self.root).await?;
+            if root.height == 0 || root.data.len() > 1 {
+                break;
+            }
+            self.root = *root.data[0]
+                .cid()
+                .expect("height > 0, payload must be a cid");
+        }
+        Ok(Some(value))
+    }
+
+    pub async fn push_back(&mut self, value: T) -> Result<()> {
+        self.push(End::Back, value).await
+    }
+
+    pub async fn push_front(&mut self, value: T) -> Result<()> {
+        self.push(End::Front, value).await
+    }
+
+    pub async fn pop_back(&mut self) -> Result<Option<T>> {
+        self.pop(End::Back).await
+    }
+
+    pub async fn pop_front(&mut self) -> Result<Option<T>> {
+        self.pop(End::Front).await
+    }
+
+    pub async fn commit(self) -> Result<()> {
+        let Self {
+            deque,
+            root,
+            buffer,
+        } = self;
+        buffer.commit(&deque.cache, &deque.tmp, &root).await?;
+        deque.root = root;
+        Ok(())
+    }
+
+    pub fn rollback(self) {}
+}
+
+async fn edge<S, T, C>(
+    cache: &NodeCache<S, C, Node<T>>,
+    tmp: &S::TempPin,
+    root: &Cid,
+    end: End,
+) -> Result<Option<T>>
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+    T: DagCbor + Clone + Send + Sync,
+{
+    let mut node = cache.get(root, Some(tmp)).await?;
+    if node.data.is_empty() {
+        return Ok(None);
+    }
+    while node.height > 0 {
+        let cid = *node
+            .edge(end)
+            .cid()
+            .expect("height > 0, payload must be a cid");
+        node = cache.get(&cid, Some(tmp)).await?;
+    }
+    node.edge(end).load(cache).await
+}
+
+// the elements occupy a contiguous range of the slots the tree could hold,
+// this returns the position of the first one and the one past the last
+async fn bounds<S, T, C>(
+    cache: &NodeCache<S, C, Node<T>>,
+    tmp: &S::TempPin,
+    root: &Cid,
+) -> Result<(u64, u64)>
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+    T: DagCbor + Clone + Send + Sync,
+{
+    let root = cache.get(root, Some(tmp)).await?;
+    if root.data.is_empty() {
+        return Ok((0, 0));
+    }
+    let mut bounds = [0, 0];
+    for (bound, end) in bounds.iter_mut().zip([End::Front, End::Back].iter()) {
+        let mut node = root.clone();
+        loop {
+            let slot = match end {
+                End::Front => node.offset,
+                End::Back => node.offset + node.data.len() as u32 - 1,
+            };
+            *bound += slot as u64 * node.capacity();
+            if node.height == 0 {
+                break;
+            }
+            let cid = *node
+                .edge(*end)
+                .cid()
+                .expect("height > 0, payload must be a cid");
+            node = cache.get(&cid, Some(tmp)).await?;
+        }
+    }
+    Ok((bounds[0], bounds[1] + 1))
+}
+
+async fn get<S, T, C>(
+    cache: &NodeCache<S, C, Node<T>>,
+    tmp: &S::TempPin,
+    root: &Cid,
+    index: u64,
+) -> Result<Option<T>>
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+    T: DagCbor + Clone + Send + Sync,
+{
+    let (start, end) = bounds(cache, tmp, root).await?;
+    if index >= end - start {
+        return Ok(None);
+    }
+    let mut slot = start + index;
+    let mut node = cache.get(root, Some(tmp)).await?;
+    loop {
+        let capacity = node.capacity();
+        let data = &node.data[(slot / capacity) as usize - node.offset as usize];
+        if node.height == 0 {
+            return data.load(cache).await;
+        }
+        slot %= capacity;
+        let cid = *data.cid().expect("height > 0, payload must be a cid");
+        node = cache.get(&cid, Some(tmp)).await?;
+    }
+}
+
+async fn len<S, T, C>(cache: &NodeCache<S, C, Node<T>>, tmp: &S::TempPin, root: &Cid) -> Result<u64>
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+    T: DagCbor + Clone + Send + Sync,
+{
+    let (start, end) = bounds(cache, tmp, root).await?;
+    Ok(end - start)
+}
+
+// walks the leaves in order, keeping the values of the current one
+pub struct DequeIter<'a, S: Store, T: DagCbor, C = DagCborCodec> {
+    deque: &'a Deque<S, T, C>,
+    nodes: Traversal<S::Params, ()>,
+    values: Vec<T>,
+}
+
+impl<'a, S, T, C> DequeIter<'a, S, T, C>
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+    T: DagCbor + Clone + Send + Sync,
+{
+    #[allow(clippy::should_implement_trait)]
+    pub async fn next(&mut self) -> Result<Option<T>> {
+        let cache = &self.deque.cache;
+        while self.values.is_empty() {
+            let node = match cache.next(&mut self.nodes).await {
+                Some((_, (), node)) => node?,
+                None => return Ok(None),
+            };
+            for cid in node.links().into_iter().rev() {
+                self.nodes.push(cid, ());
+            }
+            let spilled = node.spilled();
+            let mut loaded = vec![];
+            for value in cache.load_many(&spilled, 1).await {
+                loaded.push(value?);
+            }
+            let mut loaded = loaded.into_iter();
+            for data in node.data.into_iter().rev() {
+                match data {
+                    Data::Value(value) => self.values.push(value),
+                    Data::Spilled(_) => self.values.push(loaded.next_back().expect("loaded")),
+                    Data::Link(_) => {}
+                }
+            }
+        }
+        Ok(self.values.pop())
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum End {
+    Front,
+    Back,
+}
+
+// like a list node, but the data only starts at slot offset so elements can
+// be added and removed at the front
+#[derive(Clone, Debug, DagCbor)]
+struct Node<T: DagCbor> {
+    width: u32,
+    height: u32,
+    offset: u32,
+    data: Vec<Data<T>>,
+}
+
+impl<T: DagCbor> Links for Node<T> {
+    fn links(&self) -> Vec<Cid> {
+        self.data.iter().filter_map(Data::cid).copied().collect()
+    }
+
+    fn spilled(&self) -> Vec<Cid> {
+        self.data
+            .iter()
+            .filter_map(|data| match data {
+                Data::Spilled(cid) => Some(*cid),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+impl<P, T> Spill<P> for Node<T>
+where
+    P: StoreParams,
+    T: DagCbor,
+{
+    fn spill<C>(&mut self, codec: C, hash: P::Hashes, excess: usize) -> Result<Vec<Block<P>>>
+    where
+        C: NodeCodec,
+    {
+        spill(&mut self.data, codec, hash, excess)
+    }
+}
+
+impl<T: DagCbor> Node<T> {
+    fn new(width: u32, height: u32, offset: u32, data: Vec<Data<T>>) -> Self {
+        Node {
+            width,
+            height,
+            offset,
+            data,
+        }
+    }
+
+    // number of slots covered by each child
+    fn capacity(&self) -> u64 {
+        (self.width as u64).pow(self.height)
+    }
+
+    fn has_room(&self, end: End) -> bool {
+        match end {
+            End::Front => self.offset > 0,
+            End::Back => (self.offset as usize + self.data.len()) < self.width as usize,
+        }
+    }
+
+    fn edge(&self, end: End) -> &Data<T> {
+        match end {
+            End::Front => self.data.first(),
+            End::Back => self.data.last(),
+        }
+        .expect("at least one element")
+    }
+
+    fn set_edge(&mut self, end: End, data: Data<T>) {
+        let index = match end {
+            End::Front => 0,
+            End::Back => self.data.len() - 1,
+        };
+        self.data[index] = data;
+    }
+
+    fn push(&mut self, end: End, data: Data<T>) {
+        match end {
+            End::Front => {
+                self.offset -= 1;
+                self.data.insert(0, data);
+            }
+            End::Back => self.data.push(data),
+        }
+    }
+
+    fn pop(&mut self, end: End) -> Option<Data<T>> {
+        if self.data.is_empty() {
+            return None;
+        }
+        match end {
+            End::Front => {
+                self.offset += 1;
+                Some(self.data.remove(0))
+            }
+            End::Back => self.data.pop(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::task;
+    use libipld::mem::MemStore;
+    use libipld::multihash::Code;
+    use libipld::store::DefaultParams;
+    use model::*;
+    use std::collections::VecDeque;
+
+    async fn collect<T>(deque: &Deque<MemStore<DefaultParams>, T>) -> Result<Vec<T>>
+    where
+        T: DagCbor + Clone + Send + Sync,
+    {
+        let mut values = vec![];
+        let mut iter = deque.iter();
+        while let Some(value) = iter.next().await? {
+            values.push(value);
+        }
+        Ok(values)
+    }
+
+    #[async_std::test]
+    async fn test_deque() -> Result<()> {
+        let store = MemStore::<DefaultParams>::default();
+        let mut config = DequeConfig::new(store, Code::Blake2b256);
+        config.set_width(3);
+        let mut deque = Deque::new(config).await?;
+        for i in 0..20 {
+            deque.push_back(i as i64).await?;
+            deque.push_front(-(i as i64) - 1).await?;
+        }
+        assert_eq!(deque.len().await?, 40);
+        assert_eq!(deque.front().await?, Some(-20));
+        assert_eq!(deque.back().await?, Some(19));
+        for i in 0..40 {
+            assert_eq!(deque.get(i).await?, Some(i as i64 - 20));
+        }
+        assert_eq!(collect(&deque).await?, (-20..20).collect::<Vec<_>>());
+        for i in 0..20 {
+            assert_eq!(deque.pop_front().await?, Some(i - 20));
+        }
+        for i in (0..20).rev() {
+            assert_eq!(deque.pop_back().await?, Some(i));
+        }
+        assert_eq!(deque.pop_front().await?, None);
+        assert!(deque.is_empty().await?);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_deque_spills_large_values() -> Result<()> {
+        let store = MemStore::<DefaultParams>::default();
+        let mut config = DequeConfig::new(store, Code::Blake2b256);
+        config.set_width(3);
+        let value = |i: usize| vec![i as u8; DefaultParams::MAX_BLOCK_SIZE / 2].into_boxed_slice();
+        let mut deque = Deque::new(config).await?;
+        for i in 0..5 {
+            deque.push_back(value(i)).await?;
+        }
+        assert_eq!(deque.front().await?, Some(value(0)));
+        assert_eq!(deque.back().await?, Some(value(4)));
+        assert_eq!(deque.get(2).await?, Some(value(2)));
+        assert_eq!(
+            collect(&deque).await?,
+            (0..5).map(value).collect::<Vec<_>>()
+        );
+        for i in 0..5 {
+            assert_eq!(deque.pop_front().await?, Some(value(i)));
+        }
+        assert_eq!(deque.pop_front().await?, None);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_deque_releases_subtrees() -> Result<()> {
+        let store = MemStore::<DefaultParams>::default();
+        let mut config = DequeConfig::new(store, Code::Blake2b256);
+        config.set_width(3);
+        let mut deque = Deque::from(config, (0..27).map(|i| i as i64)).await?;
+        let root = deque.cache.get(deque.root(), None).await?;
+        let first = *root.data[0].cid().unwrap();
+        for i in 0..9 {
+            assert_eq!(deque.pop_front().await?, Some(i));
+        }
+        // the first subtree is gone while its siblings are untouched
+        let node = deque.cache.get(deque.root(), None).await?;
+        assert_eq!(node.offset, 1);
+        assert_eq!(node.data.len(), 2);
+        assert!(!node.links().contains(&first));
+        assert_eq!(node.links()[..], root.links()[1..]);
+        Ok(())
+    }
+
+    #[test]
+    fn deque_vec_deque_eqv() {
+        const LEN: usize = 25;
+        model! {
+            Model => let mut vec = VecDeque::new(),
+            Implementation => let mut deque = {
+                let store = MemStore::<DefaultParams>::default();
+                let mut config = DequeConfig::new(store, Code::Blake2b256);
+                config.set_width(3);
+                let fut = Deque::new(config);
+                task::block_on(fut).unwrap()
+            },
+            PushBack(usize)(i in 0..LEN) => {
+                vec.push_back(i as i64);
+                task::block_on(deque.push_back(i as i64)).unwrap();
+            },
+            PushFront(usize)(i in 0..LEN) => {
+                vec.push_front(i as i64);
+                task::block_on(deque.push_front(i as i64)).unwrap();
+            },
+            PopBack(usize)(_ in 0..LEN) => {
+                let r1 = vec.pop_back();
+                let r2 = task::block_on(deque.pop_back()).unwrap();
+                assert_eq!(r1, r2);
+            },
+            PopFront(usize)(_ in 0..LEN) => {
+                let r1 = vec.pop_front();
+                let r2 = task::block_on(deque.pop_front()).unwrap();
+                assert_eq!(r1, r2);
+            },
+            Front(usize)(_ in 0..LEN) => {
+                let r1 = vec.front().cloned();
+                let r2 = task::block_on(deque.front()).unwrap();
+                assert_eq!(r1, r2);
+            },
+            Iter(usize)(_ in 0..LEN) => {
+                let r1: Vec<_> = vec.iter().cloned().collect();
+                let r2 = task::block_on(collect(&deque)).unwrap();
+                assert_eq!(r1, r2);
+            },
+            Get(usize)(i in 0..LEN) => {
+                let r1 = vec.get(i).cloned();
+                let r2 = task::block_on(deque.get(i as u64)).unwrap();
+                assert_eq!(r1, r2);
+            },
+            Len(usize)(_ in 0..LEN) => {
+                let r1 = vec.len() as u64;
+                let r2 = task::block_on(deque.len()).unwrap();
+                assert_eq!(r1, r2);
+            }
+        }
+    }
+}
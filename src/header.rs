@@ -0,0 +1,293 @@
+use crate::codec::{decode_by_cid, CollectionStore, NodeCodec};
+use crate::list::ListConfig;
+use crate::map::{key_hash, HamtConfig};
+use crate::prolly::ProllyConfig;
+use libipld::cbor::DagCborCodec;
+use libipld::codec::Codec;
+use libipld::error::{UnsupportedCodec, UnsupportedMultihash};
+use libipld::ipld::Ipld;
+use libipld::store::StoreParams;
+use libipld::DagCbor;
+use libipld::{Cid, Result};
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{Error, ErrorKind};
+
+pub const FORMAT_VERSION: u64 = 1;
+
+// kept in the root node of a collection, so the root tells what it is and
+// how it was configured
+#[derive(Clone, Debug, Eq, PartialEq, DagCbor)]
+pub struct Header {
+    pub kind: Kind,
+    pub version: u64,
+    // the multihash code the blocks are hashed with
+    pub hash: u64,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, DagCbor)]
+pub enum Kind {
+    List {
+        // 0 when the leaves are split by size
+        leaf_width: u64,
+        branch_width: u64,
+        split_by_size: bool,
+    },
+    Hamt {
+        bucket_size: u64,
+        // the multihash code the keys are hashed with
+        key_hash: u64,
+    },
+    Prolly {
+        fanout: u64,
+    },
+}
+
+impl Kind {
+    fn name(&self) -> &'static str {
+        match self {
+            Kind::List { .. } => "list",
+            Kind::Hamt { .. } => "hamt",
+            Kind::Prolly { .. } => "prolly",
+        }
+    }
+}
+
+// the root was written by a newer version of the crate, or by an older one
+// and has to be migrated before it can be opened
+#[derive(Clone, Copy, Debug)]
+pub struct UnsupportedVersion(pub u64);
+
+impl fmt::Display for UnsupportedVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0 < FORMAT_VERSION {
+            return write!(
+                f,
+                "format version {} is outdated, the root needs to be migrated to version {} \
+                 with migrate before it can be opened",
+                self.0, FORMAT_VERSION
+            );
+        }
+        write!(
+            f,
+            "unsupported format version {}, the newest supported is {}",
+            self.0, FORMAT_VERSION
+        )
+    }
+}
+
+impl std::error::Error for UnsupportedVersion {}
+
+// roots written before headers were introduced are version 0
+pub(crate) fn version(header: Option<&Header>) -> u64 {
+    header.map(|header| header.version).unwrap_or(0)
+}
+
+// makes sure the root can be opened as the collection kind
+pub(crate) fn check(header: Option<&Header>, kind: &str) -> Result<()> {
+    let version = version(header);
+    if version != FORMAT_VERSION {
+        return Err(UnsupportedVersion(version).into());
+    }
+    check_kind(header, kind)
+}
+
+// like check, but lets older roots through so they can be migrated
+pub(crate) fn check_migrate(header: Option<&Header>, kind: &str) -> Result<()> {
+    let version = version(header);
+    if version > FORMAT_VERSION {
+        return Err(UnsupportedVersion(version).into());
+    }
+    check_kind(header, kind)
+}
+
+fn check_kind(header: Option<&Header>, kind: &str) -> Result<()> {
+    match header {
+        Some(header) if header.kind.name() != kind => {
+            let msg = format!("expected a {} root, found a {}", kind, header.kind.name());
+            Err(Error::new(ErrorKind::InvalidData, msg).into())
+        }
+        _ => Ok(()),
+    }
+}
+
+// the hashes the blocks of the collection are hashed with, the collections
+// take it from their header instead of their config
+pub(crate) fn hash<P: StoreParams>(header: Option<&Header>) -> Result<P::Hashes> {
+    let code = header.map(|header| header.hash).unwrap_or_default();
+    P::Hashes::try_from(code).map_err(|_| UnsupportedMultihash(code).into())
+}
+
+// the bucket size of a hamt. the keys have to be hashed the same way this
+// build of the crate hashes them
+pub(crate) fn bucket_size(header: Option<&Header>) -> Result<usize> {
+    match header.map(|header| &header.kind) {
+        Some(Kind::Hamt {
+            bucket_size,
+            key_hash: code,
+        }) => {
+            if *code != key_hash() {
+                return Err(UnsupportedMultihash(*code).into());
+            }
+            if *bucket_size == 0 {
+                return Err(Error::new(ErrorKind::InvalidData, "bucket size of 0").into());
+            }
+            Ok(*bucket_size as usize)
+        }
+        _ => Err(Error::new(ErrorKind::InvalidData, "not the root of a hamt").into()),
+    }
+}
+
+// the fanout of a prolly tree, the boundaries of its nodes depend on it
+pub(crate) fn fanout(header: Option<&Header>) -> Result<usize> {
+    match header.map(|header| &header.kind) {
+        Some(Kind::Prolly { fanout }) if *fanout > 1 => Ok(*fanout as usize),
+        Some(Kind::Prolly { fanout }) => {
+            let msg = format!("fanout of {}", fanout);
+            Err(Error::new(ErrorKind::InvalidData, msg).into())
+        }
+        _ => Err(Error::new(ErrorKind::InvalidData, "not the root of a prolly tree").into()),
+    }
+}
+
+// a config for opening the collection a root belongs to
+pub enum Collection<S, C = DagCborCodec>
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+{
+    List(ListConfig<S, C>),
+    Hamt(HamtConfig<S, C>),
+    Prolly(ProllyConfig<S, C>),
+}
+
+pub async fn detect<S, C>(store: S, root: &Cid) -> Result<Collection<S, C>>
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+{
+    let block = store.get(root).await?;
+    let node: Ipld = decode_by_cid(root, block.data())?;
+    let header: Header = match node.get("header") {
+        Ok(header) => DagCborCodec.decode(&DagCborCodec.encode(header)?)?,
+        Err(_) => {
+            return Err(Error::new(ErrorKind::InvalidData, "not the root of a collection").into())
+        }
+    };
+    // some codecs convert from any code
+    let codec = C::try_from(root.codec())?;
+    if codec.into() != root.codec() {
+        return Err(UnsupportedCodec(root.codec()).into());
+    }
+    let hash = self::hash::<S::Params>(Some(&header))?;
+    Ok(match header.kind {
+        Kind::List {
+            leaf_width,
+            branch_width,
+            split_by_size,
+        } => {
+            let mut config = ListConfig::with_codec(store, codec, hash);
+            if split_by_size {
+                config.set_split_by_size(true);
+            } else {
+                config.set_leaf_width(leaf_width as usize);
+            }
+            config.set_branch_width(branch_width as usize);
+            Collection::List(config)
+        }
+        Kind::Hamt { .. } => {
+            let mut config = HamtConfig::with_codec(store, codec, hash);
+            config.set_bucket_size(bucket_size(Some(&header))?);
+            Collection::Hamt(config)
+        }
+        Kind::Prolly { .. } => {
+            let mut config = ProllyConfig::with_codec(store, codec, hash);
+            config.set_fanout(fanout(Some(&header))?);
+            Collection::Prolly(config)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::list::List;
+    use crate::map::Hamt;
+    use libipld::json::DagJsonCodec;
+    use libipld::mem::MemStore;
+    use libipld::multihash::Code;
+    use libipld::store::DefaultParams;
+
+    #[async_std::test]
+    async fn test_detect_list() -> Result<()> {
+        let store = MemStore::<DefaultParams>::default();
+        let mut config = ListConfig::new(store.clone(), Code::Blake2b256);
+        config.set_leaf_width(3);
+        config.set_branch_width(2);
+        let mut list = List::from(config, 0..6u64).await?;
+        // grows a level, the header moves to the new root
+        list.push(6).await?;
+        let root = *list.root();
+        let config = match detect::<_, DagCborCodec>(store.clone(), &root).await? {
+            Collection::List(config) => config,
+            _ => unreachable!(),
+        };
+        let list = List::<_, u64>::open(config, root).await?;
+        let header = list.header().await?.unwrap();
+        assert_eq!(
+            header.kind,
+            Kind::List {
+                leaf_width: 3,
+                branch_width: 2,
+                split_by_size: false,
+            }
+        );
+        assert_eq!(header.version, FORMAT_VERSION);
+        assert_eq!(header.hash, u64::from(Code::Blake2b256));
+        for i in 0..7 {
+            assert_eq!(list.get(i).await?, Some(i as u64));
+        }
+
+        // nodes below the root don't carry the header
+        let mut children = 0;
+        for block in list.prove(0).await?.unwrap().iter().skip(1) {
+            assert!(detect::<_, DagCborCodec>(store.clone(), block.cid())
+                .await
+                .is_err());
+            children += 1;
+        }
+        assert_eq!(children, 2);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_detect_hamt() -> Result<()> {
+        let store = MemStore::<DefaultParams>::default();
+        let mut config = HamtConfig::with_codec(store.clone(), DagJsonCodec, Code::Blake2b256);
+        config.set_bucket_size(2);
+        let mut hamt = Hamt::new(config).await?;
+        for i in 0..20u8 {
+            hamt.insert(Box::new([i % 4, i]), i).await?;
+        }
+        let root = *hamt.root();
+        assert!(detect::<_, DagCborCodec>(store.clone(), &root)
+            .await
+            .is_err());
+        let config = match detect::<_, DagJsonCodec>(store.clone(), &root).await? {
+            Collection::Hamt(config) => config,
+            _ => unreachable!(),
+        };
+        let hamt = Hamt::<_, u8, _>::open(config, root).await?;
+        assert_eq!(
+            hamt.header().await?.unwrap().kind,
+            Kind::Hamt {
+                bucket_size: 2,
+                key_hash: key_hash(),
+            }
+        );
+        for i in 0..20u8 {
+            assert_eq!(hamt.get(&[i % 4, i]).await?, Some(i));
+        }
+        Ok(())
+    }
+}
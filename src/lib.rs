@@ -1,5 +1,33 @@
+mod amt;
+mod bytes;
+mod cache;
+mod car;
+mod codec;
+mod deque;
+mod header;
 mod list;
 mod map;
+mod multi;
+mod plain;
+mod prolly;
+mod proof;
+mod set;
+mod stats;
+mod tx;
+mod verify;
+mod visit;
 
-pub use list::{List, ListConfig, ListIter};
-pub use map::Hamt;
+pub use amt::{Amt, AmtConfig, AmtIter, AmtTransaction};
+pub use bytes::{Bytes, BytesConfig, BytesTransaction};
+pub use car::read_car;
+pub use codec::{CollectionStore, NodeCodec};
+pub use deque::{Deque, DequeConfig, DequeIter, DequeTransaction};
+pub use header::{detect, Collection, Header, Kind, UnsupportedVersion, FORMAT_VERSION};
+pub use list::{verify_list_proof, List, ListConfig, ListIter, ListSnapshot, ListTransaction};
+pub use map::{verify_hamt_proof, Hamt, HamtConfig, HamtIter, HamtSnapshot, HamtTransaction};
+pub use multi::{HamtMulti, HamtMultiIter};
+pub use prolly::{Prolly, ProllyConfig, ProllyRange, ProllyTransaction};
+pub use set::{HamtSet, HamtSetIter};
+pub use stats::Stats;
+pub use verify::{Problem, Report};
+pub use visit::Visitor;
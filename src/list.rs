@@ -1,41 +1,70 @@
-use libipld::cache::Cache;
-use libipld::cache::IpldCache;
+use crate::cache::{NodeCache, Traversal};
+use crate::car;
+use crate::codec::{encode, CollectionStore, NodeCodec};
+use crate::header::{self, Header, Kind, FORMAT_VERSION};
+use crate::plain::{self, Guard, JsonArray};
+use crate::proof::next_block;
+use crate::stats::{self, Stats};
+use crate::tx::{Buffer, Links, Spill};
+use crate::verify::{Problem, Report};
+use crate::visit::{self, Item, Visitor, Walker};
+use libipld::block::Block;
 use libipld::cbor::DagCbor;
 use libipld::cbor::DagCborCodec;
 use libipld::cid::Cid;
-use libipld::error::Result;
+use libipld::error::{Error, Result};
 use libipld::ipld::Ipld;
-use libipld::prelude::{Decode, Encode, References};
 use libipld::store::Store;
 use libipld::store::StoreParams;
 use libipld::DagCbor;
+use std::io::{self, ErrorKind, Read, Write};
+use std::marker::PhantomData;
+use std::ops::{Bound, RangeBounds};
+use std::sync::Arc;
 
-pub struct ListConfig<S>
+// room left in a leaf for the fields besides its data when leaves are split
+// by their encoded size
+const LEAF_OVERHEAD: usize = 64;
+
+pub struct ListConfig<S, C = DagCborCodec>
 where
-    S: Store,
-    <S::Params as StoreParams>::Codecs: Into<DagCborCodec>,
-    DagCborCodec: Into<<S::Params as StoreParams>::Codecs>,
-    Ipld: References<<S::Params as StoreParams>::Codecs>,
+    S: CollectionStore<C>,
+    C: NodeCodec,
 {
     store: S,
+    codec: C,
     cache_size: usize,
+    concurrency: usize,
     hash: <S::Params as StoreParams>::Hashes,
-    width: Option<usize>,
+    leaf_width: Option<usize>,
+    branch_width: Option<usize>,
+    split_by_size: bool,
 }
 
 impl<S> ListConfig<S>
 where
-    S: Store,
-    <S::Params as StoreParams>::Codecs: Into<DagCborCodec>,
-    DagCborCodec: Into<<S::Params as StoreParams>::Codecs>,
-    Ipld: References<<S::Params as StoreParams>::Codecs>,
+    S: CollectionStore<DagCborCodec>,
 {
     pub fn new(store: S, hash: <S::Params as StoreParams>::Hashes) -> Self {
+        Self::with_codec(store, DagCborCodec, hash)
+    }
+}
+
+impl<S, C> ListConfig<S, C>
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+{
+    pub fn with_codec(store: S, codec: C, hash: <S::Params as StoreParams>::Hashes) -> Self {
         Self {
             store,
+            codec,
             cache_size: 64,
+            concurrency: 1,
             hash,
-            width: None,
+            leaf_width: None,
+            branch_width: None,
+            split_by_size: false,
         }
     }
 
@@ -43,12 +72,38 @@ where
         self.cache_size = cache_size;
     }
 
+    // the number of blocks fetched at once when walking the whole collection,
+    // like iter, verify and export_car do
+    pub fn set_concurrency(&mut self, concurrency: usize) {
+        self.concurrency = concurrency;
+    }
+
+    // sets both the leaf and the branch width
     pub fn set_width(&mut self, width: usize) {
-        self.width = Some(width);
+        self.leaf_width = Some(width);
+        self.branch_width = Some(width);
     }
 
-    fn width<T>(&self) -> usize {
-        if let Some(width) = self.width {
+    pub fn set_leaf_width(&mut self, width: usize) {
+        self.leaf_width = Some(width);
+    }
+
+    pub fn set_branch_width(&mut self, width: usize) {
+        self.branch_width = Some(width);
+    }
+
+    // fills leaves until their encoding reaches the block size instead of
+    // giving them a fixed width, the interior nodes then keep the number of
+    // values below each of their links
+    pub fn set_split_by_size(&mut self, split_by_size: bool) {
+        self.split_by_size = split_by_size;
+    }
+
+    // leaves split by size are stored with a width of 0
+    fn leaf_width<T>(&self) -> usize {
+        if self.split_by_size {
+            0
+        } else if let Some(width) = self.leaf_width {
             width
         } else {
             let elem_size = usize::max(std::mem::size_of::<T>(), std::mem::size_of::<Cid>());
@@ -56,86 +111,594 @@ where
         }
     }
 
-    fn cache<T>(self) -> IpldCache<S, DagCborCodec, Node<T>>
+    fn branch_width(&self) -> usize {
+        if let Some(width) = self.branch_width {
+            width
+        } else if self.split_by_size {
+            let elem_size = std::mem::size_of::<Cid>() + std::mem::size_of::<u64>();
+            <S::Params as StoreParams>::MAX_BLOCK_SIZE / elem_size
+        } else {
+            <S::Params as StoreParams>::MAX_BLOCK_SIZE / std::mem::size_of::<Cid>()
+        }
+    }
+
+    fn header<T>(&self) -> Header {
+        Header {
+            kind: Kind::List {
+                leaf_width: self.leaf_width::<T>() as u64,
+                branch_width: self.branch_width() as u64,
+                split_by_size: self.split_by_size,
+            },
+            version: FORMAT_VERSION,
+            hash: self.hash.into(),
+        }
+    }
+
+    fn cache<T>(self) -> NodeCache<S, C, Node<T>>
     where
         T: DagCbor + Clone + Send + Sync,
     {
-        IpldCache::new(self.store, DagCborCodec, self.hash, self.cache_size)
+        NodeCache::new(self.store, self.codec, self.hash, self.cache_size)
     }
 }
 
-pub struct List<S: Store, T: DagCbor> {
-    cache: IpldCache<S, DagCborCodec, Node<T>>,
+pub struct List<S: Store, T: DagCbor, C = DagCborCodec> {
+    cache: Arc<NodeCache<S, C, Node<T>>>,
+    hash: <S::Params as StoreParams>::Hashes,
+    concurrency: usize,
     root: Cid,
     tmp: S::TempPin,
 }
 
-impl<S, T> List<S, T>
+impl<S, T, C> List<S, T, C>
 where
-    S: Store,
-    <S::Params as StoreParams>::Codecs: Into<DagCborCodec>,
-    DagCborCodec: Into<<S::Params as StoreParams>::Codecs>,
-    Ipld: References<<S::Params as StoreParams>::Codecs>,
+    S: CollectionStore<C>,
+    C: NodeCodec,
     T: DagCbor + Clone + Send + Sync,
 {
-    pub async fn new(config: ListConfig<S>) -> Result<Self> {
-        let width = config.width::<T>();
-        let cache = config.cache();
+    pub async fn new(config: ListConfig<S, C>) -> Result<Self> {
+        let leaf_width = config.leaf_width::<T>() as u32;
+        let branch_width = config.branch_width() as u32;
+        let lens = config.split_by_size.then(Vec::new);
+        let header = config.header::<T>();
+        let hash = config.hash;
+        let concurrency = config.concurrency;
+        let cache = Arc::new(config.cache());
         let tmp = cache.temp_pin().await?;
-        let root = cache
-            .insert(Node::new(width as _, 0, vec![]), Some(&tmp))
-            .await?;
-        Ok(Self { cache, root, tmp })
+        let mut node = Node::new(leaf_width, branch_width, 0, vec![], lens);
+        node.header = Some(header);
+        let root = cache.insert(node, Some(&tmp)).await?;
+        Ok(Self {
+            cache,
+            hash,
+            concurrency,
+            root,
+            tmp,
+        })
     }
 
-    pub async fn open(config: ListConfig<S>, root: Cid) -> Result<Self> {
-        let cache = config.cache();
+    // the widths and hash of the list are taken from its root, whatever the
+    // config has them set to. roots without a header, written before they
+    // were introduced, have to be migrated first
+    pub async fn open(config: ListConfig<S, C>, root: Cid) -> Result<Self> {
+        let concurrency = config.concurrency;
+        let cache = Arc::new(config.cache());
         let tmp = cache.temp_pin().await?;
         // warm up the cache and make sure it's available
-        cache.get(&root, Some(&tmp)).await?;
-        Ok(Self { cache, root, tmp })
+        let node = cache.get(&root, Some(&tmp)).await?;
+        header::check(node.header.as_ref(), "list")?;
+        let hash = header::hash::<S::Params>(node.header.as_ref())?;
+        Ok(Self {
+            cache,
+            hash,
+            concurrency,
+            root,
+            tmp,
+        })
+    }
+
+    // rewrites a root written by an older version of the crate and returns
+    // the new one, roots that are up to date are returned as they are. the
+    // widths of old lists are read from their root
+    pub async fn migrate(config: ListConfig<S, C>, root: Cid) -> Result<Cid> {
+        let hash = config.hash.into();
+        let cache = config.cache();
+        let tmp = cache.temp_pin().await?;
+        let mut node: Node<T> = cache.get(&root, Some(&tmp)).await?;
+        header::check_migrate(node.header.as_ref(), "list")?;
+        match header::version(node.header.as_ref()) {
+            0 => {
+                node.header = Some(Header {
+                    kind: Kind::List {
+                        leaf_width: node.leaf_width() as u64,
+                        branch_width: node.branch_width() as u64,
+                        split_by_size: node.lens.is_some(),
+                    },
+                    version: FORMAT_VERSION,
+                    hash,
+                });
+                cache.insert(node, Some(&tmp)).await
+            }
+            _ => Ok(root),
+        }
     }
 
     pub fn root(&self) -> &Cid {
         &self.root
     }
 
-    pub async fn from(config: ListConfig<S>, items: impl Iterator<Item = T>) -> Result<Self> {
-        let width = config.width::<T>();
-        let cache = config.cache();
+    // roots written before headers were introduced don't have one
+    pub async fn header(&self) -> Result<Option<Header>> {
+        Ok(self.cache.get(&self.root, Some(&self.tmp)).await?.header)
+    }
+
+    // writes the list into a CARv1 file with the list's root as its root
+    pub async fn export_car<W: Write>(&self, mut writer: W) -> Result<()> {
+        car::export(
+            &self.cache,
+            &self.root,
+            self.concurrency,
+            Node::items,
+            &mut writer,
+        )
+        .await
+    }
+
+    // the root is checked before any of the blocks are inserted
+    pub async fn import_car<R: Read>(config: ListConfig<S, C>, mut reader: R) -> Result<Self> {
+        let concurrency = config.concurrency;
+        let cache = Arc::new(config.cache());
         let tmp = cache.temp_pin().await?;
+        let (root, blocks) = car::read::<S::Params, _>(&mut reader)?;
+        let node: Node<T> = cache.decode(car::root_block(&root, &blocks)?)?;
+        header::check(node.header.as_ref(), "list")?;
+        let hash = header::hash::<S::Params>(node.header.as_ref())?;
+        car::insert(&**cache, Some(&tmp), &blocks).await?;
+        Ok(Self {
+            cache,
+            hash,
+            concurrency,
+            root,
+            tmp,
+        })
+    }
 
-        let mut items: Vec<Data<T>> = items.map(Data::Value).collect();
-        let mut height = 0;
-        let mut cid = cache
-            .insert(Node::new(width as _, height, vec![]), Some(&tmp))
-            .await?;
+    // materializes the values as an ipld list, failing once their encoding
+    // gets larger than max_size
+    pub async fn to_ipld(&self, max_size: usize) -> Result<Ipld> {
+        let mut guard = Guard::new(max_size);
+        let mut values = vec![];
+        let mut iter = self.iter();
+        while let Some(value) = iter.next().await? {
+            values.push(guard.convert(&value)?);
+        }
+        Ok(Ipld::List(values))
+    }
+
+    pub async fn from_ipld(config: ListConfig<S, C>, ipld: &Ipld) -> Result<Self> {
+        let values = match ipld {
+            Ipld::List(values) => values,
+            _ => return Err(plain::unexpected("a list").into()),
+        };
+        let values = values
+            .iter()
+            .map(plain::from_ipld)
+            .collect::<Result<Vec<T>>>()?;
+        Self::from(config, values.into_iter()).await
+    }
+
+    // writes the values as a dag-json array, one at a time
+    pub async fn export_json<W: Write>(&self, mut writer: W) -> Result<()> {
+        let mut array = JsonArray::new(&mut writer)?;
+        let mut iter = self.iter();
+        while let Some(value) = iter.next().await? {
+            array.push(&value)?;
+        }
+        array.finish()
+    }
+
+    pub async fn from(config: ListConfig<S, C>, items: impl Iterator<Item = T>) -> Result<Self> {
+        let leaf_width = config.leaf_width::<T>();
+        let branch_width = config.branch_width();
+        let split_by_size = config.split_by_size;
+        let node = |height, data, lens| {
+            Node::new(leaf_width as u32, branch_width as u32, height, data, lens)
+        };
+        let header = config.header::<T>();
+        let hash = config.hash;
+        let concurrency = config.concurrency;
+        let cache = Arc::new(config.cache());
+        let tmp = cache.temp_pin().await?;
+
+        let mut buffer = Buffer::new(cache.codec(), hash);
+
+        let items: Vec<Data<T>> = items.map(Data::Value).collect();
+        let mut nodes = if split_by_size {
+            let max = <S::Params as StoreParams>::MAX_BLOCK_SIZE - LEAF_OVERHEAD;
+            let mut leaves = vec![];
+            let mut leaf = vec![];
+            let mut size = 0;
+            let leaf_node = |data, size| {
+                let mut node = node(0, data, Some(vec![]));
+                node.size = Some(size as u64);
+                node
+            };
+            for item in items {
+                let item_size = cache.codec().encode_node(&item)?.len();
+                if !leaf.is_empty() && size + item_size > max {
+                    leaves.push(leaf_node(leaf, size));
+                    leaf = vec![];
+                    size = 0;
+                }
+                size += item_size;
+                leaf.push(item);
+            }
+            leaves.push(leaf_node(leaf, size));
+            leaves
+        } else {
+            items
+                .chunks(leaf_width)
+                .map(|chunk| node(0, chunk.to_vec(), None))
+                .collect()
+        };
+        if nodes.is_empty() {
+            nodes.push(node(0, vec![], None));
+        }
 
+        let mut height = 0;
         loop {
-            let n_items = items.len() / width + 1;
-            let mut items_next = Vec::with_capacity(n_items);
-            for chunk in items.chunks(width) {
-                let node = Node::new(width as u32, height, chunk.to_vec());
-                cid = cache.insert(node, Some(&tmp)).await?;
-                items_next.push(Data::Link(cid));
-            }
-            if items_next.len() == 1 {
+            if nodes.len() == 1 {
+                let mut node = nodes.pop().expect("one node");
+                node.header = Some(header);
+                let root = buffer.insert_spilling(node)?;
+                buffer.commit(&cache, &tmp, &root).await?;
                 return Ok(Self {
                     cache,
-                    root: cid,
+                    hash,
+                    concurrency,
+                    root,
                     tmp,
                 });
             }
-            items = items_next;
+            let mut links = Vec::with_capacity(nodes.len());
+            for node in nodes {
+                let len = node.len();
+                links.push((Data::Link(buffer.insert_spilling(node)?), len));
+            }
             height += 1;
+            nodes = links
+                .chunks(branch_width)
+                .map(|chunk| {
+                    let data = chunk.iter().map(|(data, _)| data.clone()).collect();
+                    let lens = split_by_size.then(|| chunk.iter().map(|(_, len)| *len).collect());
+                    node(height, data, lens)
+                })
+                .collect();
+        }
+    }
+
+    pub fn transaction(&mut self) -> ListTransaction<'_, S, T, C> {
+        let root = self.root;
+        let buffer = Buffer::new(self.cache.codec(), self.hash);
+        ListTransaction {
+            list: self,
+            root,
+            buffer,
         }
     }
 
     pub async fn push(&mut self, value: T) -> Result<()> {
-        let mut value = Data::Value(value);
+        let mut tx = self.transaction();
+        tx.push(value).await?;
+        tx.commit().await
+    }
+
+    pub async fn pop(&mut self) -> Result<Option<T>> {
+        // TODO
+        Ok(None)
+    }
+
+    pub async fn get(&self, index: usize) -> Result<Option<T>> {
+        get(&self.cache, &self.tmp, &self.root, index).await
+    }
+
+    pub async fn set(&mut self, _index: usize, _value: T) -> Result<()> {
+        // TODO
+        Ok(())
+    }
+
+    pub async fn len(&self) -> Result<usize> {
+        len(&self.cache, &self.tmp, &self.root).await
+    }
+
+    pub async fn is_empty(&self) -> Result<bool> {
+        is_empty(&self.cache, &self.tmp, &self.root).await
+    }
+
+    // the blocks that reading the values in range needs but that aren't in the
+    // store. the children of a missing node are only known once it's fetched,
+    // see prefetch
+    pub async fn missing(&self, range: impl RangeBounds<usize>) -> Result<Vec<Cid>> {
+        let start = match range.start_bound() {
+            Bound::Included(start) => *start,
+            Bound::Excluded(start) => *start + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(end) => *end + 1,
+            Bound::Excluded(end) => *end,
+            Bound::Unbounded => usize::MAX,
+        };
+        let mut missing = vec![];
+        // the node and the index of its first value
+        let mut stack = vec![(self.root, 0)];
+        while let Some((cid, mut offset)) = stack.pop() {
+            if !Store::contains(&**self.cache, &cid).await? {
+                missing.push(cid);
+                continue;
+            }
+            let node = self.cache.get(&cid, None).await?;
+            let mut children = vec![];
+            for (i, data) in node.data.iter().enumerate() {
+                let len = node.child_len(i);
+                if offset < end && start < offset + len {
+                    match data {
+                        Data::Link(child) => children.push((*child, offset)),
+                        Data::Spilled(value) => {
+                            if !Store::contains(&**self.cache, value).await? {
+                                missing.push(*value);
+                            }
+                        }
+                        Data::Value(_) => {}
+                    }
+                }
+                offset += len;
+            }
+            stack.extend(children.into_iter().rev());
+        }
+        Ok(missing)
+    }
+
+    // fetches the blocks that reading the values in range needs, the blocks
+    // of each level are fetched concurrently
+    pub async fn prefetch(&self, range: impl RangeBounds<usize> + Clone) -> Result<()> {
+        let mut fetched = vec![];
+        loop {
+            let missing = self.missing(range.clone()).await?;
+            if missing.is_empty() {
+                return Ok(());
+            }
+            // the store didn't keep the blocks of the last round
+            if missing == fetched {
+                return Err(io::Error::new(
+                    ErrorKind::NotFound,
+                    "fetched blocks are still missing",
+                )
+                .into());
+            }
+            self.cache.fetch(&missing, &self.tmp).await?;
+            fetched = missing;
+        }
+    }
+
+    // the blocks on the path from the root to the leaf holding index, followed
+    // by the value's own block if it was spilled. see verify_list_proof
+    pub async fn prove(&self, mut index: usize) -> Result<Option<Vec<Block<S::Params>>>> {
+        let mut blocks = vec![];
+        let mut cid = self.root;
+        loop {
+            let block = Store::get(&**self.cache, &cid).await?;
+            let node: Node<T> = self.cache.decode(&block)?;
+            blocks.push(block);
+            if node.height() == 0 {
+                match node.data().get(index) {
+                    Some(Data::Spilled(cid)) => blocks.push(Store::get(&**self.cache, cid).await?),
+                    Some(_) => {}
+                    None => return Ok(None),
+                }
+                return Ok(Some(blocks));
+            }
+            match node.child(index) {
+                Some((child, child_index)) => {
+                    cid = *child;
+                    index = child_index;
+                }
+                None => return Ok(None),
+            }
+        }
+    }
+
+    pub async fn walk<V: Visitor<T>>(&self, visitor: &mut V) -> Result<()> {
+        visit::walk(
+            &self.cache,
+            &self.root,
+            self.concurrency,
+            visitor,
+            Node::items,
+        )
+        .await
+    }
+
+    // describes the shape of the tree, for tuning the widths and the cache
+    pub async fn stats(&self) -> Result<Stats> {
+        let max = <S::Params as StoreParams>::MAX_BLOCK_SIZE;
+        let mut leaves = 0;
+        let mut fill = 0.0;
+        let mut stats = stats::collect(
+            &self.cache,
+            &self.root,
+            self.concurrency,
+            Node::items,
+            |_, node: &Node<T>, size| {
+                if node.height == 0 {
+                    leaves += 1;
+                    fill += match node.width() {
+                        0 => size as f64 / max as f64,
+                        width => node.data.len() as f64 / width as f64,
+                    };
+                }
+            },
+        )
+        .await?;
+        if leaves > 0 {
+            stats.leaf_fill = fill / leaves as f64;
+        }
+        Ok(stats)
+    }
+
+    // walks every block of the list and checks the invariants the other
+    // methods rely on
+    pub async fn verify(&self) -> Report {
+        let mut verifier = Verifier {
+            codec: self.cache.codec(),
+            report: Report::default(),
+            expected: vec![Some((None, None, true))],
+            current: None,
+            _marker: PhantomData,
+        };
+        // the verifier turns errors into problems, the walk doesn't fail
+        let walk = visit::traverse(
+            &self.cache,
+            &self.root,
+            self.concurrency,
+            &mut verifier,
+            Node::items,
+        );
+        walk.await.ok();
+        verifier.report
+    }
+
+    // the indices whose values differ from other's, with the value here and
+    // the one in other. subtrees both lists share are skipped without loading
+    // them
+    pub async fn diff(&self, other: &Self) -> Result<Vec<(usize, Option<T>, Option<T>)>>
+    where
+        T: PartialEq,
+    {
+        let mut changes = vec![];
+        // what's left of each list, with the number of values of each item.
+        // both start at index
+        let mut left = vec![(Data::Link(self.root), self.len().await? as u64)];
+        let mut right = vec![(Data::Link(other.root), other.len().await? as u64)];
+        let mut index = 0;
+        loop {
+            match (left.last(), right.last()) {
+                (None, None) => return Ok(changes),
+                (Some((Data::Link(a), len)), Some((Data::Link(b), _))) if a == b => {
+                    index += *len as usize;
+                    left.pop();
+                    right.pop();
+                }
+                (Some((Data::Spilled(a), _)), Some((Data::Spilled(b), _))) if a == b => {
+                    index += 1;
+                    left.pop();
+                    right.pop();
+                }
+                // the larger subtree is split up until both sides start with
+                // the same subtree or with a value
+                (Some((Data::Link(_), a)), Some((Data::Link(_), b))) if a < b => {
+                    expand(&other.cache, &other.tmp, &mut right).await?
+                }
+                (Some((Data::Link(_), _)), _) => expand(&self.cache, &self.tmp, &mut left).await?,
+                (_, Some((Data::Link(_), _))) => {
+                    expand(&other.cache, &other.tmp, &mut right).await?
+                }
+                (a, b) => {
+                    let a = match a {
+                        Some((data, _)) => data.load(&self.cache).await?,
+                        None => None,
+                    };
+                    let b = match b {
+                        Some((data, _)) => data.load(&other.cache).await?,
+                        None => None,
+                    };
+                    left.pop();
+                    right.pop();
+                    if a != b {
+                        changes.push((index, a, b));
+                    }
+                    index += 1;
+                }
+            }
+        }
+    }
+
+    pub fn iter(&self) -> ListIter<'_, S, T, C> {
+        self.iter_from(0)
+    }
+
+    // iterates over the values from index start on, the subtrees before it
+    // aren't fetched
+    pub fn iter_from(&self, start: usize) -> ListIter<'_, S, T, C> {
+        ListIter {
+            list: self,
+            nodes: Traversal::new(self.concurrency, self.root, start),
+            values: vec![],
+        }
+    }
+
+    pub async fn snapshot(&self) -> Result<ListSnapshot<S, T, C>> {
+        let tmp = self.cache.temp_pin().await?;
+        // reinserting the root pins it and everything it references under
+        // the snapshot's own temp pin
         let root = self.cache.get(&self.root, Some(&self.tmp)).await?;
+        self.cache.insert(root, Some(&tmp)).await?;
+        Ok(ListSnapshot {
+            cache: self.cache.clone(),
+            root: self.root,
+            tmp,
+        })
+    }
+
+    pub async fn flush<A: AsRef<[u8]> + Send + Sync>(&mut self, alias: A) -> Result<()> {
+        self.cache.alias(alias, Some(self.root())).await?;
+        self.tmp = self.cache.temp_pin().await?;
+        self.cache.flush().await?;
+        Ok(())
+    }
+}
+
+pub struct ListTransaction<'a, S: Store, T: DagCbor, C = DagCborCodec> {
+    list: &'a mut List<S, T, C>,
+    root: Cid,
+    buffer: Buffer<S, C, Node<T>>,
+}
+
+impl<'a, S, T, C> ListTransaction<'a, S, T, C>
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+    T: DagCbor + Clone + Send + Sync,
+{
+    pub fn root(&self) -> &Cid {
+        &self.root
+    }
+
+    async fn node(&self, cid: &Cid) -> Result<Node<T>> {
+        if let Some(node) = self.buffer.get(cid) {
+            return Ok(node.clone());
+        }
+        self.list.cache.get(cid, Some(&self.list.tmp)).await
+    }
+
+    pub async fn push(&mut self, value: T) -> Result<()> {
+        let mut value = Data::Value(value);
+        let root = self.node(&self.root).await?;
         let height = root.height();
-        let width = root.width();
+        let leaf_width = root.leaf_width();
+        let branch_width = root.branch_width();
+        let split_by_size = root.lens.is_some();
+        let max = <S::Params as StoreParams>::MAX_BLOCK_SIZE - LEAF_OVERHEAD;
+        let size = match split_by_size {
+            true => self.list.cache.codec().encode_node(&value)?.len(),
+            false => 0,
+        };
+        // number of values below the nodes created for the new value
+        let lens = |height| match height {
+            _ if !split_by_size => None,
+            0 => Some(vec![]),
+            _ => Some(vec![1]),
+        };
 
         let chain = {
             let mut height = root.height();
@@ -150,190 +713,645 @@ where
                     .expect("at least one link")
                     .cid()
                     .expect("height > 0, payload must be a cid");
-                let node = self.cache.get(cid, Some(&self.tmp)).await?;
+                let node = self.node(cid).await?;
                 height = node.height();
                 chain.push(node);
             }
             chain
         };
+        let len = chain[0].len();
 
+        // the header moves to the new root if the list grows a level
+        let mut old_root = self.root;
+        let mut header = None;
         let mut mutated = false;
-        let mut last = self
-            .cache
-            .insert(Node::new(width as u32, height, vec![]), Some(&self.tmp))
-            .await?;
+        let mut last = None;
         for mut node in chain.into_iter().rev() {
             if mutated {
+                if let Some(len) = node.lens.as_mut().and_then(|lens| lens.last_mut()) {
+                    *len += 1;
+                }
                 let data = node.data_mut();
                 data.pop();
                 data.push(value);
-                last = self.cache.insert(node, Some(&self.tmp)).await?;
-                value = Data::Link(last);
+                let cid = self.buffer.insert_spilling(node)?;
+                value = Data::Link(cid);
+                last = Some(cid);
+            } else if node.has_room(size, max) {
+                let height = node.height();
+                if let Some(lens) = node.lens.as_mut().filter(|_| height > 0) {
+                    lens.push(1);
+                }
+                node.grow(size as u64);
+                node.data_mut().push(value);
+                let cid = self.buffer.insert_spilling(node)?;
+                value = Data::Link(cid);
+                last = Some(cid);
+                mutated = true;
             } else {
-                let data = node.data_mut();
-                if data.len() < width {
-                    data.push(value);
-                    last = self.cache.insert(node, Some(&self.tmp)).await?;
-                    value = Data::Link(last);
-                    mutated = true;
-                } else {
-                    let node = Node::new(width as u32, node.height(), vec![value]);
-                    last = self.cache.insert(node, Some(&self.tmp)).await?;
-                    value = Data::Link(last);
-                    mutated = false;
+                let height = node.height();
+                if node.header.is_some() {
+                    header = node.header.take();
+                    old_root = self.buffer.insert_spilling(node)?;
                 }
+                let mut node = Node::new(leaf_width, branch_width, height, vec![], lens(height));
+                node.grow(size as u64);
+                node.data_mut().push(value);
+                let cid = self.buffer.insert_spilling(node)?;
+                value = Data::Link(cid);
+                last = Some(cid);
+                mutated = false;
             }
         }
 
         if !mutated {
-            let children = vec![Data::Link(*self.root()), value];
-            let node = Node::new(width as u32, height + 1, children);
-            last = self.cache.insert(node, Some(&self.tmp)).await?;
+            let children = vec![Data::Link(old_root), value];
+            let lens = split_by_size.then(|| vec![len, 1]);
+            let mut node = Node::new(leaf_width, branch_width, height + 1, children, lens);
+            node.header = header;
+            last = Some(self.buffer.insert_spilling(node)?);
         }
 
-        self.root = last;
+        // the chain always holds at least the root
+        self.root = last.expect("at least one node");
 
         Ok(())
     }
 
-    pub async fn pop(&mut self) -> Result<Option<T>> {
-        // TODO
-        Ok(None)
+    pub async fn commit(self) -> Result<()> {
+        let Self { list, root, buffer } = self;
+        buffer.commit(&list.cache, &list.tmp, &root).await?;
+        list.root = root;
+        Ok(())
     }
 
-    pub async fn get(&mut self, mut index: usize) -> Result<Option<T>> {
-        let node = self.cache.get(&self.root, Some(&self.tmp)).await?;
-        let mut node_ref = &node;
-        let width = node.width();
-        let mut height = node.height();
-        let mut node;
+    pub fn rollback(self) {}
+}
 
-        if index > width.pow(height + 1) {
-            return Ok(None);
-        }
+// checks the nodes of a list while it's walked
+struct Verifier<T, C> {
+    codec: C,
+    report: Report,
+    // the height and length the parent expects of each child that's still
+    // to be entered, and whether it's on the rightmost path. None for the
+    // links that are misplaced in a leaf
+    expected: Vec<Option<Expected>>,
+    // what's expected of the node that was entered last
+    current: Option<Expected>,
+    _marker: PhantomData<T>,
+}
 
-        loop {
-            let data_index = index / width.pow(height);
-            if let Some(data) = node_ref.data().get(data_index) {
-                if height == 0 {
-                    return Ok(Some(data.value().unwrap().clone()));
-                }
-                let cid = data.cid().unwrap();
-                node = self.cache.get(cid, Some(&self.tmp)).await?;
-                node_ref = &node;
-                index %= width.pow(height);
-                height = node.height();
-            } else {
-                return Ok(None);
-            }
-        }
+type Expected = (Option<u32>, Option<u64>, bool);
+
+impl<T, C, P> Walker<Node<T>, T, P> for Verifier<T, C>
+where
+    T: DagCbor,
+    C: NodeCodec,
+    P: StoreParams,
+{
+    fn prefetch(&self) -> bool {
+        true
     }
 
-    pub async fn set(&mut self, _index: usize, _value: T) -> Result<()> {
-        // TODO
-        Ok(())
+    fn enter(&mut self, _cid: &Cid, _depth: usize) -> bool {
+        self.current = self.expected.pop().flatten();
+        self.current.is_some()
     }
 
-    pub async fn len(&mut self) -> Result<usize> {
-        let root = self.cache.get(&self.root, Some(&self.tmp)).await?;
-        let width = root.width();
-        let mut height = root.height();
-        let mut size = width.pow(height + 1);
-        let mut node = root;
-        loop {
-            let data = node.data();
-            size -= width.pow(height) * (width - data.len());
-            if height == 0 {
-                return Ok(size);
+    fn node(&mut self, block: &Block<P>, node: &Node<T>, _depth: usize) -> Result<bool> {
+        let (height, len, rightmost) = self.current.take().expect("entered");
+        let report = &mut self.report;
+        let cid = *block.cid();
+        report.blocks += 1;
+        if let Some(expected) = height {
+            if node.height != expected {
+                report.problems.push(Problem::Height {
+                    cid,
+                    expected,
+                    found: node.height,
+                });
+                return Ok(false);
             }
-            let cid = data.last().unwrap().cid().unwrap();
-            node = self.cache.get(cid, Some(&self.tmp)).await?;
-            height = node.height();
         }
+        if let Some(expected) = len {
+            if node.len() != expected {
+                report.problems.push(Problem::Length {
+                    cid,
+                    expected,
+                    found: node.len(),
+                });
+            }
+        }
+        // leaves split by size have no width
+        let width = node.width();
+        if width > 0 && (node.data.len() > width || !rightmost && node.data.len() < width) {
+            report.problems.push(Problem::Width {
+                cid,
+                len: node.data.len(),
+                width,
+            });
+        }
+        let last = node.data.len().saturating_sub(1);
+        let mut children = vec![];
+        for (i, data) in node.data.iter().enumerate() {
+            match (data, node.height) {
+                (Data::Link(_), height) if height > 0 => {
+                    let len = node.lens.as_ref().and_then(|lens| lens.get(i).copied());
+                    children.push(Some((Some(height - 1), len, rightmost && i == last)));
+                }
+                (Data::Link(_), _) => {
+                    report.problems.push(Problem::Misplaced(cid));
+                    children.push(None);
+                }
+                (_, 0) => {}
+                _ => report.problems.push(Problem::Misplaced(cid)),
+            }
+        }
+        self.expected.extend(children.into_iter().rev());
+        Ok(true)
     }
 
-    pub async fn is_empty(&mut self) -> Result<bool> {
-        let root = self.cache.get(&self.root, Some(&self.tmp)).await?;
-        Ok(root.data().is_empty())
-    }
-
-    pub fn iter(&mut self) -> ListIter<'_, S, T> {
-        ListIter {
-            list: self,
-            index: 0,
+    fn spilled(&mut self, _key: Option<&[u8]>, block: &Block<P>) -> Result<()> {
+        match self.codec.decode_node::<T>(block.data()) {
+            Ok(_) => self.report.blocks += 1,
+            Err(err) => {
+                let problem = Problem::Unreadable(*block.cid(), err.to_string());
+                self.report.problems.push(problem);
+            }
         }
+        Ok(())
     }
 
-    pub async fn flush<A: AsRef<[u8]> + Send + Sync>(&mut self, alias: A) -> Result<()> {
-        self.cache.alias(alias, Some(self.root())).await?;
-        self.tmp = self.cache.temp_pin().await?;
-        self.cache.flush().await?;
+    fn unreadable(&mut self, cid: &Cid, err: Error) -> Result<()> {
+        let problem = Problem::Unreadable(*cid, err.to_string());
+        self.report.problems.push(problem);
         Ok(())
     }
 }
 
-pub struct ListIter<'a, S: Store, T: DagCbor> {
-    list: &'a mut List<S, T>,
-    index: usize,
+pub struct ListSnapshot<S: Store, T: DagCbor, C = DagCborCodec> {
+    cache: Arc<NodeCache<S, C, Node<T>>>,
+    root: Cid,
+    tmp: S::TempPin,
 }
 
-impl<'a, S, T: DagCbor> ListIter<'a, S, T>
-where
-    S: Store,
-    <S::Params as StoreParams>::Codecs: Into<DagCborCodec>,
-    DagCborCodec: Into<<S::Params as StoreParams>::Codecs>,
-    Ipld: References<<S::Params as StoreParams>::Codecs>,
-    T: Decode<DagCborCodec> + Encode<DagCborCodec> + Clone + Send + Sync,
-{
-    #[allow(clippy::should_implement_trait)]
-    pub async fn next(&mut self) -> Result<Option<T>> {
-        let elem = self.list.get(self.index).await?;
-        self.index += 1;
-        Ok(elem)
+// the clones share the temp pin, the blocks stay pinned until the last one is
+// dropped
+impl<S: Store, T: DagCbor, C> Clone for ListSnapshot<S, T, C> {
+    fn clone(&self) -> Self {
+        Self {
+            cache: self.cache.clone(),
+            root: self.root,
+            tmp: self.tmp.clone(),
+        }
     }
 }
 
-#[derive(Clone, Debug, DagCbor)]
-struct Node<T: DagCbor> {
-    width: u32,
-    height: u32,
-    data: Vec<Data<T>>,
-}
+impl<S, T, C> ListSnapshot<S, T, C>
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+    T: DagCbor + Clone + Send + Sync,
+{
+    pub fn root(&self) -> &Cid {
+        &self.root
+    }
 
-impl<T: DagCbor> Node<T> {
-    fn new(width: u32, height: u32, data: Vec<Data<T>>) -> Self {
-        Node {
-            width,
-            height,
-            data,
-        }
+    pub async fn get(&self, index: usize) -> Result<Option<T>> {
+        get(&self.cache, &self.tmp, &self.root, index).await
     }
 
-    fn width(&self) -> usize {
-        self.width as usize
+    pub async fn len(&self) -> Result<usize> {
+        len(&self.cache, &self.tmp, &self.root).await
     }
 
-    fn height(&self) -> u32 {
-        self.height
+    pub async fn is_empty(&self) -> Result<bool> {
+        is_empty(&self.cache, &self.tmp, &self.root).await
     }
+}
 
-    fn data(&self) -> &[Data<T>] {
-        &self.data
+async fn get<S, T, C>(
+    cache: &NodeCache<S, C, Node<T>>,
+    tmp: &S::TempPin,
+    root: &Cid,
+    mut index: usize,
+) -> Result<Option<T>>
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+    T: DagCbor + Clone + Send + Sync,
+{
+    let node = cache.get(root, Some(tmp)).await?;
+    let mut node_ref = &node;
+    let mut node;
+
+    if node_ref.lens.is_none() && index >= node_ref.capacity() {
+        return Ok(None);
     }
 
-    fn data_mut(&mut self) -> &mut Vec<Data<T>> {
-        &mut self.data
+    loop {
+        if node_ref.height() == 0 {
+            return match node_ref.data().get(index) {
+                Some(data) => data.load(cache).await,
+                None => Ok(None),
+            };
+        }
+        let cid = match node_ref.child(index) {
+            Some((cid, child_index)) => {
+                index = child_index;
+                cid
+            }
+            None => return Ok(None),
+        };
+        node = cache.get(cid, Some(tmp)).await?;
+        node_ref = &node;
     }
 }
 
-#[derive(Clone, Debug, DagCbor)]
-enum Data<T: DagCbor> {
-    Value(T),
+// checks a proof made by List::prove against a trusted root and returns the
+// value at index if it holds. the nodes have to keep the root's widths and
+// their heights have to go down by one per level
+pub fn verify_list_proof<P, T>(root: &Cid, mut index: usize, proof: &[Block<P>]) -> Option<T>
+where
+    P: StoreParams,
+    T: DagCbor,
+{
+    let mut blocks = proof.iter();
+    let root_node: Node<T> = next_block(&mut blocks, root)?;
+    let (leaf_width, branch_width) = (root_node.leaf_width(), root_node.branch_width());
+    if root_node.lens.is_none() && index >= root_node.capacity() {
+        return None;
+    }
+    let mut node = root_node;
+    loop {
+        let width = node.width();
+        if node.leaf_width() != leaf_width
+            || node.branch_width() != branch_width
+            || width > 0 && node.data.len() > width
+        {
+            return None;
+        }
+        if node.height == 0 {
+            let value = match node.data.into_iter().nth(index)? {
+                Data::Value(value) => value,
+                Data::Spilled(cid) => next_block(&mut blocks, &cid)?,
+                Data::Link(_) => return None,
+            };
+            return if blocks.next().is_none() {
+                Some(value)
+            } else {
+                None
+            };
+        }
+        let (child, child_index) = node.child(index)?;
+        let child = *child;
+        index = child_index;
+        let child_node: Node<T> = next_block(&mut blocks, &child)?;
+        if child_node.height + 1 != node.height {
+            return None;
+        }
+        node = child_node;
+    }
+}
+
+async fn len<S, T, C>(
+    cache: &NodeCache<S, C, Node<T>>,
+    tmp: &S::TempPin,
+    root: &Cid,
+) -> Result<usize>
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+    T: DagCbor + Clone + Send + Sync,
+{
+    let root = cache.get(root, Some(tmp)).await?;
+    if root.lens.is_some() {
+        return Ok(root.len() as usize);
+    }
+    let mut size = root.capacity();
+    let mut node = root;
+    loop {
+        let data = node.data();
+        size -= node.child_capacity() * (node.width() - data.len());
+        if node.height() == 0 {
+            return Ok(size);
+        }
+        let cid = data.last().unwrap().cid().unwrap();
+        node = cache.get(cid, Some(tmp)).await?;
+    }
+}
+
+// replaces the link on top of stack by the children of its node, each with
+// the number of values below it
+async fn expand<S, T, C>(
+    cache: &NodeCache<S, C, Node<T>>,
+    tmp: &S::TempPin,
+    stack: &mut Vec<(Data<T>, u64)>,
+) -> Result<()>
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+    T: DagCbor + Clone + Send + Sync,
+{
+    let (data, len) = stack.pop().expect("checked by the caller");
+    let cid = data.cid().expect("checked by the caller");
+    let node = cache.get(cid, Some(tmp)).await?;
+    let capacity = node.child_capacity() as u64;
+    let count = node.data.len();
+    let mut children = Vec::with_capacity(count);
+    for (i, data) in node.data.into_iter().enumerate() {
+        let len = match (&node.lens, node.height) {
+            (_, 0) => 1,
+            (Some(lens), _) => lens.get(i).copied().unwrap_or_default(),
+            // only the last child of a list with fixed widths isn't full
+            (None, _) if i + 1 < count => capacity,
+            (None, _) => len - capacity * (count as u64 - 1),
+        };
+        children.push((data, len));
+    }
+    stack.extend(children.into_iter().rev());
+    Ok(())
+}
+
+async fn is_empty<S, T, C>(
+    cache: &NodeCache<S, C, Node<T>>,
+    tmp: &S::TempPin,
+    root: &Cid,
+) -> Result<bool>
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+    T: DagCbor + Clone + Send + Sync,
+{
+    let root = cache.get(root, Some(tmp)).await?;
+    Ok(root.data().is_empty())
+}
+
+// walks the leaves in order, keeping the values of the current one. each
+// node comes with the number of its values to skip
+pub struct ListIter<'a, S: Store, T: DagCbor, C = DagCborCodec> {
+    list: &'a List<S, T, C>,
+    nodes: Traversal<S::Params, usize>,
+    values: Vec<T>,
+}
+
+impl<'a, S, T, C> ListIter<'a, S, T, C>
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+    T: DagCbor + Clone + Send + Sync,
+{
+    #[allow(clippy::should_implement_trait)]
+    pub async fn next(&mut self) -> Result<Option<T>> {
+        let list = self.list;
+        let cache = &list.cache;
+        while self.values.is_empty() {
+            let (skip, mut node) = match cache.next(&mut self.nodes).await {
+                Some((_, skip, node)) => (skip, node?),
+                None => return Ok(None),
+            };
+            if node.height == 0 {
+                node.data.drain(..skip.min(node.data.len()));
+            }
+            // children that end before the first value are left out
+            let mut children = vec![];
+            let mut offset = 0;
+            for (i, data) in node.data.iter().enumerate() {
+                let len = node.child_len(i);
+                if let Data::Link(cid) = data {
+                    if skip < offset + len {
+                        children.push((*cid, skip.saturating_sub(offset)));
+                    }
+                }
+                offset += len;
+            }
+            for (cid, skip) in children.into_iter().rev() {
+                self.nodes.push(cid, skip);
+            }
+            let spilled = node.spilled();
+            let mut loaded = vec![];
+            for value in cache.load_many(&spilled, list.concurrency).await {
+                loaded.push(value?);
+            }
+            let mut loaded = loaded.into_iter();
+            for data in node.data.into_iter().rev() {
+                match data {
+                    Data::Value(value) => self.values.push(value),
+                    Data::Spilled(_) => self.values.push(loaded.next_back().expect("loaded")),
+                    Data::Link(_) => {}
+                }
+            }
+        }
+        Ok(self.values.pop())
+    }
+}
+
+#[derive(Clone, Debug, DagCbor)]
+struct Node<T: DagCbor> {
+    // the width of the leaves, and of the branches unless they have their own
+    width: u32,
+    height: u32,
+    data: Vec<Data<T>>,
+    // the fields below are left out while they're unset, so nodes of lists
+    // that don't use them encode the way they always did
+    #[ipld(default = None)]
+    branch_width: Option<u32>,
+    // number of values below each link, only kept when leaves are split by
+    // size. leaves have an empty list then
+    #[ipld(default = None)]
+    lens: Option<Vec<u64>>,
+    // encoded size of the values of a leaf split by size, kept up to date as
+    // values are pushed so they don't have to be encoded again. only leaves
+    // have one
+    #[ipld(default = None)]
+    size: Option<u64>,
+    // only the root has one
+    #[ipld(default = None)]
+    header: Option<Header>,
+}
+
+impl<T: DagCbor> Links for Node<T> {
+    fn links(&self) -> Vec<Cid> {
+        self.data.iter().filter_map(Data::cid).copied().collect()
+    }
+
+    fn spilled(&self) -> Vec<Cid> {
+        self.data
+            .iter()
+            .filter_map(|data| match data {
+                Data::Spilled(cid) => Some(*cid),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+impl<P, T> Spill<P> for Node<T>
+where
+    P: StoreParams,
+    T: DagCbor,
+{
+    fn spill<C>(&mut self, codec: C, hash: P::Hashes, excess: usize) -> Result<Vec<Block<P>>>
+    where
+        C: NodeCodec,
+    {
+        spill(&mut self.data, codec, hash, excess)
+    }
+}
+
+impl<T: DagCbor> Node<T> {
+    fn new(
+        leaf_width: u32,
+        branch_width: u32,
+        height: u32,
+        data: Vec<Data<T>>,
+        lens: Option<Vec<u64>>,
+    ) -> Self {
+        let size = (height == 0 && lens.is_some()).then_some(0);
+        Node {
+            width: leaf_width,
+            height,
+            data,
+            branch_width: (branch_width != leaf_width).then_some(branch_width),
+            lens,
+            size,
+            header: None,
+        }
+    }
+
+    // number of values below the node, interior nodes only know it when
+    // leaves are split by size
+    fn len(&self) -> u64 {
+        match &self.lens {
+            Some(lens) if self.height > 0 => lens.iter().sum(),
+            _ => self.data.len() as u64,
+        }
+    }
+
+    // leaves split by size are full once a value of size would push their
+    // encoding past max, or once a value had to be spilled out of them
+    fn has_room(&self, size: usize, max: usize) -> bool {
+        let leaf_size = match self.size {
+            Some(leaf_size) => leaf_size as usize,
+            None => return self.data.len() < self.width(),
+        };
+        if self.data.is_empty() {
+            return true;
+        }
+        if self
+            .data
+            .iter()
+            .any(|data| matches!(data, Data::Spilled(_)))
+        {
+            return false;
+        }
+        leaf_size + size <= max
+    }
+
+    // what walk and the other traversals make of the node
+    fn items(self) -> Vec<Item<T>> {
+        self.data
+            .into_iter()
+            .map(|data| match data {
+                Data::Value(value) => Item::Value(None, value),
+                Data::Link(cid) => Item::Link(cid),
+                Data::Spilled(cid) => Item::Spilled(None, cid),
+            })
+            .collect()
+    }
+
+    // accounts for a value of size pushed onto a leaf split by size
+    fn grow(&mut self, size: u64) {
+        if let Some(leaf_size) = self.size.as_mut() {
+            *leaf_size += size;
+        }
+    }
+
+    // the link to the child holding index, and the index within that child
+    fn child(&self, mut index: usize) -> Option<(&Cid, usize)> {
+        let child = if let Some(lens) = self.lens.as_ref() {
+            // leaves have a variable number of values, so the lens are
+            // needed to find the child holding index
+            let mut child = lens.len();
+            for (i, len) in lens.iter().enumerate() {
+                if (index as u64) < *len {
+                    child = i;
+                    break;
+                }
+                index -= *len as usize;
+            }
+            child
+        } else {
+            let capacity = self.child_capacity();
+            let child = index / capacity;
+            index %= capacity;
+            child
+        };
+        let cid = self.data.get(child)?.cid()?;
+        Some((cid, index))
+    }
+
+    fn leaf_width(&self) -> u32 {
+        self.width
+    }
+
+    fn branch_width(&self) -> u32 {
+        self.branch_width.unwrap_or(self.width)
+    }
+
+    fn width(&self) -> usize {
+        if self.height == 0 {
+            self.leaf_width() as usize
+        } else {
+            self.branch_width() as usize
+        }
+    }
+
+    // number of values below the child at i
+    fn child_len(&self, i: usize) -> usize {
+        match (&self.lens, self.height) {
+            (_, 0) => 1,
+            (Some(lens), _) => lens.get(i).copied().unwrap_or_default() as usize,
+            (None, _) => self.child_capacity(),
+        }
+    }
+
+    // number of values each child can hold
+    fn child_capacity(&self) -> usize {
+        match self.height {
+            0 => 1,
+            height => self.leaf_width() as usize * (self.branch_width() as usize).pow(height - 1),
+        }
+    }
+
+    // number of values the node can hold
+    fn capacity(&self) -> usize {
+        self.child_capacity() * self.width()
+    }
+
+    fn height(&self) -> u32 {
+        self.height
+    }
+
+    fn data(&self) -> &[Data<T>] {
+        &self.data
+    }
+
+    fn data_mut(&mut self) -> &mut Vec<Data<T>> {
+        &mut self.data
+    }
+}
+
+#[derive(Clone, Debug, DagCbor)]
+pub(crate) enum Data<T: DagCbor> {
+    Value(T),
     Link(Cid),
+    // a value too large to be stored inline
+    Spilled(Cid),
 }
 
 impl<T: DagCbor> Data<T> {
-    fn value(&self) -> Option<&T> {
+    pub fn value(&self) -> Option<&T> {
         if let Self::Value(value) = self {
             Some(value)
         } else {
@@ -341,19 +1359,75 @@ impl<T: DagCbor> Data<T> {
         }
     }
 
-    fn cid(&self) -> Option<&Cid> {
+    pub fn cid(&self) -> Option<&Cid> {
         if let Self::Link(cid) = self {
             Some(cid)
         } else {
             None
         }
     }
+
+    // like value, but loads spilled values from the store
+    pub async fn load<S, C, N>(&self, cache: &NodeCache<S, C, N>) -> Result<Option<T>>
+    where
+        S: CollectionStore<C>,
+        C: NodeCodec,
+        N: DagCbor + Clone + Send + Sync,
+        T: Clone,
+    {
+        match self {
+            Self::Value(value) => Ok(Some(value.clone())),
+            Self::Spilled(cid) => Ok(Some(cache.load(cid).await?)),
+            Self::Link(_) => Ok(None),
+        }
+    }
+}
+
+// spills the largest values of a leaf until it shrank by excess bytes. values
+// whose encoding isn't larger than a link stay inline
+pub(crate) fn spill<P, C, T>(
+    data: &mut [Data<T>],
+    codec: C,
+    hash: P::Hashes,
+    excess: usize,
+) -> Result<Vec<Block<P>>>
+where
+    P: StoreParams,
+    C: NodeCodec,
+    T: DagCbor,
+{
+    let mut sizes = vec![];
+    for (i, data) in data.iter().enumerate() {
+        if let Data::Value(value) = data {
+            sizes.push((codec.encode_node(value)?.len(), i));
+        }
+    }
+    sizes.sort_unstable_by(|a, b| b.cmp(a));
+    let mut blocks = vec![];
+    let mut saved = 0;
+    for (size, i) in sizes {
+        if saved >= excess {
+            break;
+        }
+        let block = encode(codec, hash, data[i].value().expect("inline value"))?;
+        let link = codec.encode_node(block.cid())?.len();
+        if size <= link {
+            break;
+        }
+        saved += size - link;
+        data[i] = Data::Spilled(*block.cid());
+        blocks.push(block);
+    }
+    Ok(blocks)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cache::tests::RemoteStore;
+    use crate::header::UnsupportedVersion;
     use async_std::task;
+    use libipld::codec::Codec;
     use libipld::mem::MemStore;
     use libipld::multihash::Code;
     use libipld::store::DefaultParams;
@@ -390,7 +1464,7 @@ mod tests {
         let mut config = ListConfig::new(store, Code::Blake2b256);
         config.set_width(3);
         let data: Vec<_> = (0..13).map(|i| i as i64).collect();
-        let mut list = List::from(config, data.clone().into_iter()).await?;
+        let list = List::from(config, data.clone().into_iter()).await?;
         let mut data2 = vec![];
         let mut iter = list.iter();
         while let Some(elem) = iter.next().await? {
@@ -434,6 +1508,90 @@ mod tests {
         }
     }
 
+    #[async_std::test]
+    async fn test_list_snapshot() -> Result<()> {
+        let store = MemStore::<DefaultParams>::default();
+        let mut config = ListConfig::new(store, Code::Blake2b256);
+        config.set_width(3);
+        let mut list = List::from(config, (0..5).map(|i| i as i64)).await?;
+        let snapshot = list.snapshot().await?;
+        for i in 5..13 {
+            list.push(i as i64).await?;
+        }
+        assert_ne!(snapshot.root(), list.root());
+        assert_eq!(snapshot.len().await?, 5);
+        assert_eq!(list.len().await?, 13);
+        for i in 0..5 {
+            assert_eq!(snapshot.get(i).await?, Some(i as i64));
+        }
+        assert_eq!(snapshot.get(5).await?, None);
+
+        let clone = snapshot.clone();
+        drop(snapshot);
+        assert_eq!(clone.len().await?, 5);
+        assert_eq!(clone.get(4).await?, Some(4));
+        assert_eq!(clone.get(5).await?, None);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_list_transaction() -> Result<()> {
+        let store = MemStore::<DefaultParams>::default();
+        let mut config = ListConfig::new(store.clone(), Code::Blake2b256);
+        config.set_width(3);
+        let mut list = List::from(config, (0..4).map(|i| i as i64)).await?;
+        let root = *list.root();
+
+        let mut tx = list.transaction();
+        for i in 4..13 {
+            tx.push(i as i64).await?;
+        }
+        let uncommitted = *tx.root();
+        tx.rollback();
+        assert_eq!(list.root(), &root);
+        assert_eq!(list.len().await?, 4);
+        assert!(!store.contains(&uncommitted).await?);
+
+        let mut tx = list.transaction();
+        for i in 4..13 {
+            tx.push(i as i64).await?;
+        }
+        tx.commit().await?;
+        assert_eq!(list.root(), &uncommitted);
+        assert_eq!(list.len().await?, 13);
+        for i in 0..13 {
+            assert_eq!(list.get(i).await?, Some(i as i64));
+        }
+        Ok(())
+    }
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[async_std::test]
+    async fn test_list_concurrent_readers() -> Result<()> {
+        assert_send_sync::<List<MemStore<DefaultParams>, i64>>();
+        let store = MemStore::<DefaultParams>::default();
+        let mut config = ListConfig::new(store, Code::Blake2b256);
+        config.set_width(3);
+        let list = Arc::new(List::from(config, (0..64).map(|i| i as i64)).await?);
+        let readers: Vec<_> = (0..16)
+            .map(|reader| {
+                let list = list.clone();
+                task::spawn(async move {
+                    for i in 0..64 {
+                        let index = (i + reader * 4) % 64;
+                        assert_eq!(list.get(index).await.unwrap(), Some(index as i64));
+                    }
+                    assert_eq!(list.len().await.unwrap(), 64);
+                })
+            })
+            .collect();
+        for reader in readers {
+            reader.await;
+        }
+        Ok(())
+    }
+
     #[async_std::test]
     async fn test_width() -> Result<()> {
         let store = MemStore::<DefaultParams>::default();
@@ -442,4 +1600,692 @@ mod tests {
         let _list = List::from(config, (0..n).map(|n| n as u64)).await?;
         Ok(())
     }
+
+    #[test]
+    fn test_list_node_fields() -> Result<()> {
+        let keys = |node: &Node<u64>| -> Result<Vec<String>> {
+            match DagCborCodec.decode(&DagCborCodec.encode(node)?)? {
+                Ipld::StringMap(map) => Ok(map.keys().cloned().collect()),
+                _ => unreachable!(),
+            }
+        };
+        // nodes with the same widths and without lens only have the fields
+        // nodes always had
+        let node = Node::new(4, 4, 0, vec![Data::Value(0)], None);
+        assert_eq!(keys(&node)?, vec!["data", "height", "width"]);
+        let node = Node::new(4, 2, 0, vec![Data::Value(0)], Some(vec![]));
+        assert_eq!(
+            keys(&node)?,
+            vec!["branch_width", "data", "height", "lens", "size", "width"]
+        );
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_list_mixed_widths() -> Result<()> {
+        let store = MemStore::<DefaultParams>::default();
+        let config = || {
+            let mut config = ListConfig::new(store.clone(), Code::Blake2b256);
+            config.set_leaf_width(4);
+            config.set_branch_width(2);
+            config
+        };
+        let mut list = List::new(config()).await?;
+        for i in 0..40 {
+            assert_eq!(list.get(i).await?, None);
+            assert_eq!(list.len().await?, i);
+            list.push(i as i64).await?;
+        }
+        for i in 0..40 {
+            assert_eq!(list.get(i).await?, Some(i as i64));
+        }
+        let root = list.cache.get(list.root(), None).await?;
+        // 10 leaves need a tree of height 4
+        assert_eq!(root.height(), 4);
+        assert_eq!((root.leaf_width(), root.branch_width()), (4, 2));
+
+        let from = List::from(config(), (0..40).map(|i| i as i64)).await?;
+        assert_eq!(from.root(), list.root());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_list_split_by_size() -> Result<()> {
+        let store = MemStore::<DefaultParams>::default();
+        let config = || {
+            let mut config = ListConfig::new(store.clone(), Code::Blake2b256);
+            config.set_width(3);
+            config.set_split_by_size(true);
+            config
+        };
+        let value = |i: usize| vec![i as u8; (i % 7 + 1) * 50_000].into_boxed_slice();
+        let mut list = List::new(config()).await?;
+        for i in 0..60 {
+            assert_eq!(list.len().await?, i);
+            list.push(value(i)).await?;
+        }
+        for i in 0..60 {
+            assert_eq!(list.get(i).await?, Some(value(i)));
+        }
+        assert_eq!(list.get(60).await?, None);
+        assert_eq!(list.len().await?, 60);
+
+        // leaves are filled up to the block size, so they end up with
+        // different numbers of values and none of them are spilled
+        let mut widths = std::collections::BTreeSet::new();
+        let mut stack = vec![*list.root()];
+        while let Some(cid) = stack.pop() {
+            let node = list.cache.get(&cid, None).await?;
+            if node.height() == 0 {
+                assert!(node.data().iter().all(|data| data.value().is_some()));
+                widths.insert(node.data().len());
+            } else {
+                assert!(node.data().len() <= 3);
+                stack.extend(node.links());
+            }
+        }
+        assert!(widths.len() > 1);
+
+        let from = List::from(config(), (0..60).map(value)).await?;
+        assert_eq!(from.root(), list.root());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_list_spills_large_values() -> Result<()> {
+        let store = MemStore::<DefaultParams>::default();
+        let mut config = ListConfig::new(store.clone(), Code::Blake2b256);
+        config.set_width(3);
+        let value = |i: usize| vec![i as u8; DefaultParams::MAX_BLOCK_SIZE / 2].into_boxed_slice();
+        let mut list = List::new(config).await?;
+        for i in 0..7 {
+            list.push(value(i)).await?;
+        }
+        for i in 0..7 {
+            assert_eq!(list.get(i).await?, Some(value(i)));
+        }
+        let root = list.cache.get(list.root(), None).await?;
+        let leaf = list.cache.get(root.data()[0].cid().unwrap(), None).await?;
+        assert!(leaf.data().iter().any(|data| data.value().is_some()));
+        assert!(leaf
+            .data()
+            .iter()
+            .any(|data| matches!(data, Data::Spilled(_))));
+
+        let mut config = ListConfig::new(store, Code::Blake2b256);
+        config.set_width(3);
+        let from = List::from(config, (0..7).map(value)).await?;
+        assert_eq!(from.root(), list.root());
+
+        // a value that doesn't fit into a block on its own can't be stored
+        let value = vec![0; DefaultParams::MAX_BLOCK_SIZE].into_boxed_slice();
+        assert!(list.push(value).await.is_err());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_list_car() -> Result<()> {
+        let mut config = ListConfig::new(MemStore::<DefaultParams>::default(), Code::Blake2b256);
+        config.set_width(3);
+        let value = |i: usize| vec![i as u8; i * DefaultParams::MAX_BLOCK_SIZE / 16];
+        let list = List::from(config, (0..10).map(value)).await?;
+        let mut car = vec![];
+        list.export_car(&mut car).await?;
+
+        let mut config = ListConfig::new(MemStore::<DefaultParams>::default(), Code::Blake2b256);
+        config.set_width(3);
+        let imported = List::<_, Vec<u8>>::import_car(config, &car[..]).await?;
+        assert_eq!(imported.root(), list.root());
+        for i in 0..10 {
+            assert_eq!(imported.get(i).await?, Some(value(i)));
+        }
+
+        // blocks that don't match their cid are rejected
+        let last = car.len() - 1;
+        car[last] ^= 1;
+        let config = ListConfig::new(MemStore::<DefaultParams>::default(), Code::Blake2b256);
+        assert!(List::<_, Vec<u8>>::import_car(config, &car[..])
+            .await
+            .is_err());
+
+        // the root block is written first, without it nothing is inserted
+        let mut reader = &car[..];
+        let header = car::read_section(&mut reader)?.unwrap();
+        car::read_section(&mut reader)?;
+        let mut missing = vec![];
+        car::write_section(&mut missing, &header)?;
+        missing.extend_from_slice(reader);
+        let child = Cid::read_bytes(&car::read_section(&mut reader)?.unwrap()[..])?;
+        let store = MemStore::<DefaultParams>::default();
+        let config = ListConfig::new(store.clone(), Code::Blake2b256);
+        assert!(List::<_, Vec<u8>>::import_car(config, &missing[..])
+            .await
+            .is_err());
+        assert!(store.get(&child).await.is_err());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_list_verify() -> Result<()> {
+        let store = MemStore::<DefaultParams>::default();
+        let mut config = ListConfig::new(store.clone(), Code::Blake2b256);
+        config.set_width(4);
+        let list = List::from(config, 0..40u64).await?;
+        let report = list.verify().await;
+        assert!(report.is_ok());
+        assert_eq!(report.blocks, 14);
+
+        let header = list.header().await?;
+        let leaf = |n| Node::new(4, 4, 0, (0..n).map(Data::Value).collect(), None);
+        let a = list.cache.insert(leaf(2), None).await?;
+        let b = list.cache.insert(leaf(4), None).await?;
+        let links = vec![Data::Link(a), Data::Link(b)];
+        let mut root = Node::new(4, 4, 1, links, None);
+        root.header = header.clone();
+        let root = list.cache.insert(root, None).await?;
+        let list =
+            List::<_, u64>::open(ListConfig::new(store.clone(), Code::Blake2b256), root).await?;
+        let report = list.verify().await;
+        assert_eq!(
+            report.problems,
+            vec![Problem::Width {
+                cid: a,
+                len: 2,
+                width: 4
+            }]
+        );
+
+        let mut root = Node::new(4, 4, 2, vec![Data::Link(b), Data::Value(0)], None);
+        root.header = header;
+        let root = list.cache.insert(root, None).await?;
+        let list = List::<_, u64>::open(ListConfig::new(store, Code::Blake2b256), root).await?;
+        let report = list.verify().await;
+        assert_eq!(
+            report.problems,
+            vec![
+                Problem::Misplaced(root),
+                Problem::Height {
+                    cid: b,
+                    expected: 1,
+                    found: 0
+                }
+            ]
+        );
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_list_proof() -> Result<()> {
+        let mut config = ListConfig::new(MemStore::<DefaultParams>::default(), Code::Blake2b256);
+        config.set_width(4);
+        let list = List::from(config, 0..40u64).await?;
+        let root = *list.root();
+        for i in 0..40 {
+            let proof = list.prove(i).await?.unwrap();
+            assert_eq!(proof.len(), 3);
+            assert_eq!(verify_list_proof(&root, i, &proof), Some(i as u64));
+        }
+        assert!(list.prove(40).await?.is_none());
+
+        let proof = list.prove(5).await?.unwrap();
+        // the proof covers the whole leaf, but not the others
+        assert_eq!(verify_list_proof(&root, 6, &proof), Some(6u64));
+        assert_eq!(verify_list_proof::<_, u64>(&root, 9, &proof), None);
+        assert_eq!(verify_list_proof::<_, u64>(proof[1].cid(), 5, &proof), None);
+        assert_eq!(verify_list_proof::<_, u64>(&root, 5, &proof[..2]), None);
+        let mut extra = proof.clone();
+        extra.push(proof[0].clone());
+        assert_eq!(verify_list_proof::<_, u64>(&root, 5, &extra), None);
+        let forged = Block::new_unchecked(*proof[2].cid(), proof[2].data()[1..].to_vec());
+        let forged = vec![proof[0].clone(), proof[1].clone(), forged];
+        assert_eq!(verify_list_proof::<_, u64>(&root, 5, &forged), None);
+
+        // spilled values come with their own block
+        let mut config = ListConfig::new(MemStore::<DefaultParams>::default(), Code::Blake2b256);
+        config.set_width(3);
+        let value = |i: usize| vec![i as u8; i * DefaultParams::MAX_BLOCK_SIZE / 16];
+        let list = List::from(config, (0..10).map(value)).await?;
+        let mut spilled = 0;
+        for i in 0..10 {
+            let proof = list.prove(i).await?.unwrap();
+            if proof.len() > 2 {
+                spilled += 1;
+            }
+            assert_eq!(verify_list_proof(list.root(), i, &proof), Some(value(i)));
+        }
+        assert!(spilled > 0);
+
+        // lists split by size are walked through their lens
+        let mut config = ListConfig::new(MemStore::<DefaultParams>::default(), Code::Blake2b256);
+        config.set_split_by_size(true);
+        let list = List::from(config, (0..10).map(value)).await?;
+        for i in 0..10 {
+            let proof = list.prove(i).await?.unwrap();
+            assert_eq!(verify_list_proof(list.root(), i, &proof), Some(value(i)));
+        }
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_list_prefetch() -> Result<()> {
+        let remote = MemStore::<DefaultParams>::default();
+        let mut config = ListConfig::new(remote.clone(), Code::Blake2b256);
+        config.set_width(4);
+        let root = *List::from(config, 0..64u64).await?.root();
+
+        let store = RemoteStore::new(remote);
+        let mut config = ListConfig::new(store.clone(), Code::Blake2b256);
+        config.set_width(4);
+        let list = List::<_, u64>::open(config, root).await?;
+        assert_eq!(store.fetched(), 1);
+        // 20..28 are in the second and third leaf below the second branch
+        let branch = *list.cache.get(&root, None).await?.data()[1].cid().unwrap();
+        assert_eq!(list.missing(20..28).await?, vec![branch]);
+        list.prefetch(20..28).await?;
+        assert_eq!(store.fetched(), 3);
+        assert!(list.missing(20..28).await?.is_empty());
+        assert_eq!(list.missing(16..=20).await?.len(), 1);
+        for i in 20..28 {
+            assert_eq!(list.get(i).await?, Some(i as u64));
+        }
+        assert_eq!(store.fetched(), 0);
+
+        assert_eq!(list.missing(..).await?.len(), 5);
+        list.prefetch(..).await?;
+        assert_eq!(store.fetched(), 17);
+
+        let store = RemoteStore::new(store.remote);
+        let mut config = ListConfig::new(store.clone(), Code::Blake2b256);
+        config.set_width(4);
+        let list = List::<_, u64>::open(config, root).await?;
+        store.forget();
+        assert!(list.prefetch(..).await.is_err());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_list_iter_cached() -> Result<()> {
+        let remote = MemStore::<DefaultParams>::default();
+        let mut config = ListConfig::new(remote.clone(), Code::Blake2b256);
+        config.set_width(4);
+        let root = *List::from(config, 0..64u64).await?.root();
+
+        let store = RemoteStore::new(remote);
+        let mut config = ListConfig::new(store.clone(), Code::Blake2b256);
+        config.set_width(4);
+        let list = List::<_, u64>::open(config, root).await?;
+        let mut iter = list.iter();
+        while iter.next().await?.is_some() {}
+        assert_eq!(store.fetched(), 21);
+        // all of the nodes fit into the cache
+        let mut iter = list.iter();
+        while iter.next().await?.is_some() {}
+        assert_eq!(store.fetched(), 0);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_list_iter_from() -> Result<()> {
+        let remote = MemStore::<DefaultParams>::default();
+        let mut config = ListConfig::new(remote.clone(), Code::Blake2b256);
+        config.set_width(4);
+        let root = *List::from(config, 0..64u64).await?.root();
+
+        let store = RemoteStore::new(remote);
+        let mut config = ListConfig::new(store.clone(), Code::Blake2b256);
+        config.set_width(4);
+        let list = List::<_, u64>::open(config, root).await?;
+        store.fetched();
+        let mut iter = list.iter_from(50);
+        let mut values = vec![];
+        while let Some(value) = iter.next().await? {
+            values.push(value);
+        }
+        assert_eq!(values, (50..64).collect::<Vec<_>>());
+        // the last branch and its four leaves, the root was fetched by open
+        assert_eq!(store.fetched(), 5);
+
+        for start in [0, 3, 4, 17, 63, 64, 100].iter() {
+            let mut iter = list.iter_from(*start);
+            let mut values = vec![];
+            while let Some(value) = iter.next().await? {
+                values.push(value);
+            }
+            assert_eq!(values, ((*start).min(64) as u64..64).collect::<Vec<_>>());
+        }
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_list_diff() -> Result<()> {
+        let remote = MemStore::<DefaultParams>::default();
+        let mut config = ListConfig::new(remote.clone(), Code::Blake2b256);
+        config.set_width(4);
+        let root = *List::from(config, 0..64u64).await?.root();
+        let mut config = ListConfig::new(remote.clone(), Code::Blake2b256);
+        config.set_width(4);
+        let values = (0..66u64).map(|i| if i == 5 { 100 } else { i });
+        let other_root = *List::from(config, values).await?.root();
+
+        let store = RemoteStore::new(remote.clone());
+        let config = ListConfig::new(store.clone(), Code::Blake2b256);
+        let list = List::<_, u64>::open(config, root).await?;
+        let config = ListConfig::new(store.clone(), Code::Blake2b256);
+        let other = List::<_, u64>::open(config, other_root).await?;
+        store.fetched();
+        assert_eq!(
+            list.diff(&other).await?,
+            vec![
+                (5, Some(5), Some(100)),
+                (64, None, Some(64)),
+                (65, None, Some(65)),
+            ]
+        );
+        // the last three branches and three of the leaves below the first
+        // one are shared and never loaded
+        assert!(store.fetched() < 16);
+        assert_eq!(
+            other.diff(&list).await?,
+            vec![
+                (5, Some(100), Some(5)),
+                (64, Some(64), None),
+                (65, Some(65), None),
+            ]
+        );
+        assert!(list.diff(&list).await?.is_empty());
+
+        // lists of different shapes are compared value by value
+        let mut config = ListConfig::new(remote.clone(), Code::Blake2b256);
+        config.set_width(3);
+        let list = List::from(config, 0..64u64).await?;
+        let mut config = ListConfig::new(remote.clone(), Code::Blake2b256);
+        config.set_split_by_size(true);
+        let other = List::from(config, 0..64u64).await?;
+        assert!(list.diff(&other).await?.is_empty());
+        let config = ListConfig::new(remote, Code::Blake2b256);
+        let empty = List::<_, u64>::new(config).await?;
+        let changes = empty.diff(&list).await?;
+        assert_eq!(changes.len(), 64);
+        assert_eq!(changes[63], (63, None, Some(63)));
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_list_concurrency() -> Result<()> {
+        let store = MemStore::<DefaultParams>::default();
+        let value = |i: usize| vec![i as u8; i % 8 * DefaultParams::MAX_BLOCK_SIZE / 16];
+        let mut config = ListConfig::new(store.clone(), Code::Blake2b256);
+        config.set_width(3);
+        let root = *List::from(config, (0..40).map(value)).await?.root();
+
+        let mut cars = vec![];
+        for concurrency in &[1, 4, 16] {
+            let mut config = ListConfig::new(store.clone(), Code::Blake2b256);
+            config.set_concurrency(*concurrency);
+            let list = List::<_, Vec<u8>>::open(config, root).await?;
+            let mut iter = list.iter();
+            for i in 0..40 {
+                assert_eq!(iter.next().await?, Some(value(i)));
+            }
+            assert_eq!(iter.next().await?, None);
+            let report = list.verify().await;
+            assert!(report.is_ok());
+            let mut car = vec![];
+            list.export_car(&mut car).await?;
+            cars.push((report.blocks, car));
+        }
+        assert_eq!(cars[0], cars[1]);
+        assert_eq!(cars[0], cars[2]);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_list_stats() -> Result<()> {
+        let store = MemStore::<DefaultParams>::default();
+        let mut config = ListConfig::new(store.clone(), Code::Blake2b256);
+        config.set_width(4);
+        let mut list = List::from(config, 0..40u64).await?;
+        let stats = list.stats().await?;
+        assert_eq!(stats.depth(), 3);
+        assert_eq!(stats.nodes, vec![1, 3, 10]);
+        assert_eq!(stats.blocks, 14);
+        assert!(stats.bytes > 0);
+        assert!(stats.buckets.is_empty());
+        assert_eq!(stats.leaf_fill, 1.0);
+
+        list.push(40).await?;
+        let stats = list.stats().await?;
+        assert_eq!(stats.nodes, vec![1, 3, 11]);
+        assert_eq!(stats.leaf_fill, 10.25 / 11.0);
+
+        // the leaves and the first two branches are all the same
+        let mut config = ListConfig::new(store.clone(), Code::Blake2b256);
+        config.set_width(4);
+        let list = List::from(config, (0..40u64).map(|i| i % 4)).await?;
+        let stats = list.stats().await?;
+        assert_eq!(stats.nodes, vec![1, 2, 1]);
+        assert_eq!(stats.blocks, 4);
+        assert_eq!(stats.leaf_fill, 1.0);
+        Ok(())
+    }
+
+    // records the calls to check their nesting, skips nodes at skip
+    #[derive(Default)]
+    struct Recorder {
+        skip: Option<usize>,
+        open: Vec<Cid>,
+        entered: usize,
+        links: usize,
+        spilled: usize,
+        values: Vec<Vec<u8>>,
+    }
+
+    impl Visitor<Vec<u8>> for Recorder {
+        fn enter(&mut self, cid: &Cid, depth: usize) -> bool {
+            assert_eq!(depth, self.open.len());
+            if self.skip == Some(depth) {
+                return false;
+            }
+            self.open.push(*cid);
+            self.entered += 1;
+            true
+        }
+
+        fn exit(&mut self, cid: &Cid, depth: usize) {
+            assert_eq!(self.open.pop(), Some(*cid));
+            assert_eq!(depth, self.open.len());
+        }
+
+        fn link(&mut self, _: &Cid) {
+            self.links += 1;
+        }
+
+        fn spilled(&mut self, _: &Cid) {
+            self.spilled += 1;
+        }
+
+        fn value(&mut self, key: Option<&[u8]>, value: &Vec<u8>) {
+            assert!(key.is_none());
+            self.values.push(value.clone());
+        }
+    }
+
+    #[async_std::test]
+    async fn test_list_walk() -> Result<()> {
+        let store = MemStore::<DefaultParams>::default();
+        let value = |i: usize| vec![i as u8; i % 4 * DefaultParams::MAX_BLOCK_SIZE / 4];
+        let mut config = ListConfig::new(store.clone(), Code::Blake2b256);
+        config.set_width(2);
+        config.set_concurrency(4);
+        let list = List::from(config, (0..5).map(value)).await?;
+
+        let mut recorder = Recorder::default();
+        list.walk(&mut recorder).await?;
+        assert!(recorder.open.is_empty());
+        assert_eq!(recorder.entered, 6);
+        assert_eq!(recorder.links, 5);
+        assert!(recorder.spilled > 0);
+        assert_eq!(recorder.values, (0..5).map(value).collect::<Vec<_>>());
+        let stats = list.stats().await?;
+        assert_eq!(stats.blocks, (recorder.entered + recorder.spilled) as u64);
+
+        let mut recorder = Recorder {
+            skip: Some(1),
+            ..Default::default()
+        };
+        list.walk(&mut recorder).await?;
+        assert_eq!(recorder.entered, 1);
+        assert_eq!(recorder.links, 2);
+        assert!(recorder.values.is_empty());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_list_walk_skip() -> Result<()> {
+        let remote = MemStore::<DefaultParams>::default();
+        let mut config = ListConfig::new(remote.clone(), Code::Blake2b256);
+        config.set_width(4);
+        let root = *List::from(config, (0..64u8).map(|i| vec![i])).await?.root();
+
+        let store = RemoteStore::new(remote);
+        let mut config = ListConfig::new(store.clone(), Code::Blake2b256);
+        config.set_width(4);
+        let list = List::<_, Vec<u8>>::open(config, root).await?;
+        assert_eq!(store.fetched(), 1);
+        // skipped nodes aren't fetched
+        let mut recorder = Recorder {
+            skip: Some(1),
+            ..Default::default()
+        };
+        list.walk(&mut recorder).await?;
+        assert_eq!(recorder.links, 4);
+        assert_eq!(store.fetched(), 0);
+        let mut recorder = Recorder {
+            skip: Some(2),
+            ..Default::default()
+        };
+        list.walk(&mut recorder).await?;
+        assert_eq!(recorder.entered, 5);
+        assert_eq!(store.fetched(), 4);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_list_versions() -> Result<()> {
+        let store = MemStore::<DefaultParams>::default();
+        let mut config = ListConfig::new(store.clone(), Code::Blake2b256);
+        config.set_width(4);
+        let list = List::from(config, 0..10u64).await?;
+        let header = list.header().await?.unwrap();
+
+        // a root from before headers were introduced
+        let mut node = list.cache.get(list.root(), None).await?;
+        node.header = None;
+        let old = list.cache.insert(node.clone(), None).await?;
+        let config = ListConfig::new(store.clone(), Code::Blake2b256);
+        let new = List::<_, u64>::migrate(config, old).await?;
+        assert_eq!(new, *list.root());
+        let config = ListConfig::new(store.clone(), Code::Blake2b256);
+        assert_eq!(List::<_, u64>::migrate(config, new).await?, new);
+        // it has to be migrated before it can be opened
+        let config = ListConfig::new(store.clone(), Code::Blake2b256);
+        let err = List::<_, u64>::open(config, old).await.err().unwrap();
+        assert_eq!(err.downcast_ref::<UnsupportedVersion>().unwrap().0, 0);
+
+        // a root from a newer version
+        node.header = Some(Header {
+            version: FORMAT_VERSION + 1,
+            ..header
+        });
+        let newer = list.cache.insert(node, None).await?;
+        let config = ListConfig::new(store.clone(), Code::Blake2b256);
+        let err = List::<_, u64>::open(config, newer).await.err().unwrap();
+        assert!(err.downcast_ref::<UnsupportedVersion>().is_some());
+        let config = ListConfig::new(store.clone(), Code::Blake2b256);
+        assert!(List::<_, u64>::migrate(config, newer).await.is_err());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_list_baseline() -> Result<()> {
+        use libipld::multihash::MultihashDigest;
+
+        // a leaf holding 0, 1 and 2 the way the crate wrote it before nodes
+        // had headers, lens or separate widths: {data: [{Value: [0]},
+        // {Value: [1]}, {Value: [2]}], height: 0, width: 4}
+        let bytes = vec![
+            0xa3, 0x64, 0x64, 0x61, 0x74, 0x61, 0x83, 0xa1, 0x65, 0x56, 0x61, 0x6c, 0x75, 0x65,
+            0x81, 0x00, 0xa1, 0x65, 0x56, 0x61, 0x6c, 0x75, 0x65, 0x81, 0x01, 0xa1, 0x65, 0x56,
+            0x61, 0x6c, 0x75, 0x65, 0x81, 0x02, 0x66, 0x68, 0x65, 0x69, 0x67, 0x68, 0x74, 0x00,
+            0x65, 0x77, 0x69, 0x64, 0x74, 0x68, 0x04,
+        ];
+        let cid = Cid::new_v1(DagCborCodec.into(), Code::Blake2b256.digest(&bytes));
+        let store = MemStore::<DefaultParams>::default();
+        store
+            .insert(&Block::new_unchecked(cid, bytes), None)
+            .await?;
+
+        let config = ListConfig::new(store.clone(), Code::Blake2b256);
+        let err = List::<_, u64>::open(config, cid).await.err().unwrap();
+        assert_eq!(err.downcast_ref::<UnsupportedVersion>().unwrap().0, 0);
+        assert!(err.to_string().contains("migrated"));
+
+        let config = ListConfig::new(store.clone(), Code::Blake2b256);
+        let root = List::<_, u64>::migrate(config, cid).await?;
+        let config = ListConfig::new(store.clone(), Code::Blake2b256);
+        let mut list = List::<_, u64>::open(config, root).await?;
+        match list.header().await?.unwrap().kind {
+            Kind::List {
+                leaf_width,
+                branch_width,
+                split_by_size,
+            } => assert_eq!((leaf_width, branch_width, split_by_size), (4, 4, false)),
+            _ => unreachable!(),
+        }
+        for i in 3..10 {
+            list.push(i).await?;
+        }
+        assert_eq!(list.len().await?, 10);
+        for i in 0..10 {
+            assert_eq!(list.get(i).await?, Some(i as u64));
+        }
+
+        let mut config = ListConfig::new(store, Code::Blake2b256);
+        config.set_width(4);
+        let from = List::from(config, 0..10u64).await?;
+        assert_eq!(from.root(), list.root());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_list_ipld() -> Result<()> {
+        let store = MemStore::<DefaultParams>::default();
+        let mut config = ListConfig::new(store.clone(), Code::Blake2b256);
+        config.set_width(4);
+        let list = List::from(config, 0..10u64).await?;
+        let ipld = list.to_ipld(1024).await?;
+        assert_eq!(ipld, Ipld::List((0..10).map(Ipld::Integer).collect()));
+        assert!(list.to_ipld(5).await.is_err());
+
+        let mut config = ListConfig::new(store.clone(), Code::Blake2b256);
+        config.set_width(4);
+        let loaded = List::<_, u64>::from_ipld(config, &ipld).await?;
+        assert_eq!(loaded.root(), list.root());
+        let config = ListConfig::new(store.clone(), Code::Blake2b256);
+        assert!(List::<_, u64>::from_ipld(config, &Ipld::Integer(0))
+            .await
+            .is_err());
+
+        let mut json = vec![];
+        list.export_json(&mut json).await?;
+        assert_eq!(json, b"[0,1,2,3,4,5,6,7,8,9]");
+        let config = ListConfig::new(store.clone(), Code::Blake2b256);
+        let mut json = vec![];
+        List::<_, u64>::new(config)
+            .await?
+            .export_json(&mut json)
+            .await?;
+        assert_eq!(json, b"[]");
+        Ok(())
+    }
 }
@@ -1,14 +1,31 @@
 use Bit::{One, Zero};
 
-use libipld::cache::{Cache, IpldCache};
+use crate::cache::{NodeCache, Traversal};
+use crate::car;
+use crate::codec::{encode, CollectionStore, NodeCodec};
+use crate::header::{self, Header, Kind, FORMAT_VERSION};
+use crate::plain::{self, Guard, JsonArray};
+use crate::proof::next_block;
+use crate::stats::{self, Stats};
+use crate::tx::{Buffer, Links, Spill};
+use crate::verify::{Problem, Report};
+use crate::visit::{self, Item, Visitor, Walker};
+use libipld::block::Block;
 use libipld::cbor::{DagCbor, DagCborCodec};
-use libipld::prelude::{References, Store, StoreParams};
+use libipld::error::Error;
+use libipld::ipld::Ipld;
+use libipld::prelude::{Store, StoreParams};
 use libipld::DagCbor;
-use libipld::{Cid, Ipld, Result};
+use libipld::{Cid, Result};
 use std::cmp::PartialEq;
 use std::collections::BTreeMap;
 use std::fmt::Debug;
+use std::future::Future;
+use std::io::{self, ErrorKind, Read, Write};
 use std::iter::once;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::sync::Arc;
 
 // TODO use const generics
 const MAP_LEN: usize = 32;
@@ -23,20 +40,23 @@ fn hash(bytes: &[u8]) -> Vec<u8> {
     }
 }
 
-macro_rules! validate {
-    ($block:expr) => {
-        if $block.data.len() != 0 && $block.data.len() != popcount_all(&$block.map) as usize {
-            todo!("Return error: Malformed block");
-        }
-    };
+// the multihash code of hash
+pub(crate) fn key_hash() -> u64 {
+    if cfg!(test) {
+        0x00
+    } else {
+        0x12
+    }
 }
 
-macro_rules! validate_or_empty {
+// nodes need an element for each bit set in their map, otherwise looking up
+// a key could index past their data
+macro_rules! validate {
     ($block:expr) => {
-        if $block.data.len() == 0 && *$block.map != [0; 32]
-            || $block.data.len() != 0 && $block.data.len() != popcount_all(&$block.map) as usize
+        if $block.map.len() != MAP_LEN
+            || $block.data.len() != $block.map.iter().map(|b| b.count_ones() as usize).sum()
         {
-            todo!("Return error: Malformed block");
+            return Err(io::Error::new(ErrorKind::InvalidData, "malformed hamt node").into());
         }
     };
 }
@@ -121,7 +141,7 @@ impl<T: DagCbor> FullPath<T> {
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
-enum Bit {
+pub(crate) enum Bit {
     Zero,
     One,
 }
@@ -137,6 +157,15 @@ enum RemoveError {
     Id(Cid, usize),
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum SetOp {
+    Union,
+    Intersection,
+    Difference,
+}
+
+type NodeFuture<'a, T> = Pin<Box<dyn Future<Output = Result<Node<T>>> + Send + 'a>>;
+
 #[cfg(test)]
 impl<T: DagCbor> InsertError<T> {
     fn is_id(&self) -> bool {
@@ -176,7 +205,7 @@ fn popcount(map: &[u8], bit: u8) -> u8 {
     count_ones
 }
 
-fn popcount_all(map: &[u8]) -> u8 {
+pub(crate) fn popcount_all(map: &[u8]) -> u8 {
     // if not true, can overflow
     debug_assert!(map.len() * 8 <= 256);
     let mut count_ones = 0x00;
@@ -190,7 +219,7 @@ fn popcount_all(map: &[u8]) -> u8 {
     count_ones
 }
 
-fn get_bit(map: &[u8], bit: u8) -> Bit {
+pub(crate) fn get_bit(map: &[u8], bit: u8) -> Bit {
     debug_assert!(map.len() * 8 >= bit.into());
     let in_byte = (bit / 8) as usize;
     let shifts = (7 - bit % 8) % 8;
@@ -203,7 +232,7 @@ fn get_bit(map: &[u8], bit: u8) -> Bit {
     }
 }
 
-fn set_bit(map: &mut [u8], bit: u8, val: Bit) {
+pub(crate) fn set_bit(map: &mut [u8], bit: u8, val: Bit) {
     debug_assert!(map.len() * 8 >= bit.into());
     let in_byte = (bit / 8) as usize;
     let shifts = (7 - bit % 8) % 8;
@@ -223,6 +252,81 @@ struct Node<T: DagCbor> {
     // map has 2.pow(bit_width) bits, here 256
     map: Box<[u8]>,
     data: Vec<Element<T>>,
+    // only the root has one
+    #[ipld(default = None)]
+    header: Option<Header>,
+}
+
+impl<T: DagCbor> Links for Node<T> {
+    fn links(&self) -> Vec<Cid> {
+        self.data
+            .iter()
+            .filter_map(|elt| match elt {
+                Element::HashNode(cid) => Some(*cid),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn spilled(&self) -> Vec<Cid> {
+        self.data
+            .iter()
+            .flat_map(|elt| match elt {
+                Element::Spilled(bucket) => bucket.iter().map(|entry| entry.value).collect(),
+                _ => vec![],
+            })
+            .collect()
+    }
+}
+
+// whole buckets are spilled, each of their values into a block of its own
+impl<P, T> Spill<P> for Node<T>
+where
+    P: StoreParams,
+    T: DagCbor,
+{
+    fn spill<C>(&mut self, codec: C, hash: P::Hashes, excess: usize) -> Result<Vec<Block<P>>>
+    where
+        C: NodeCodec,
+    {
+        let mut sizes = vec![];
+        for (i, elt) in self.data.iter().enumerate() {
+            if let Element::Bucket(bucket) = elt {
+                let mut size = 0;
+                for entry in bucket {
+                    size += codec.encode_node(&entry.value)?.len();
+                }
+                sizes.push((size, i));
+            }
+        }
+        sizes.sort_unstable_by(|a, b| b.cmp(a));
+        let mut blocks = vec![];
+        let mut saved = 0;
+        for (size, i) in sizes {
+            if saved >= excess {
+                break;
+            }
+            let bucket = match &self.data[i] {
+                Element::Bucket(bucket) => bucket,
+                _ => unreachable!(),
+            };
+            let mut spilled = Vec::with_capacity(bucket.len());
+            let mut links = 0;
+            for entry in bucket {
+                let block = encode(codec, hash, &entry.value)?;
+                links += codec.encode_node(block.cid())?.len();
+                spilled.push(Entry::new(entry.key.clone(), *block.cid()));
+                blocks.push(block);
+            }
+            if size <= links {
+                blocks.truncate(blocks.len() - spilled.len());
+                break;
+            }
+            saved += size - links;
+            self.data[i] = Element::Spilled(spilled);
+        }
+        Ok(blocks)
+    }
 }
 
 impl<T: DagCbor> Node<T> {
@@ -230,7 +334,27 @@ impl<T: DagCbor> Node<T> {
         Self {
             map: Box::new([0; MAP_LEN]),
             data: vec![],
+            header: None,
+        }
+    }
+    fn items(self) -> Vec<Item<T>> {
+        let mut items = vec![];
+        for elt in self.data {
+            match elt {
+                Element::HashNode(cid) => items.push(Item::Link(cid)),
+                Element::Bucket(bucket) => items.extend(
+                    bucket
+                        .into_iter()
+                        .map(|Entry { key, value }| Item::Value(Some(key), value)),
+                ),
+                Element::Spilled(bucket) => items.extend(
+                    bucket
+                        .into_iter()
+                        .map(|Entry { key, value }| Item::Spilled(Some(key), value)),
+                ),
+            }
         }
+        items
     }
     fn has_children(&self) -> bool {
         self.data.iter().any(|elt| elt.is_hash_node())
@@ -278,7 +402,6 @@ impl<T: DagCbor> Node<T> {
             }
         }
     }
-    #[cfg(test)]
     fn set(&mut self, index: u8, element: Element<T>) {
         let idx = popcount(&self.map, index);
         match get_bit(&self.map, index) {
@@ -323,6 +446,9 @@ impl<T: DagCbor> Node<T> {
             One => {
                 match &mut self.data[data_index] {
                     Element::HashNode(cid) => Err(Id(entry, *cid, data_index)),
+                    Element::Spilled(_) => {
+                        unreachable!("spilled buckets are loaded with their node")
+                    }
                     Element::Bucket(ref mut bucket) => {
                         let found = bucket
                             .iter_mut()
@@ -368,6 +494,9 @@ impl<T: DagCbor> Node<T> {
                 let elt = &mut self.data[data_index];
                 match elt {
                     Element::HashNode(cid) => Err(Id(*cid, data_index)),
+                    Element::Spilled(_) => {
+                        unreachable!("spilled buckets are loaded with their node")
+                    }
                     Element::Bucket(bucket) if bucket.len() != 1 => {
                         for i in 0..bucket.len() {
                             if &*bucket[i].key == key {
@@ -393,6 +522,8 @@ impl<T: DagCbor> Node<T> {
 enum Element<T: DagCbor> {
     HashNode(Cid),
     Bucket(Vec<Entry<T>>),
+    // a bucket whose values were too large to be stored inline
+    Spilled(Vec<Entry<Cid>>),
 }
 
 impl<T: DagCbor> Default for Element<T> {
@@ -405,11 +536,18 @@ impl<T: DagCbor> Element<T> {
     fn is_hash_node(&self) -> bool {
         match self {
             Element::HashNode(_) => true,
-            Element::Bucket(_) => false,
+            Element::Bucket(_) | Element::Spilled(_) => false,
         }
     }
 }
 
+// a value found while diffing, spilled values are only loaded if the other
+// side doesn't link to the same block
+enum Stored<T> {
+    Inline(T),
+    Spilled(Cid),
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, DagCbor)]
 struct Entry<T: DagCbor> {
     key: Box<[u8]>,
@@ -452,30 +590,56 @@ struct EntryWithHash<T: DagCbor> {
     hash: Vec<u8>,
 }
 
-pub struct HamtConfig<S>
+pub struct HamtConfig<S, C = DagCborCodec>
 where
-    S: Store,
-    <S::Params as StoreParams>::Codecs: Into<DagCborCodec>,
-    DagCborCodec: Into<<S::Params as StoreParams>::Codecs>,
-    Ipld: References<<S::Params as StoreParams>::Codecs>,
+    S: CollectionStore<C>,
+    C: NodeCodec,
 {
     store: S,
+    codec: C,
     cache_size: usize,
+    concurrency: usize,
     hash: <S::Params as StoreParams>::Hashes,
     bucket_size: usize,
 }
 
+impl<S, C> Clone for HamtConfig<S, C>
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+{
+    fn clone(&self) -> Self {
+        Self {
+            store: self.store.clone(),
+            codec: self.codec,
+            cache_size: self.cache_size,
+            concurrency: self.concurrency,
+            hash: self.hash,
+            bucket_size: self.bucket_size,
+        }
+    }
+}
+
 impl<S> HamtConfig<S>
 where
-    S: Store,
-    <S::Params as StoreParams>::Codecs: Into<DagCborCodec>,
-    DagCborCodec: Into<<S::Params as StoreParams>::Codecs>,
-    Ipld: References<<S::Params as StoreParams>::Codecs>,
+    S: CollectionStore<DagCborCodec>,
 {
     pub fn new(store: S, hash: <S::Params as StoreParams>::Hashes) -> Self {
+        Self::with_codec(store, DagCborCodec, hash)
+    }
+}
+
+impl<S, C> HamtConfig<S, C>
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+{
+    pub fn with_codec(store: S, codec: C, hash: <S::Params as StoreParams>::Hashes) -> Self {
         Self {
             store,
+            codec,
             cache_size: 64,
+            concurrency: 1,
             hash,
             bucket_size: 3,
         }
@@ -485,6 +649,12 @@ where
         self.cache_size = cache_size;
     }
 
+    // the number of blocks fetched at once when walking the whole collection,
+    // like iter, verify and export_car do
+    pub fn set_concurrency(&mut self, concurrency: usize) {
+        self.concurrency = concurrency;
+    }
+
     pub fn set_bucket_size(&mut self, bucket_size: usize) {
         self.bucket_size = bucket_size;
     }
@@ -493,71 +663,594 @@ where
         self.bucket_size
     }
 
-    fn cache<T>(self) -> IpldCache<S, DagCborCodec, Node<T>>
+    fn header(&self) -> Header {
+        root_header(self.bucket_size, self.hash.into())
+    }
+
+    fn cache<T>(self) -> NodeCache<S, C, Node<T>>
     where
         T: DagCbor + Clone + Send + Sync,
     {
-        IpldCache::new(self.store, DagCborCodec, self.hash, self.cache_size)
+        NodeCache::new(self.store, self.codec, self.hash, self.cache_size)
+    }
+}
+
+fn root_header(bucket_size: usize, hash: u64) -> Header {
+    Header {
+        kind: Kind::Hamt {
+            bucket_size: bucket_size as u64,
+            key_hash: key_hash(),
+        },
+        version: FORMAT_VERSION,
+        hash,
     }
 }
 
-pub struct Hamt<S: Store, T: DagCbor> {
-    cache: IpldCache<S, DagCborCodec, Node<T>>,
+pub struct Hamt<S: Store, T: DagCbor, C = DagCborCodec> {
+    cache: Arc<NodeCache<S, C, Node<T>>>,
+    hash: <S::Params as StoreParams>::Hashes,
+    concurrency: usize,
     root: Cid,
     tmp: S::TempPin,
     bucket_size: usize,
 }
 
-impl<S, T> Hamt<S, T>
+impl<S, T, C> Hamt<S, T, C>
 where
-    S: Store,
-    <S::Params as StoreParams>::Codecs: Into<DagCborCodec>,
-    DagCborCodec: Into<<S::Params as StoreParams>::Codecs>,
-    Ipld: References<<S::Params as StoreParams>::Codecs>,
+    S: CollectionStore<C>,
+    C: NodeCodec,
     T: DagCbor + Clone + Send + Sync,
 {
-    pub async fn new(config: HamtConfig<S>) -> Result<Self> {
+    pub async fn new(config: HamtConfig<S, C>) -> Result<Self> {
         let bucket_size = config.bucket_size();
-        let cache = config.cache();
+        let mut node = Node::new();
+        node.header = Some(config.header());
+        let hash = config.hash;
+        let concurrency = config.concurrency;
+        let cache = Arc::new(config.cache());
         let tmp = cache.temp_pin().await?;
-        let root = cache.insert(Node::new(), Some(&tmp)).await?;
+        let root = cache.insert(node, Some(&tmp)).await?;
         Ok(Self {
             cache,
+            hash,
+            concurrency,
             root,
             tmp,
             bucket_size,
         })
     }
 
-    pub async fn open(config: HamtConfig<S>, root: Cid) -> Result<Self> {
+    // a handle for the hamts nested in another collection, it's pointed at
+    // one of them with set_root or clear. unlike new it doesn't write anything
+    pub(crate) async fn nested(config: HamtConfig<S, C>) -> Result<Self> {
         let bucket_size = config.bucket_size();
-        let cache = config.cache();
+        let mut node = Node::<T>::new();
+        node.header = Some(config.header());
+        let hash = config.hash;
+        let concurrency = config.concurrency;
+        let cache = Arc::new(config.cache());
+        let tmp = cache.temp_pin().await?;
+        // the root isn't in the store until clear writes it
+        let root = *cache.encode(&node)?.cid();
+        Ok(Self {
+            cache,
+            hash,
+            concurrency,
+            root,
+            tmp,
+            bucket_size,
+        })
+    }
+
+    // the bucket size and hash of the hamt are taken from its root, whatever
+    // the config has them set to. roots without a header, written before
+    // they were introduced, have to be migrated first
+    pub async fn open(config: HamtConfig<S, C>, root: Cid) -> Result<Self> {
+        let concurrency = config.concurrency;
+        let cache = Arc::new(config.cache());
         let tmp = cache.temp_pin().await?;
         // warm up the cache and make sure it's available
-        cache.get(&root, Some(&tmp)).await?;
+        let node = cache.get(&root, Some(&tmp)).await?;
+        header::check(node.header.as_ref(), "hamt")?;
+        let hash = header::hash::<S::Params>(node.header.as_ref())?;
+        let bucket_size = header::bucket_size(node.header.as_ref())?;
         Ok(Self {
             cache,
+            hash,
+            concurrency,
             root,
             tmp,
             bucket_size,
         })
     }
 
+    // rewrites a root written by an older version of the crate and returns
+    // the new one, roots that are up to date are returned as they are. old
+    // hamts don't record their bucket size, it's taken from the config and
+    // the whole hamt is checked against it
+    pub async fn migrate(config: HamtConfig<S, C>, root: Cid) -> Result<Cid> {
+        let header = config.header();
+        let bucket_size = config.bucket_size();
+        let concurrency = config.concurrency;
+        let cache = config.cache();
+        let tmp = cache.temp_pin().await?;
+        let mut node: Node<T> = cache.get(&root, Some(&tmp)).await?;
+        header::check_migrate(node.header.as_ref(), "hamt")?;
+        match header::version(node.header.as_ref()) {
+            0 => {
+                let report = verify_nodes(&cache, &root, concurrency, bucket_size).await;
+                if let Some(problem) = report.problems.first() {
+                    let msg = format!(
+                        "the hamt doesn't match a bucket size of {}: {:?}",
+                        bucket_size, problem
+                    );
+                    return Err(io::Error::new(ErrorKind::InvalidInput, msg).into());
+                }
+                node.header = Some(header);
+                cache.insert(node, Some(&tmp)).await
+            }
+            _ => Ok(root),
+        }
+    }
+
     pub fn root(&self) -> &Cid {
         &self.root
     }
 
+    // roots written before headers were introduced don't have one
+    pub async fn header(&self) -> Result<Option<Header>> {
+        Ok(self.cache.get(&self.root, Some(&self.tmp)).await?.header)
+    }
+
+    // writes the hamt into a CARv1 file with the hamt's root as its root
+    pub async fn export_car<W: Write>(&self, mut writer: W) -> Result<()> {
+        car::export(
+            &self.cache,
+            &self.root,
+            self.concurrency,
+            Node::items,
+            &mut writer,
+        )
+        .await
+    }
+
+    // the root is checked before any of the blocks are inserted
+    pub async fn import_car<R: Read>(config: HamtConfig<S, C>, mut reader: R) -> Result<Self> {
+        let concurrency = config.concurrency;
+        let cache = Arc::new(config.cache());
+        let tmp = cache.temp_pin().await?;
+        let (root, blocks) = car::read::<S::Params, _>(&mut reader)?;
+        let node: Node<T> = cache.decode(car::root_block(&root, &blocks)?)?;
+        header::check(node.header.as_ref(), "hamt")?;
+        let hash = header::hash::<S::Params>(node.header.as_ref())?;
+        let bucket_size = header::bucket_size(node.header.as_ref())?;
+        car::insert(&**cache, Some(&tmp), &blocks).await?;
+        Ok(Self {
+            cache,
+            hash,
+            concurrency,
+            root,
+            tmp,
+            bucket_size,
+        })
+    }
+
+    // materializes the entries as an ipld map from key to value if all keys
+    // are valid utf-8. ipld maps only have string keys, so hamts with binary
+    // keys become a list of {key, value} maps sorted by key instead. fails
+    // once the keys and values get larger than max_size
+    pub async fn to_ipld(&self, max_size: usize) -> Result<Ipld> {
+        let mut guard = Guard::new(max_size);
+        let mut values = BTreeMap::new();
+        let mut iter = self.iter();
+        while let Some((key, value)) = iter.next().await? {
+            guard.add(key.len())?;
+            let value = guard.convert(&value)?;
+            values.insert(key, value);
+        }
+        if values.keys().all(|key| std::str::from_utf8(key).is_ok()) {
+            let map = values
+                .into_iter()
+                .map(|(key, value)| (String::from_utf8_lossy(&key).into_owned(), value))
+                .collect();
+            return Ok(Ipld::StringMap(map));
+        }
+        let entries = values.into_iter().map(|(key, value)| {
+            let mut entry = BTreeMap::new();
+            entry.insert("key".to_string(), Ipld::Bytes(key.into_vec()));
+            entry.insert("value".to_string(), value);
+            Ipld::StringMap(entry)
+        });
+        Ok(Ipld::List(entries.collect()))
+    }
+
+    // takes either form to_ipld returns
+    pub async fn from_ipld(config: HamtConfig<S, C>, ipld: &Ipld) -> Result<Self> {
+        let mut btree = BTreeMap::new();
+        match ipld {
+            Ipld::List(entries) => {
+                for entry in entries {
+                    let Entry { key, value } = plain::from_ipld(entry)?;
+                    btree.insert(key, value);
+                }
+            }
+            Ipld::StringMap(map) => {
+                for (key, value) in map {
+                    btree.insert(key.as_bytes().into(), plain::from_ipld(value)?);
+                }
+            }
+            _ => return Err(plain::unexpected("a list of entries or a map").into()),
+        }
+        Self::from(config, btree).await
+    }
+
+    // writes the entries as a dag-json array of {key, value} maps, one at a
+    // time in the order iter returns them
+    pub async fn export_json<W: Write>(&self, mut writer: W) -> Result<()> {
+        let mut array = JsonArray::new(&mut writer)?;
+        let mut iter = self.iter();
+        while let Some((key, value)) = iter.next().await? {
+            array.push(&Entry::new(key, value))?;
+        }
+        array.finish()
+    }
+
     pub async fn from<I: Into<Box<[u8]>>>(
-        config: HamtConfig<S>,
+        config: HamtConfig<S, C>,
         btree: BTreeMap<I, T>,
     ) -> Result<Self> {
         let mut hamt = Hamt::new(config).await?;
+        let mut tx = hamt.transaction();
         for (key, value) in btree {
-            hamt.insert(key.into(), value).await?;
+            tx.insert(key.into(), value).await?;
         }
+        tx.commit().await?;
         Ok(hamt)
     }
 
+    pub async fn get(&self, key: &[u8]) -> Result<Option<T>> {
+        get(&self.cache, &self.tmp, &self.root, key).await
+    }
+
+    pub fn iter(&self) -> HamtIter<S, T, C> {
+        self.iter_at(self.root)
+    }
+
+    // iterates the hamt at another root of the same store and bucket size,
+    // reusing this one's cache
+    pub(crate) fn iter_at(&self, root: Cid) -> HamtIter<S, T, C> {
+        HamtIter {
+            cache: self.cache.clone(),
+            _tmp: self.tmp.clone(),
+            concurrency: self.concurrency,
+            nodes: Traversal::new(self.concurrency, root, ()),
+            entries: vec![],
+        }
+    }
+
+    // points the hamt at another root of the same store and bucket size, so
+    // collections nesting many hamts can share one cache and temp pin
+    pub(crate) async fn set_root(&mut self, root: Cid) -> Result<()> {
+        // make sure it's available and pinned
+        self.cache.get(&root, Some(&self.tmp)).await?;
+        self.root = root;
+        Ok(())
+    }
+
+    // points the hamt at a new empty one
+    pub(crate) async fn clear(&mut self) -> Result<()> {
+        let mut node = Node::new();
+        node.header = Some(root_header(self.bucket_size, self.hash.into()));
+        self.root = self.cache.insert(node, Some(&self.tmp)).await?;
+        Ok(())
+    }
+
+    // releases the blocks pinned so far, for nested hamts whose blocks are
+    // reachable from their parent once it's written
+    pub(crate) async fn unpin(&mut self) -> Result<()> {
+        self.tmp = self.cache.temp_pin().await?;
+        Ok(())
+    }
+
+    pub fn transaction(&mut self) -> HamtTransaction<'_, S, T, C> {
+        let root = self.root;
+        let buffer = Buffer::new(self.cache.codec(), self.hash);
+        HamtTransaction {
+            hamt: self,
+            root,
+            buffer,
+        }
+    }
+
+    pub async fn insert(&mut self, key: Box<[u8]>, value: T) -> Result<()> {
+        let mut tx = self.transaction();
+        tx.insert(key, value).await?;
+        tx.commit().await
+    }
+
+    pub async fn remove(&mut self, key: &[u8]) -> Result<()> {
+        let mut tx = self.transaction();
+        tx.remove(key).await?;
+        tx.commit().await
+    }
+
+    // the block that looking up key needs next but that isn't in the store.
+    // the blocks below it are only known once it's fetched, see prefetch
+    pub async fn missing(&self, key: &[u8]) -> Result<Vec<Cid>> {
+        let mut cid = self.root;
+        for index in hash(key).iter() {
+            if !Store::contains(&**self.cache, &cid).await? {
+                return Ok(vec![cid]);
+            }
+            match self.cache.get(&cid, None).await?.get(*index) {
+                Some(Element::HashNode(child)) => cid = *child,
+                Some(Element::Spilled(bucket)) => {
+                    if let Some(entry) = bucket.iter().find(|entry| &*entry.key == key) {
+                        if !Store::contains(&**self.cache, &entry.value).await? {
+                            return Ok(vec![entry.value]);
+                        }
+                    }
+                    break;
+                }
+                _ => break,
+            }
+        }
+        Ok(vec![])
+    }
+
+    // fetches the blocks that looking up each of the keys needs, the blocks
+    // of the keys are fetched concurrently level by level
+    pub async fn prefetch<K: AsRef<[u8]>>(&self, keys: &[K]) -> Result<()> {
+        let mut fetched = vec![];
+        loop {
+            let mut missing = vec![];
+            for key in keys {
+                for cid in self.missing(key.as_ref()).await? {
+                    if !missing.contains(&cid) {
+                        missing.push(cid);
+                    }
+                }
+            }
+            if missing.is_empty() {
+                return Ok(());
+            }
+            // the store didn't keep the blocks of the last round
+            if missing == fetched {
+                return Err(io::Error::new(
+                    ErrorKind::NotFound,
+                    "fetched blocks are still missing",
+                )
+                .into());
+            }
+            self.cache.fetch(&missing, &self.tmp).await?;
+            fetched = missing;
+        }
+    }
+
+    // the nodes visited while looking up key, followed by the value's own
+    // block if it was spilled. proves the key's value or its absence, see
+    // verify_hamt_proof
+    pub async fn prove(&self, key: &[u8]) -> Result<Vec<Block<S::Params>>> {
+        let mut blocks = vec![];
+        let mut cid = self.root;
+        for index in hash(key).iter() {
+            let block = Store::get(&**self.cache, &cid).await?;
+            let node: Node<T> = self.cache.decode(&block)?;
+            blocks.push(block);
+            match node.get(*index) {
+                Some(Element::HashNode(child)) => cid = *child,
+                Some(Element::Spilled(bucket)) => {
+                    if let Some(entry) = bucket.iter().find(|entry| &*entry.key == key) {
+                        blocks.push(Store::get(&**self.cache, &entry.value).await?);
+                    }
+                    break;
+                }
+                _ => break,
+            }
+        }
+        Ok(blocks)
+    }
+
+    pub async fn walk<V: Visitor<T>>(&self, visitor: &mut V) -> Result<()> {
+        visit::walk(
+            &self.cache,
+            &self.root,
+            self.concurrency,
+            visitor,
+            Node::items,
+        )
+        .await
+    }
+
+    // describes the shape of the tree, for tuning the bucket size and the
+    // cache
+    pub async fn stats(&self) -> Result<Stats> {
+        stats::collect(
+            &self.cache,
+            &self.root,
+            self.concurrency,
+            Node::items,
+            |stats, node: &Node<T>, _| {
+                for elt in node.data.iter() {
+                    let len = match elt {
+                        Element::HashNode(_) => continue,
+                        Element::Bucket(bucket) => bucket.len(),
+                        Element::Spilled(bucket) => bucket.len(),
+                    };
+                    if stats.buckets.len() <= len {
+                        stats.buckets.resize(len + 1, 0);
+                    }
+                    stats.buckets[len] += 1;
+                }
+            },
+        )
+        .await
+    }
+
+    // walks every block of the hamt and checks the invariants the other
+    // methods rely on
+    pub async fn verify(&self) -> Report {
+        verify_nodes(&self.cache, &self.root, self.concurrency, self.bucket_size).await
+    }
+
+    // the keys whose values differ from other's, with the value here and the
+    // one in other, ordered by key. subtrees both hamts share are skipped
+    // without loading them
+    pub async fn diff(&self, other: &Self) -> Result<Vec<(Box<[u8]>, Option<T>, Option<T>)>>
+    where
+        T: PartialEq,
+    {
+        let mut changes = vec![];
+        let mut stack = vec![(self.root, other.root)];
+        while let Some((a, b)) = stack.pop() {
+            if a == b {
+                continue;
+            }
+            let a = self.cache.get(&a, Some(&self.tmp)).await?;
+            let b = other.cache.get(&b, Some(&other.tmp)).await?;
+            for bit in 0..=255 {
+                match (a.get(bit), b.get(bit)) {
+                    (None, None) => {}
+                    (Some(Element::HashNode(a)), Some(Element::HashNode(b))) => {
+                        stack.push((*a, *b))
+                    }
+                    // keys under the same bit share a hash prefix, the side
+                    // holding a subtree is flattened into its entries
+                    (left, right) => {
+                        let mut right = other.entries(right).await?;
+                        for (key, value) in self.entries(left).await? {
+                            let other_value = right.remove(&key);
+                            if let (Stored::Spilled(x), Some(Stored::Spilled(y))) =
+                                (&value, &other_value)
+                            {
+                                if x == y {
+                                    continue;
+                                }
+                            }
+                            let value = self.stored(Some(value)).await?;
+                            let other_value = other.stored(other_value).await?;
+                            if value != other_value {
+                                changes.push((key, value, other_value));
+                            }
+                        }
+                        for (key, value) in right {
+                            changes.push((key, None, other.stored(Some(value)).await?));
+                        }
+                    }
+                }
+            }
+        }
+        changes.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(changes)
+    }
+
+    // the entries of an element and of all the nodes below it
+    async fn entries(
+        &self,
+        element: Option<&Element<T>>,
+    ) -> Result<BTreeMap<Box<[u8]>, Stored<T>>> {
+        let mut entries = BTreeMap::new();
+        let mut elements: Vec<_> = element.into_iter().cloned().collect();
+        while let Some(element) = elements.pop() {
+            match element {
+                Element::HashNode(cid) => {
+                    elements.extend(self.cache.get(&cid, Some(&self.tmp)).await?.data)
+                }
+                Element::Bucket(bucket) => {
+                    for Entry { key, value } in bucket {
+                        entries.insert(key, Stored::Inline(value));
+                    }
+                }
+                Element::Spilled(bucket) => {
+                    for Entry { key, value } in bucket {
+                        entries.insert(key, Stored::Spilled(value));
+                    }
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    async fn stored(&self, value: Option<Stored<T>>) -> Result<Option<T>> {
+        Ok(match value {
+            Some(Stored::Inline(value)) => Some(value),
+            Some(Stored::Spilled(cid)) => Some(self.cache.load(&cid).await?),
+            None => None,
+        })
+    }
+
+    pub async fn snapshot(&self) -> Result<HamtSnapshot<S, T, C>> {
+        let tmp = self.cache.temp_pin().await?;
+        // reinserting the root pins it and everything it references under
+        // the snapshot's own temp pin
+        let root = self.cache.get(&self.root, Some(&self.tmp)).await?;
+        self.cache.insert(root, Some(&tmp)).await?;
+        Ok(HamtSnapshot {
+            cache: self.cache.clone(),
+            root: self.root,
+            tmp,
+        })
+    }
+
+    pub async fn flush<A: AsRef<[u8]> + Send + Sync>(&mut self, alias: A) -> Result<()> {
+        self.cache.alias(alias, Some(self.root())).await?;
+        self.tmp = self.cache.temp_pin().await?;
+        self.cache.flush().await?;
+        Ok(())
+    }
+}
+
+// keys whose hashes collide more often than the bucket size at every level
+// can't be told apart
+fn collision_depth() -> io::Error {
+    let msg = "maximum collision depth reached";
+    io::Error::new(ErrorKind::InvalidData, msg)
+}
+
+pub struct HamtTransaction<'a, S: Store, T: DagCbor, C = DagCborCodec> {
+    hamt: &'a mut Hamt<S, T, C>,
+    root: Cid,
+    buffer: Buffer<S, C, Node<T>>,
+}
+
+impl<'a, S, T, C> HamtTransaction<'a, S, T, C>
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+    T: DagCbor + Clone + Send + Sync,
+{
+    pub fn root(&self) -> &Cid {
+        &self.root
+    }
+
+    async fn node(&self, cid: &Cid) -> Result<Node<T>> {
+        let node = match self.buffer.get(cid) {
+            Some(node) => node.clone(),
+            None => self.hamt.cache.get(cid, Some(&self.hamt.tmp)).await?,
+        };
+        self.unspill(&self.hamt.cache, node).await
+    }
+
+    // loads spilled buckets back into the node, so the transaction only has
+    // to deal with inline entries. they're spilled again when it's inserted
+    async fn unspill(
+        &self,
+        cache: &NodeCache<S, C, Node<T>>,
+        mut node: Node<T>,
+    ) -> Result<Node<T>> {
+        for elt in node.data.iter_mut() {
+            if let Element::Spilled(spilled) = elt {
+                let mut bucket = Vec::with_capacity(spilled.len());
+                for entry in spilled.iter() {
+                    let value = self.buffer.load(cache, &entry.value).await?;
+                    bucket.push(Entry::new(entry.key.clone(), value));
+                }
+                *elt = Element::Bucket(bucket);
+            }
+        }
+        Ok(node)
+    }
+
     // retrace the path traveled backwards, "bubbling up" the changes
     async fn bubble_up(&mut self, full_path: FullPath<T>) -> Result<Cid> {
         let FullPath {
@@ -565,57 +1258,27 @@ where
             path,
         } = full_path;
         let path = path.into_iter().rev();
-        let mut cid = self.cache.insert(block, Some(&self.tmp)).await?;
+        let mut cid = self.buffer.insert_spilling(block)?;
         for elt in path {
             let PathNode { idx, block: node } = elt;
             block = node;
             block.data[idx] = Element::HashNode(cid);
-            cid = self.cache.insert(block, Some(&self.tmp)).await?;
+            cid = self.buffer.insert_spilling(block)?;
         }
         Ok(cid)
     }
 
-    pub async fn get(&mut self, key: &[u8]) -> Result<Option<T>> {
-        // TODO calculate correct hash
-        let hash = hash(&key);
-
-        let mut current = self.cache.get(&self.root, Some(&self.tmp)).await?;
-        validate_or_empty!(current);
-        for index in hash.iter() {
-            let bit = get_bit(&current.map, *index);
-            if let Bit::Zero = bit {
-                return Ok(None);
-            }
-            let data_index = popcount(&current.map, *index) as usize;
-            let Node { mut data, .. } = current;
-            current = match data.remove(data_index) {
-                Element::HashNode(cid) => self.cache.get(&cid, Some(&self.tmp)).await?,
-                Element::Bucket(bucket) => {
-                    for elt in bucket {
-                        if &*elt.key == key {
-                            let Entry { value, .. } = elt;
-                            return Ok(Some(value));
-                        }
-                    }
-                    return Ok(None);
-                }
-            };
-            validate!(current);
-        }
-        Ok(None)
-    }
-
     pub async fn insert(&mut self, key: Box<[u8]>, value: T) -> Result<()> {
         let mut queue = Queue::new();
         let hash_len = hash(&key).len();
         queue.add(Entry::new(key, value));
         let mut path = Path::new();
         // start from root going down
-        let mut current = self.cache.get(&self.root, Some(&self.tmp)).await?;
+        let mut current = self.node(&self.root).await?;
+        validate!(current);
         for lvl in 0..hash_len {
-            // validate_or_empty!(current);
             use InsertError::{Id, Overflow};
-            match current.insert_all(lvl, &mut queue, self.bucket_size) {
+            match current.insert_all(lvl, &mut queue, self.hamt.bucket_size) {
                 Ok(_) => {
                     let full_path = path.record_last(current);
                     // recalculate cids recursively
@@ -625,7 +1288,7 @@ where
                 Err(Id(entry, cid, data_index)) => {
                     path.record(current, data_index);
                     queue.add(entry);
-                    current = self.cache.get(&cid, Some(&self.tmp)).await?;
+                    current = self.node(&cid).await?;
                     validate!(current);
                 }
                 Err(Overflow(overflow, data_index)) => {
@@ -637,7 +1300,7 @@ where
                 }
             }
         }
-        todo!("Output error due to maximum collision depth reached");
+        Err(collision_depth().into())
     }
 
     pub async fn remove(&mut self, key: &[u8]) -> Result<()> {
@@ -646,38 +1309,459 @@ where
         let hash_len = hash.len();
         let mut path = Path::new();
         // start from root going down
-        let mut current = self.cache.get(&self.root, Some(&self.tmp)).await?;
-        // validate_or_empty!(current);
+        let mut current = self.node(&self.root).await?;
+        validate!(current);
         for lvl in 0..hash_len {
             match current.remove(lvl, key, &hash) {
                 Ok(_) => {
                     let mut full_path = path.record_last(current);
-                    full_path.full_reduce(self.bucket_size);
+                    full_path.full_reduce(self.hamt.bucket_size);
                     // recalculate cids recursively
                     self.root = self.bubble_up(full_path).await?;
                     return Ok(());
                 }
                 Err(Id(cid, data_index)) => {
                     path.record(current, data_index);
-                    current = self.cache.get(&cid, Some(&self.tmp)).await?;
+                    current = self.node(&cid).await?;
                     validate!(current);
                 }
             }
         }
-        todo!("Output error due to maximum collision depth reached");
+        Err(collision_depth().into())
     }
 
-    pub async fn flush<A: AsRef<[u8]> + Send + Sync>(&mut self, alias: A) -> Result<()> {
-        self.cache.alias(alias, Some(self.root())).await?;
-        self.tmp = self.cache.temp_pin().await?;
-        self.cache.flush().await?;
+    // nodes of other are looked up in the buffer first, which holds the nodes
+    // built from buckets while merging
+    async fn other_node(&self, other: &Hamt<S, T, C>, cid: &Cid) -> Result<Node<T>> {
+        let node = match self.buffer.get(cid) {
+            Some(node) => node.clone(),
+            None => other.cache.get(cid, Some(&other.tmp)).await?,
+        };
+        self.unspill(&other.cache, node).await
+    }
+
+    // inserts entries into the subtree of node at level, the way `insert` does
+    fn insert_into(
+        &mut self,
+        mut node: Node<T>,
+        level: usize,
+        entries: Vec<Entry<T>>,
+    ) -> NodeFuture<'_, T> {
+        Box::pin(async move {
+            use InsertError::{Id, Overflow};
+            for entry in entries {
+                match node.insert(level, entry.with_hash(), self.hamt.bucket_size) {
+                    Ok(()) => {}
+                    Err(Id(entry, cid, data_index)) => {
+                        let child = self.node(&cid).await?;
+                        let child = self.insert_into(child, level + 1, vec![entry]).await?;
+                        node.data[data_index] =
+                            Element::HashNode(self.buffer.insert_spilling(child)?);
+                    }
+                    Err(Overflow(overflow, data_index)) => {
+                        let child = self.insert_into(Node::new(), level + 1, overflow).await?;
+                        node.data[data_index] =
+                            Element::HashNode(self.buffer.insert_spilling(child)?);
+                    }
+                }
+            }
+            Ok(node)
+        })
+    }
+
+    // collapses a merged child node into a bucket if it's small enough, the
+    // way `full_reduce` does
+    fn reduce(&mut self, mut node: Node<T>) -> Result<Option<Element<T>>> {
+        if node.has_children() || node.more_entries_than(self.hamt.bucket_size) {
+            return Ok(Some(Element::HashNode(self.buffer.insert_spilling(node)?)));
+        }
+        let entries = node.extract();
+        if entries.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(Element::Bucket(entries)))
+        }
+    }
+
+    // combines two nodes at the same level, identical subtrees and subtrees
+    // that only exist on one side are reused without loading them
+    fn merge<'b>(
+        &'b mut self,
+        other: &'b Hamt<S, T, C>,
+        mut node: Node<T>,
+        other_node: Node<T>,
+        level: usize,
+        op: SetOp,
+    ) -> NodeFuture<'b, T> {
+        Box::pin(async move {
+            for bit in 0..=255 {
+                let left = node.get(bit).cloned();
+                let right = other_node.get(bit).cloned();
+                let element = match (op, left, right) {
+                    (_, None, None) => continue,
+                    (SetOp::Union, left, None) => left,
+                    (SetOp::Union, None, right) => right,
+                    (SetOp::Intersection, None, _) | (SetOp::Intersection, _, None) => None,
+                    (SetOp::Difference, None, _) => None,
+                    (SetOp::Difference, left, None) => left,
+                    (op, Some(Element::HashNode(a)), Some(Element::HashNode(b))) if a == b => {
+                        match op {
+                            SetOp::Difference => None,
+                            _ => Some(Element::HashNode(a)),
+                        }
+                    }
+                    (op, Some(Element::Bucket(a)), Some(Element::Bucket(b))) => {
+                        let contains = |entry: &Entry<T>| b.iter().any(|elt| elt.key == entry.key);
+                        let entries: Vec<_> = match op {
+                            SetOp::Union => {
+                                let mut entries = a.clone();
+                                entries.extend(
+                                    b.iter()
+                                        .filter(|elt| !a.iter().any(|entry| entry.key == elt.key))
+                                        .cloned(),
+                                );
+                                entries
+                            }
+                            SetOp::Intersection => {
+                                a.into_iter().filter(|entry| contains(entry)).collect()
+                            }
+                            SetOp::Difference => {
+                                a.into_iter().filter(|entry| !contains(entry)).collect()
+                            }
+                        };
+                        if entries.len() > self.hamt.bucket_size {
+                            let child = self.insert_into(Node::new(), level + 1, entries).await?;
+                            self.reduce(child)?
+                        } else if entries.is_empty() {
+                            None
+                        } else {
+                            Some(Element::Bucket(entries))
+                        }
+                    }
+                    (op, Some(left), Some(right)) => {
+                        let left = match left {
+                            Element::HashNode(cid) => self.node(&cid).await?,
+                            Element::Bucket(entries) => {
+                                self.insert_into(Node::new(), level + 1, entries).await?
+                            }
+                            Element::Spilled(_) => unreachable!(),
+                        };
+                        let right = match right {
+                            Element::HashNode(cid) => self.other_node(other, &cid).await?,
+                            Element::Bucket(entries) => {
+                                self.insert_into(Node::new(), level + 1, entries).await?
+                            }
+                            Element::Spilled(_) => unreachable!(),
+                        };
+                        let child = self.merge(other, left, right, level + 1, op).await?;
+                        self.reduce(child)?
+                    }
+                };
+                match element {
+                    Some(element) => node.set(bit, element),
+                    None => node.unset(bit),
+                }
+            }
+            Ok(node)
+        })
+    }
+
+    // both hamts need to share the same store and bucket size
+    pub(crate) async fn merge_with(&mut self, other: &Hamt<S, T, C>, op: SetOp) -> Result<()> {
+        let node = self.node(&self.root).await?;
+        let other_node = self.other_node(other, &other.root).await?;
+        let mut node = self.merge(other, node, other_node, 0, op).await?;
+        node.unset_empty();
+        self.root = self.buffer.insert_spilling(node)?;
+        Ok(())
+    }
+
+    pub async fn commit(self) -> Result<()> {
+        let Self { hamt, root, buffer } = self;
+        buffer.commit(&hamt.cache, &hamt.tmp, &root).await?;
+        hamt.root = root;
+        Ok(())
+    }
+
+    pub fn rollback(self) {}
+}
+
+// holds on to the cache and temp pin instead of borrowing the hamt, so the
+// iterator can outlive it. the temp pin keeps the root, and with it the rest
+// of the tree, from being collected
+pub struct HamtIter<S: Store, T: DagCbor, C = DagCborCodec> {
+    cache: Arc<NodeCache<S, C, Node<T>>>,
+    _tmp: S::TempPin,
+    concurrency: usize,
+    nodes: Traversal<S::Params, ()>,
+    entries: Vec<Entry<T>>,
+}
+
+impl<S, T, C> HamtIter<S, T, C>
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+    T: DagCbor + Clone + Send + Sync,
+{
+    #[allow(clippy::should_implement_trait)]
+    pub async fn next(&mut self) -> Result<Option<(Box<[u8]>, T)>> {
+        while self.entries.is_empty() {
+            let node = match self.cache.next(&mut self.nodes).await {
+                Some((_, (), node)) => node?,
+                None => return Ok(None),
+            };
+            let spilled = node.spilled();
+            let mut loaded = vec![];
+            for value in self.cache.load_many(&spilled, self.concurrency).await {
+                loaded.push(value?);
+            }
+            let mut loaded = loaded.into_iter();
+            for elt in node.data.into_iter().rev() {
+                match elt {
+                    Element::HashNode(cid) => self.nodes.push(cid, ()),
+                    Element::Bucket(bucket) => self.entries.extend(bucket.into_iter().rev()),
+                    Element::Spilled(bucket) => {
+                        for Entry { key, .. } in bucket.into_iter().rev() {
+                            let value = loaded.next_back().expect("loaded");
+                            self.entries.push(Entry::new(key, value));
+                        }
+                    }
+                }
+            }
+        }
+        Ok(self.entries.pop().map(|Entry { key, value }| (key, value)))
+    }
+}
+
+async fn verify_nodes<S, T, C>(
+    cache: &NodeCache<S, C, Node<T>>,
+    root: &Cid,
+    concurrency: usize,
+    bucket_size: usize,
+) -> Report
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+    T: DagCbor + Clone + Send + Sync,
+{
+    let mut verifier = Verifier {
+        codec: cache.codec(),
+        bucket_size,
+        report: Report::default(),
+        _marker: PhantomData,
+    };
+    // the verifier turns errors into problems, the walk doesn't fail
+    let walk = visit::traverse(cache, root, concurrency, &mut verifier, Node::items);
+    walk.await.ok();
+    verifier.report
+}
+
+// checks the nodes of a hamt while it's walked
+struct Verifier<T, C> {
+    codec: C,
+    bucket_size: usize,
+    report: Report,
+    _marker: PhantomData<T>,
+}
+
+impl<T, C, P> Walker<Node<T>, T, P> for Verifier<T, C>
+where
+    T: DagCbor,
+    C: NodeCodec,
+    P: StoreParams,
+{
+    fn prefetch(&self) -> bool {
+        true
+    }
+
+    fn enter(&mut self, _cid: &Cid, _depth: usize) -> bool {
+        true
+    }
+
+    fn node(&mut self, block: &Block<P>, node: &Node<T>, depth: usize) -> Result<bool> {
+        let report = &mut self.report;
+        let cid = *block.cid();
+        report.blocks += 1;
+        let bits = node.map.iter().map(|byte| byte.count_ones() as usize).sum();
+        if bits != node.data.len() {
+            report.problems.push(Problem::Bitmap {
+                cid,
+                bits,
+                len: node.data.len(),
+            });
+        }
+        let mut entries = 0;
+        for elt in node.data.iter() {
+            let len = match elt {
+                Element::HashNode(_) => continue,
+                Element::Bucket(bucket) => bucket.len(),
+                Element::Spilled(bucket) => bucket.len(),
+            };
+            if len == 0 || len > self.bucket_size {
+                report.problems.push(Problem::Bucket {
+                    cid,
+                    len,
+                    bucket_size: self.bucket_size,
+                });
+            }
+            entries += len;
+        }
+        // see full_reduce
+        if depth > 0 && !node.has_children() && entries <= self.bucket_size {
+            report.problems.push(Problem::Unreduced(cid));
+        }
+        Ok(true)
+    }
+
+    fn spilled(&mut self, _key: Option<&[u8]>, block: &Block<P>) -> Result<()> {
+        match self.codec.decode_node::<T>(block.data()) {
+            Ok(_) => self.report.blocks += 1,
+            Err(err) => {
+                let problem = Problem::Unreadable(*block.cid(), err.to_string());
+                self.report.problems.push(problem);
+            }
+        }
+        Ok(())
+    }
+
+    fn unreadable(&mut self, cid: &Cid, err: Error) -> Result<()> {
+        let problem = Problem::Unreadable(*cid, err.to_string());
+        self.report.problems.push(problem);
         Ok(())
     }
 }
 
+pub struct HamtSnapshot<S: Store, T: DagCbor, C = DagCborCodec> {
+    cache: Arc<NodeCache<S, C, Node<T>>>,
+    root: Cid,
+    tmp: S::TempPin,
+}
+
+// the clones share the temp pin, the blocks stay pinned until the last one is
+// dropped
+impl<S: Store, T: DagCbor, C> Clone for HamtSnapshot<S, T, C> {
+    fn clone(&self) -> Self {
+        Self {
+            cache: self.cache.clone(),
+            root: self.root,
+            tmp: self.tmp.clone(),
+        }
+    }
+}
+
+impl<S, T, C> HamtSnapshot<S, T, C>
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+    T: DagCbor + Clone + Send + Sync,
+{
+    pub fn root(&self) -> &Cid {
+        &self.root
+    }
+
+    pub async fn get(&self, key: &[u8]) -> Result<Option<T>> {
+        get(&self.cache, &self.tmp, &self.root, key).await
+    }
+}
+
+async fn get<S, T, C>(
+    cache: &NodeCache<S, C, Node<T>>,
+    tmp: &S::TempPin,
+    root: &Cid,
+    key: &[u8],
+) -> Result<Option<T>>
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+    T: DagCbor + Clone + Send + Sync,
+{
+    // TODO calculate correct hash
+    let hash = hash(key);
+
+    let mut current = cache.get(root, Some(tmp)).await?;
+    validate!(current);
+    for index in hash.iter() {
+        let bit = get_bit(&current.map, *index);
+        if let Bit::Zero = bit {
+            return Ok(None);
+        }
+        let data_index = popcount(&current.map, *index) as usize;
+        let Node { mut data, .. } = current;
+        current = match data.remove(data_index) {
+            Element::HashNode(cid) => cache.get(&cid, Some(tmp)).await?,
+            Element::Bucket(bucket) => {
+                for elt in bucket {
+                    if &*elt.key == key {
+                        let Entry { value, .. } = elt;
+                        return Ok(Some(value));
+                    }
+                }
+                return Ok(None);
+            }
+            Element::Spilled(bucket) => {
+                for elt in bucket {
+                    if &*elt.key == key {
+                        return Ok(Some(cache.load(&elt.value).await?));
+                    }
+                }
+                return Ok(None);
+            }
+        };
+        validate!(current);
+    }
+    Ok(None)
+}
+
+// checks a proof made by Hamt::prove against a trusted root. returns None if
+// the proof doesn't hold, otherwise the key's value or None if the proof shows
+// that the key is absent
+pub fn verify_hamt_proof<P, T>(root: &Cid, key: &[u8], proof: &[Block<P>]) -> Option<Option<T>>
+where
+    P: StoreParams,
+    T: DagCbor + Clone,
+{
+    let mut blocks = proof.iter();
+    let mut cid = *root;
+    for index in hash(key).iter() {
+        let node: Node<T> = next_block(&mut blocks, &cid)?;
+        let bits: usize = node.map.iter().map(|byte| byte.count_ones() as usize).sum();
+        if node.map.len() != MAP_LEN || bits != node.data.len() {
+            return None;
+        }
+        let value = match node.get(*index) {
+            // absent keys either have no bit set
+            None => None,
+            Some(Element::HashNode(child)) => {
+                cid = *child;
+                continue;
+            }
+            // or are missing from the bucket at their position
+            Some(Element::Bucket(bucket)) => bucket
+                .iter()
+                .find(|entry| &*entry.key == key)
+                .map(|entry| entry.value.clone()),
+            Some(Element::Spilled(bucket)) => {
+                match bucket.iter().find(|entry| &*entry.key == key) {
+                    Some(entry) => Some(next_block(&mut blocks, &entry.value)?),
+                    None => None,
+                }
+            }
+        };
+        return if blocks.next().is_none() {
+            Some(value)
+        } else {
+            None
+        };
+    }
+    None
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cache::tests::RemoteStore;
+    use crate::header::UnsupportedVersion;
+    use crate::list::{List, ListConfig};
     use async_std::task;
     use libipld::mem::MemStore;
     use libipld::multihash::Code;
@@ -748,6 +1832,7 @@ mod tests {
         Node {
             map: Box::new([0_u8]),
             data: vec![],
+            header: None,
         }
     }
 
@@ -763,8 +1848,8 @@ mod tests {
         let hamt = dummy_hamt().await;
         assert_eq!(
             &[
-                17, 5, 205, 113, 186, 135, 108, 41, 45, 228, 103, 3, 117, 148, 111, 12, 194, 34,
-                144, 30, 201, 157, 222, 81, 41, 154, 114, 30, 207, 222, 150, 53
+                148, 241, 130, 11, 138, 179, 24, 70, 62, 5, 111, 175, 244, 108, 206, 187, 71, 2,
+                67, 199, 61, 49, 173, 154, 186, 25, 111, 3, 95, 191, 85, 27
             ],
             hamt.root.hash().digest()
         );
@@ -852,8 +1937,9 @@ mod tests {
         node2_clone.set(0, Element::Bucket(vec![]));
         path.record(node2_clone, 0);
         let full_path = path.record_last(node1_clone);
-        let cid = hamt_clone.bubble_up(full_path).await.unwrap();
-        hamt_clone.root = cid;
+        let mut tx = hamt_clone.transaction();
+        tx.root = tx.bubble_up(full_path).await.unwrap();
+        tx.commit().await.unwrap();
 
         assert_eq!(hamt.root, hamt_clone.root);
         let block = hamt.cache.get(&hamt.root, None).await.unwrap();
@@ -888,6 +1974,7 @@ mod tests {
         hamt.insert(entry.key, entry.value).await.unwrap();
         let mut node = Node::new();
         let _ = node.insert(0, Entry::new([0, 0, 0], 0).with_hash(), 3);
+        node.header = hamt.header().await.unwrap();
         assert_eq!(node, hamt.cache.get(&hamt.root, None).await.unwrap());
         let mut hamt = dummy_hamt().await;
         let entry1 = Entry::new([0, 0, 0], 0);
@@ -903,8 +1990,8 @@ mod tests {
         assert_eq!(
             &hamt.root.hash().digest(),
             &[
-                10, 133, 110, 7, 1, 116, 103, 149, 130, 193, 198, 132, 161, 142, 33, 76, 89, 142,
-                81, 181, 60, 135, 167, 116, 140, 112, 168, 13, 40, 172, 223, 90
+                207, 60, 53, 201, 202, 53, 134, 66, 90, 20, 126, 167, 65, 66, 33, 50, 247, 120, 22,
+                11, 47, 178, 77, 131, 19, 0, 55, 170, 95, 145, 108, 122
             ]
         );
         assert!(node
@@ -981,6 +2068,77 @@ mod tests {
         assert_eq!(hamt.root, other.root);
         Ok(())
     }
+    #[async_std::test]
+    async fn test_hamt_snapshot() {
+        let mut hamt = dummy_hamt().await;
+        hamt.insert(vec![0, 0].into(), 0).await.unwrap();
+        let snapshot = hamt.snapshot().await.unwrap();
+        let root = *snapshot.root();
+        hamt.insert(vec![0, 1].into(), 1).await.unwrap();
+        hamt.remove(&[0, 0]).await.unwrap();
+        assert_ne!(snapshot.root(), hamt.root());
+        assert_eq!(snapshot.get(&[0, 0]).await.unwrap(), Some(0));
+        assert_eq!(snapshot.get(&[0, 1]).await.unwrap(), None);
+        assert_eq!(hamt.get(&[0, 0]).await.unwrap(), None);
+        assert_eq!(hamt.get(&[0, 1]).await.unwrap(), Some(1));
+
+        let clone = snapshot.clone();
+        drop(snapshot);
+        assert_eq!(clone.root(), &root);
+        assert_eq!(clone.get(&[0, 0]).await.unwrap(), Some(0));
+        assert_eq!(clone.get(&[0, 1]).await.unwrap(), None);
+    }
+
+    #[async_std::test]
+    async fn test_hamt_concurrent_readers() {
+        let mut hamt = dummy_hamt().await;
+        for i in 0..32u8 {
+            hamt.insert(vec![0, i].into(), i).await.unwrap();
+        }
+        let hamt = Arc::new(hamt);
+        let readers: Vec<_> = (0..16u8)
+            .map(|reader| {
+                let hamt = hamt.clone();
+                task::spawn(async move {
+                    for i in 0..32u8 {
+                        let i = (i + reader) % 32;
+                        assert_eq!(hamt.get(&[0, i]).await.unwrap(), Some(i));
+                    }
+                })
+            })
+            .collect();
+        for reader in readers {
+            reader.await;
+        }
+    }
+
+    #[async_std::test]
+    async fn test_hamt_transaction() {
+        let mut hamt = dummy_hamt().await;
+        hamt.insert(vec![0, 0].into(), 0).await.unwrap();
+        let root = *hamt.root();
+
+        let mut tx = hamt.transaction();
+        tx.insert(vec![0, 1].into(), 1).await.unwrap();
+        tx.insert(vec![0, 2].into(), 2).await.unwrap();
+        tx.remove(&[0, 0]).await.unwrap();
+        tx.rollback();
+        assert_eq!(hamt.root(), &root);
+        assert_eq!(hamt.get(&[0, 0]).await.unwrap(), Some(0));
+        assert_eq!(hamt.get(&[0, 1]).await.unwrap(), None);
+
+        let mut tx = hamt.transaction();
+        tx.insert(vec![0, 1].into(), 1).await.unwrap();
+        tx.insert(vec![0, 2].into(), 2).await.unwrap();
+        tx.remove(&[0, 0]).await.unwrap();
+        let new_root = *tx.root();
+        tx.commit().await.unwrap();
+        assert_eq!(hamt.root(), &new_root);
+        assert_eq!(hamt.get(&[0, 0]).await.unwrap(), None);
+        assert_eq!(hamt.get(&[0, 1]).await.unwrap(), Some(1));
+        assert_eq!(hamt.get(&[0, 2]).await.unwrap(), Some(2));
+    }
+
     #[async_std::test]
     async fn test_remove() {
         // first deletion test
@@ -1003,4 +2161,535 @@ mod tests {
         }
         assert_eq!(hamt.root, other.root);
     }
+
+    #[async_std::test]
+    async fn test_spill() -> Result<()> {
+        let store = MemStore::<DefaultParams>::default();
+        let config = HamtConfig::new(store.clone(), Code::Blake2b256);
+        let value = |i: u8| vec![i; DefaultParams::MAX_BLOCK_SIZE / 2].into_boxed_slice();
+        let mut hamt = Hamt::new(config).await?;
+        for i in 0..6 {
+            hamt.insert(Box::new([i]), value(i)).await?;
+        }
+        let root = hamt.cache.get(hamt.root(), None).await?;
+        assert!(root
+            .data
+            .iter()
+            .any(|elt| matches!(elt, Element::Spilled(_))));
+        for i in 0..6 {
+            assert_eq!(hamt.get(&[i]).await?, Some(value(i)));
+        }
+        let mut iter = hamt.iter();
+        let mut n = 0;
+        while let Some((key, elt)) = iter.next().await? {
+            assert_eq!(elt, value(key[0]));
+            n += 1;
+        }
+        assert_eq!(n, 6);
+
+        // spilled values are buffered like nodes until the transaction commits
+        let mut tx = hamt.transaction();
+        tx.insert(Box::new([5]), value(6)).await?;
+        tx.rollback();
+        let block = Block::<DefaultParams>::encode(DagCborCodec, Code::Blake2b256, &value(6))?;
+        assert!(!store.contains(block.cid()).await?);
+
+        hamt.insert(Box::new([5]), value(6)).await?;
+        hamt.remove(&[1]).await?;
+        assert!(store.contains(block.cid()).await?);
+        assert_eq!(hamt.get(&[5]).await?, Some(value(6)));
+        assert_eq!(hamt.get(&[1]).await?, None);
+        assert_eq!(hamt.get(&[2]).await?, Some(value(2)));
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_hamt_car() -> Result<()> {
+        let mut config = HamtConfig::new(MemStore::<DefaultParams>::default(), Code::Blake2b256);
+        config.set_bucket_size(1);
+        let mut hamt = Hamt::new(config).await?;
+        for i in 0..50u8 {
+            hamt.insert(Box::new([i]), i as u64).await?;
+        }
+        let mut car = vec![];
+        hamt.export_car(&mut car).await?;
+
+        let config = HamtConfig::new(MemStore::<DefaultParams>::default(), Code::Blake2b256);
+        let imported = Hamt::<_, u64>::import_car(config, &car[..]).await?;
+        assert_eq!(imported.root(), hamt.root());
+        for i in 0..50u8 {
+            assert_eq!(imported.get(&[i]).await?, Some(i as u64));
+        }
+
+        // the root block is written first, without it nothing is inserted.
+        // the keys collide so the root links to children
+        let mut config = HamtConfig::new(MemStore::<DefaultParams>::default(), Code::Blake2b256);
+        config.set_bucket_size(1);
+        let mut hamt = Hamt::new(config).await?;
+        for i in 0..50u8 {
+            hamt.insert(Box::new([i % 4, i]), i as u64).await?;
+        }
+        let mut car = vec![];
+        hamt.export_car(&mut car).await?;
+        let mut reader = &car[..];
+        let header = car::read_section(&mut reader)?.unwrap();
+        car::read_section(&mut reader)?;
+        let mut missing = vec![];
+        car::write_section(&mut missing, &header)?;
+        missing.extend_from_slice(reader);
+        let child = Cid::read_bytes(&car::read_section(&mut reader)?.unwrap()[..])?;
+        let store = MemStore::<DefaultParams>::default();
+        let config = HamtConfig::new(store.clone(), Code::Blake2b256);
+        assert!(Hamt::<_, u64>::import_car(config, &missing[..])
+            .await
+            .is_err());
+        assert!(store.get(&child).await.is_err());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_hamt_verify() -> Result<()> {
+        let store = MemStore::<DefaultParams>::default();
+        let mut config = HamtConfig::new(store.clone(), Code::Blake2b256);
+        config.set_bucket_size(1);
+        let mut hamt = Hamt::new(config).await?;
+        for i in 0..50u8 {
+            hamt.insert(Box::new([i % 4, i]), i).await?;
+        }
+        let report = hamt.verify().await;
+        assert!(report.is_ok());
+        assert!(report.blocks > 1);
+
+        let mut child = Node::new();
+        set_bit(&mut child.map, 0, One);
+        child.data = vec![Element::Bucket(vec![Entry::new([0], 0)])];
+        let child = hamt.cache.insert(child, None).await?;
+        let mut root = Node::new();
+        set_bit(&mut root.map, 0, One);
+        root.data = vec![Element::HashNode(child), Element::Bucket(vec![])];
+        root.header = hamt.header().await?;
+        let root = hamt.cache.insert(root, None).await?;
+        let hamt = Hamt::<_, u8>::open(HamtConfig::new(store, Code::Blake2b256), root).await?;
+        let report = hamt.verify().await;
+        assert_eq!(
+            report.problems,
+            vec![
+                Problem::Bitmap {
+                    cid: root,
+                    bits: 1,
+                    len: 2
+                },
+                Problem::Bucket {
+                    cid: root,
+                    len: 0,
+                    bucket_size: 1
+                },
+                Problem::Unreduced(child),
+            ]
+        );
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_hamt_malformed() -> Result<()> {
+        let store = MemStore::<DefaultParams>::default();
+        let hamt = Hamt::<_, u8>::new(HamtConfig::new(store.clone(), Code::Blake2b256)).await?;
+        let header = hamt.header().await?;
+        // a root with a bit set but no element for it
+        let mut root = Node::new();
+        set_bit(&mut root.map, 0, One);
+        root.header = header.clone();
+        let root = hamt.cache.insert(root, None).await?;
+        // a child with more elements than bits set
+        let mut child = Node::new();
+        set_bit(&mut child.map, 1, One);
+        child.data = vec![
+            Element::Bucket(vec![Entry::new([0, 1], 0)]),
+            Element::Bucket(vec![]),
+        ];
+        let child = hamt.cache.insert(child, None).await?;
+        let mut parent = Node::new();
+        set_bit(&mut parent.map, 0, One);
+        parent.data = vec![Element::HashNode(child)];
+        parent.header = header;
+        let parent = hamt.cache.insert(parent, None).await?;
+
+        for root in [root, parent].iter() {
+            let config = HamtConfig::new(store.clone(), Code::Blake2b256);
+            let mut hamt = Hamt::<_, u8>::open(config, *root).await?;
+            assert!(hamt.get(&[0, 1]).await.is_err());
+            assert!(hamt.insert(Box::new([0, 1]), 1).await.is_err());
+            assert!(hamt.remove(&[0, 1]).await.is_err());
+            assert_eq!(hamt.root(), root);
+        }
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_hamt_proof() -> Result<()> {
+        let mut config = HamtConfig::new(MemStore::<DefaultParams>::default(), Code::Blake2b256);
+        config.set_bucket_size(1);
+        let mut hamt = Hamt::new(config).await?;
+        for i in 0..50u8 {
+            hamt.insert(Box::new([i % 4, i]), i).await?;
+        }
+        let root = *hamt.root();
+        for i in 0..50u8 {
+            let proof = hamt.prove(&[i % 4, i]).await?;
+            assert_eq!(verify_hamt_proof(&root, &[i % 4, i], &proof), Some(Some(i)));
+        }
+
+        // absent because the bit at the key's position isn't set
+        let proof = hamt.prove(&[9]).await?;
+        assert_eq!(proof.len(), 1);
+        assert_eq!(verify_hamt_proof::<_, u8>(&root, &[9], &proof), Some(None));
+        // absent because the bucket at the key's position holds another key
+        let proof = hamt.prove(&[0, 4, 1]).await?;
+        assert!(matches!(
+            hamt.cache.decode::<Node<u8>>(proof.last().unwrap())?.get(4),
+            Some(Element::Bucket(_))
+        ));
+        assert_eq!(
+            verify_hamt_proof::<_, u8>(&root, &[0, 4, 1], &proof),
+            Some(None)
+        );
+
+        let proof = hamt.prove(&[0, 4]).await?;
+        assert_eq!(verify_hamt_proof::<_, u8>(&root, &[1, 5], &proof), None);
+        assert_eq!(
+            verify_hamt_proof::<_, u8>(proof[1].cid(), &[0, 4], &proof),
+            None
+        );
+        assert_eq!(
+            verify_hamt_proof::<_, u8>(&root, &[0, 4], &proof[..1]),
+            None
+        );
+
+        // spilled values come with their own block
+        let config = HamtConfig::new(MemStore::<DefaultParams>::default(), Code::Blake2b256);
+        let value = |i: u8| vec![i; DefaultParams::MAX_BLOCK_SIZE / 2].into_boxed_slice();
+        let mut hamt = Hamt::new(config).await?;
+        for i in 0..6 {
+            hamt.insert(Box::new([i]), value(i)).await?;
+        }
+        let proof = hamt.prove(&[5]).await?;
+        assert_eq!(proof.len(), 2);
+        assert_eq!(
+            verify_hamt_proof(hamt.root(), &[5], &proof),
+            Some(Some(value(5)))
+        );
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_hamt_prefetch() -> Result<()> {
+        let remote = MemStore::<DefaultParams>::default();
+        let mut config = HamtConfig::new(remote.clone(), Code::Blake2b256);
+        config.set_bucket_size(1);
+        let mut hamt = Hamt::new(config).await?;
+        for i in 0..50u8 {
+            hamt.insert(Box::new([i % 4, i]), i).await?;
+        }
+
+        let store = RemoteStore::new(remote);
+        let config = HamtConfig::new(store.clone(), Code::Blake2b256);
+        let hamt = Hamt::<_, u8>::open(config, *hamt.root()).await?;
+        assert_eq!(store.fetched(), 1);
+        let keys: Vec<_> = (0..8u8).map(|i| [i % 4, i]).collect();
+        let root = hamt.cache.get(hamt.root(), None).await?;
+        let child = match root.get(0) {
+            Some(Element::HashNode(child)) => *child,
+            _ => unreachable!(),
+        };
+        assert_eq!(hamt.missing(&keys[0]).await?, vec![child]);
+        hamt.prefetch(&keys).await?;
+        assert_eq!(store.fetched(), 4);
+        for (i, key) in keys.iter().enumerate() {
+            assert!(hamt.missing(key).await?.is_empty());
+            assert_eq!(hamt.get(key).await?, Some(i as u8));
+        }
+        assert_eq!(store.fetched(), 0);
+
+        let store = RemoteStore::new(store.remote);
+        let config = HamtConfig::new(store.clone(), Code::Blake2b256);
+        let hamt = Hamt::<_, u8>::open(config, *hamt.root()).await?;
+        store.forget();
+        assert!(hamt.prefetch(&keys).await.is_err());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_hamt_diff() -> Result<()> {
+        let remote = MemStore::<DefaultParams>::default();
+        let mut config = HamtConfig::new(remote.clone(), Code::Blake2b256);
+        config.set_bucket_size(1);
+        let mut hamt = Hamt::new(config).await?;
+        for i in 0..200u8 {
+            hamt.insert(Box::new([i % 4, i]), i).await?;
+        }
+        let root = *hamt.root();
+        hamt.insert(Box::new([1, 1]), 100).await?;
+        hamt.remove(&[2, 2]).await?;
+        hamt.insert(Box::new([0, 201]), 201).await?;
+        let other_root = *hamt.root();
+
+        let store = RemoteStore::new(remote);
+        let config = HamtConfig::new(store.clone(), Code::Blake2b256);
+        let hamt = Hamt::<_, u8>::open(config, root).await?;
+        let config = HamtConfig::new(store.clone(), Code::Blake2b256);
+        let other = Hamt::<_, u8>::open(config, other_root).await?;
+        store.fetched();
+        let changes = hamt.diff(&other).await?;
+        let key = |key: [u8; 2]| key.to_vec().into_boxed_slice();
+        assert_eq!(
+            changes,
+            vec![
+                (key([0, 201]), None, Some(201)),
+                (key([1, 1]), Some(1), Some(100)),
+                (key([2, 2]), Some(2), None),
+            ]
+        );
+        // the subtrees both share were never loaded
+        store.fetched();
+        let mut iter = hamt.iter();
+        while iter.next().await?.is_some() {}
+        assert!(store.fetched() > 0);
+        assert!(hamt.diff(&hamt).await?.is_empty());
+
+        // the same entries with a different bucket size
+        let mut config = HamtConfig::new(store, Code::Blake2b256);
+        config.set_bucket_size(3);
+        let mut third = Hamt::new(config).await?;
+        for i in 0..200u8 {
+            third.insert(Box::new([i % 4, i]), i).await?;
+        }
+        assert_ne!(third.root(), hamt.root());
+        assert!(hamt.diff(&third).await?.is_empty());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_dag_json() -> Result<()> {
+        use libipld::codec::Codec;
+        use libipld::ipld::Ipld;
+        use libipld::json::DagJsonCodec;
+
+        let store = MemStore::<DefaultParams>::default();
+        let mut config = HamtConfig::with_codec(store.clone(), DagJsonCodec, Code::Blake2b256);
+        config.set_bucket_size(1);
+        let mut hamt = Hamt::new(config).await?;
+        for i in 0..20u8 {
+            hamt.insert(Box::new([i]), i as u64).await?;
+        }
+        assert_eq!(hamt.root().codec(), u64::from(DagJsonCodec));
+        let block = store.get(hamt.root()).await?;
+        let _: Ipld = DagJsonCodec.decode(block.data())?;
+
+        let config = HamtConfig::with_codec(store, DagJsonCodec, Code::Blake2b256);
+        let hamt = Hamt::<_, u64, _>::open(config, *hamt.root()).await?;
+        for i in 0..20u8 {
+            assert_eq!(hamt.get(&[i]).await?, Some(i as u64));
+        }
+        let mut iter = hamt.iter();
+        let mut n = 0;
+        while let Some((key, value)) = iter.next().await? {
+            assert_eq!(value, key[0] as u64);
+            n += 1;
+        }
+        assert_eq!(n, 20);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_hamt_concurrency() -> Result<()> {
+        let store = MemStore::<DefaultParams>::default();
+        let value = |i: u8| vec![i; i as usize % 4 * DefaultParams::MAX_BLOCK_SIZE / 8];
+        let mut config = HamtConfig::new(store.clone(), Code::Blake2b256);
+        config.set_bucket_size(2);
+        let mut hamt = Hamt::new(config).await?;
+        for i in 0..40u8 {
+            hamt.insert(Box::new([i % 4, i]), value(i)).await?;
+        }
+        let root = *hamt.root();
+
+        let mut walks = vec![];
+        for concurrency in &[1, 4, 16] {
+            let mut config = HamtConfig::new(store.clone(), Code::Blake2b256);
+            config.set_bucket_size(2);
+            config.set_concurrency(*concurrency);
+            let hamt = Hamt::<_, Vec<u8>>::open(config, root).await?;
+            let mut entries = vec![];
+            let mut iter = hamt.iter();
+            while let Some((key, found)) = iter.next().await? {
+                assert_eq!(found, value(key[1]));
+                entries.push(key);
+            }
+            assert_eq!(entries.len(), 40);
+            let report = hamt.verify().await;
+            assert!(report.is_ok());
+            let mut car = vec![];
+            hamt.export_car(&mut car).await?;
+            walks.push((entries, report.blocks, car));
+        }
+        assert_eq!(walks[0], walks[1]);
+        assert_eq!(walks[0], walks[2]);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_hamt_stats() -> Result<()> {
+        let store = MemStore::<DefaultParams>::default();
+        let mut config = HamtConfig::new(store.clone(), Code::Blake2b256);
+        config.set_bucket_size(1);
+        let mut hamt = Hamt::new(config).await?;
+        for i in 0..50u8 {
+            hamt.insert(Box::new([i % 4, i]), i).await?;
+        }
+        let stats = hamt.stats().await?;
+        assert!(stats.depth() > 1);
+        assert_eq!(stats.nodes[0], 1);
+        assert_eq!(stats.nodes.iter().sum::<u64>(), stats.blocks);
+        assert_eq!(stats.blocks, hamt.verify().await.blocks);
+        assert_eq!(stats.buckets, vec![0, 50]);
+        Ok(())
+    }
+
+    #[derive(Default)]
+    struct Recorder {
+        depth: usize,
+        entered: usize,
+        values: BTreeMap<Box<[u8]>, u8>,
+    }
+
+    impl Visitor<u8> for Recorder {
+        fn enter(&mut self, _: &Cid, depth: usize) -> bool {
+            assert_eq!(depth, self.depth);
+            self.depth += 1;
+            self.entered += 1;
+            true
+        }
+
+        fn exit(&mut self, _: &Cid, depth: usize) {
+            self.depth -= 1;
+            assert_eq!(depth, self.depth);
+        }
+
+        fn value(&mut self, key: Option<&[u8]>, value: &u8) {
+            self.values.insert(key.unwrap().into(), *value);
+        }
+    }
+
+    #[async_std::test]
+    async fn test_hamt_walk() -> Result<()> {
+        let store = MemStore::<DefaultParams>::default();
+        let mut config = HamtConfig::new(store.clone(), Code::Blake2b256);
+        config.set_bucket_size(1);
+        let mut hamt = Hamt::new(config).await?;
+        let mut expected = BTreeMap::new();
+        for i in 0..50u8 {
+            hamt.insert(Box::new([i % 4, i]), i).await?;
+            expected.insert(Box::from(&[i % 4, i][..]), i);
+        }
+        let mut recorder = Recorder::default();
+        hamt.walk(&mut recorder).await?;
+        assert_eq!(recorder.depth, 0);
+        assert_eq!(recorder.entered as u64, hamt.stats().await?.blocks);
+        assert_eq!(recorder.values, expected);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_hamt_versions() -> Result<()> {
+        let store = MemStore::<DefaultParams>::default();
+        let mut config = HamtConfig::new(store.clone(), Code::Blake2b256);
+        config.set_bucket_size(1);
+        let mut hamt = Hamt::new(config.clone()).await?;
+        for i in 0..20u8 {
+            hamt.insert(Box::new([i % 4, i]), i).await?;
+        }
+
+        let mut node = hamt.cache.get(hamt.root(), None).await?;
+        node.header = None;
+        let old = hamt.cache.insert(node, None).await?;
+        let new = Hamt::<_, u8>::migrate(config.clone(), old).await?;
+        assert_eq!(new, *hamt.root());
+        assert_eq!(Hamt::<_, u8>::migrate(config.clone(), new).await?, new);
+        // the nodes below the root hold 5 entries, with a bucket size of 5
+        // they would have been collapsed into buckets
+        let mut wrong = config.clone();
+        wrong.set_bucket_size(5);
+        assert!(Hamt::<_, u8>::migrate(wrong, old).await.is_err());
+        // the bucket size is taken from the header, not the config
+        let config = HamtConfig::new(store.clone(), Code::Blake2b256);
+        assert_eq!(
+            Hamt::<_, u8>::open(config.clone(), new).await?.bucket_size,
+            1
+        );
+        let err = Hamt::<_, u8>::open(config.clone(), old)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.downcast_ref::<UnsupportedVersion>().unwrap().0, 0);
+        assert!(err.to_string().contains("with migrate"));
+
+        // a list root can't be opened as a hamt
+        let list = List::from(ListConfig::new(store.clone(), Code::Blake2b256), 0..4u8).await?;
+        let err = Hamt::<_, u8>::open(config.clone(), *list.root()).await;
+        assert!(err.is_err());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_hamt_ipld() -> Result<()> {
+        use libipld::codec::Codec;
+        use libipld::json::DagJsonCodec;
+
+        let store = MemStore::<DefaultParams>::default();
+        let mut config = HamtConfig::new(store.clone(), Code::Blake2b256);
+        config.set_bucket_size(1);
+        let mut btree = BTreeMap::new();
+        for i in 0..20u8 {
+            btree.insert(vec![b'a' + i % 4, b'a' + i], i);
+        }
+        let hamt = Hamt::from(config.clone(), btree.clone()).await?;
+        // the keys are all utf-8, so the entries become a map
+        let ipld = hamt.to_ipld(1024).await?;
+        let map = btree
+            .iter()
+            .map(|(k, v)| {
+                (
+                    String::from_utf8(k.clone()).unwrap(),
+                    Ipld::Integer(*v as i128),
+                )
+            })
+            .collect();
+        assert_eq!(ipld, Ipld::StringMap(map));
+        assert!(hamt.to_ipld(20).await.is_err());
+        let loaded = Hamt::<_, u8>::from_ipld(config.clone(), &ipld).await?;
+        assert_eq!(loaded.root(), hamt.root());
+
+        // a binary key turns them into a list of entries
+        btree.insert(vec![0xff, 0], 20);
+        let binary = Hamt::from(config.clone(), btree.clone()).await?;
+        let ipld = binary.to_ipld(1024).await?;
+        let entries: Vec<Entry<u8>> = match &ipld {
+            Ipld::List(entries) => entries
+                .iter()
+                .map(plain::from_ipld)
+                .collect::<Result<_>>()?,
+            _ => unreachable!(),
+        };
+        let expected: Vec<_> = btree.iter().map(|(k, v)| Entry::new(&k[..], *v)).collect();
+        assert_eq!(entries, expected);
+        let loaded = Hamt::<_, u8>::from_ipld(config, &ipld).await?;
+        assert_eq!(loaded.root(), binary.root());
+
+        let mut json = vec![];
+        hamt.export_json(&mut json).await?;
+        match DagJsonCodec.decode(&json)? {
+            Ipld::List(entries) => assert_eq!(entries.len(), 20),
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,300 @@
+use crate::codec::{CollectionStore, NodeCodec};
+use crate::map::{Hamt, HamtConfig};
+use crate::set::{encode, HamtSet, HamtSetIter};
+use libipld::cbor::{DagCbor, DagCborCodec};
+use libipld::prelude::Store;
+use libipld::DagCbor;
+use libipld::{Cid, Result};
+use std::vec::IntoIter;
+
+// values of a key are kept inline until there are more than this many, then
+// they're moved into a nested set. inline values are sorted by their
+// encoding, so how the values of a key are stored only depends on them
+const INLINE_SIZE: usize = 8;
+
+#[derive(Clone, Debug, Eq, PartialEq, DagCbor)]
+enum Values<V: DagCbor> {
+    Inline(Vec<V>),
+    Linked(Cid),
+}
+
+pub struct HamtMulti<S, V, C = DagCborCodec>
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+    V: DagCbor,
+{
+    hamt: Hamt<S, Values<V>, C>,
+    // pointed at the nested set of whichever key is being read or written,
+    // so all of them share one cache. its temp pin only lasts for one write,
+    // after that the nested sets are reachable from the root of the hamt
+    sets: HamtSet<S, V, C>,
+}
+
+impl<S, V, C> HamtMulti<S, V, C>
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+    V: DagCbor + Clone + Send + Sync,
+{
+    pub async fn new(config: HamtConfig<S, C>) -> Result<Self> {
+        Ok(Self {
+            sets: HamtSet::nested(config.clone()).await?,
+            hamt: Hamt::new(config).await?,
+        })
+    }
+
+    pub async fn open(config: HamtConfig<S, C>, root: Cid) -> Result<Self> {
+        Ok(Self {
+            sets: HamtSet::nested(config.clone()).await?,
+            hamt: Hamt::open(config, root).await?,
+        })
+    }
+
+    pub fn root(&self) -> &Cid {
+        self.hamt.root()
+    }
+
+    pub async fn insert_value(&mut self, key: Box<[u8]>, value: V) -> Result<()> {
+        let values = match self.hamt.get(&key).await? {
+            None => Values::Inline(vec![value]),
+            Some(Values::Inline(mut values)) => {
+                let encoded = encode(&value)?;
+                let mut position = values.len();
+                for (i, other) in values.iter().enumerate() {
+                    let other = encode(other)?;
+                    if other == encoded {
+                        return Ok(());
+                    }
+                    if other > encoded {
+                        position = i;
+                        break;
+                    }
+                }
+                values.insert(position, value);
+                if values.len() > INLINE_SIZE {
+                    self.sets.clear().await?;
+                    self.sets.extend(values.into_iter()).await?;
+                    Values::Linked(*self.sets.root())
+                } else {
+                    Values::Inline(values)
+                }
+            }
+            Some(Values::Linked(root)) => {
+                self.sets.set_root(root).await?;
+                self.sets.insert(value).await?;
+                Values::Linked(*self.sets.root())
+            }
+        };
+        self.hamt.insert(key, values).await?;
+        self.sets.unpin().await
+    }
+
+    pub async fn remove_value(&mut self, key: &[u8], value: &V) -> Result<()> {
+        let values = match self.hamt.get(key).await? {
+            None => return Ok(()),
+            Some(Values::Inline(values)) => {
+                let encoded = encode(value)?;
+                let mut remaining = Vec::with_capacity(values.len());
+                for other in values {
+                    if encode(&other)? != encoded {
+                        remaining.push(other);
+                    }
+                }
+                if remaining.is_empty() {
+                    return self.hamt.remove(key).await;
+                }
+                Values::Inline(remaining)
+            }
+            Some(Values::Linked(root)) => {
+                self.sets.set_root(root).await?;
+                self.sets.remove(value).await?;
+                // the values are moved back inline once they fit
+                let mut values = vec![];
+                let mut iter = self.sets.iter();
+                while let Some(value) = iter.next().await? {
+                    values.push((encode(&value)?, value));
+                    if values.len() > INLINE_SIZE {
+                        break;
+                    }
+                }
+                if values.is_empty() {
+                    self.hamt.remove(key).await?;
+                    return self.sets.unpin().await;
+                }
+                if values.len() > INLINE_SIZE {
+                    Values::Linked(*self.sets.root())
+                } else {
+                    values.sort_by(|a, b| a.0.cmp(&b.0));
+                    Values::Inline(values.into_iter().map(|(_, value)| value).collect())
+                }
+            }
+        };
+        self.hamt.insert(key.into(), values).await?;
+        self.sets.unpin().await
+    }
+
+    pub async fn get_all(&self, key: &[u8]) -> Result<HamtMultiIter<S, V, C>> {
+        let (inline, set) = match self.hamt.get(key).await? {
+            None => (vec![], None),
+            Some(Values::Inline(values)) => (values, None),
+            Some(Values::Linked(root)) => (vec![], Some(self.sets.iter_at(root))),
+        };
+        Ok(HamtMultiIter {
+            inline: inline.into_iter(),
+            set,
+        })
+    }
+
+    // the nested sets are in the same store and linked from the hamt, so
+    // they're flushed and pinned by the alias along with it
+    pub async fn flush<A: AsRef<[u8]> + Send + Sync>(&mut self, alias: A) -> Result<()> {
+        self.hamt.flush(alias).await?;
+        self.sets.unpin().await
+    }
+}
+
+pub struct HamtMultiIter<S: Store, V, C = DagCborCodec> {
+    inline: IntoIter<V>,
+    set: Option<HamtSetIter<S, V, C>>,
+}
+
+impl<S, V, C> HamtMultiIter<S, V, C>
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+    V: DagCbor,
+{
+    #[allow(clippy::should_implement_trait)]
+    pub async fn next(&mut self) -> Result<Option<V>> {
+        if let Some(value) = self.inline.next() {
+            return Ok(Some(value));
+        }
+        match &mut self.set {
+            Some(set) => set.next().await,
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::tests::RemoteStore;
+    use libipld::mem::MemStore;
+    use libipld::multihash::Code;
+    use libipld::store::DefaultParams;
+    use std::collections::BTreeSet;
+
+    async fn get_all(
+        multi: &HamtMulti<MemStore<DefaultParams>, u64>,
+        key: &[u8],
+    ) -> Result<BTreeSet<u64>> {
+        let mut values = BTreeSet::new();
+        let mut iter = multi.get_all(key).await?;
+        while let Some(value) = iter.next().await? {
+            assert!(values.insert(value));
+        }
+        Ok(values)
+    }
+
+    #[async_std::test]
+    async fn test_multi() -> Result<()> {
+        let store = MemStore::<DefaultParams>::default();
+        let config = HamtConfig::new(store, Code::Blake2b256);
+        let mut multi = HamtMulti::new(config).await?;
+        for i in 0..100u64 {
+            multi.insert_value(Box::new([(i % 3) as u8]), i).await?;
+            multi.insert_value(Box::new([(i % 3) as u8]), i).await?;
+        }
+        for key in 0..3 {
+            let expected: BTreeSet<_> = (0..100).filter(|i| i % 3 == key).collect();
+            assert_eq!(get_all(&multi, &[key as u8]).await?, expected);
+        }
+        assert_eq!(get_all(&multi, &[3]).await?, BTreeSet::new());
+
+        for i in (0..100).filter(|i| i % 2 == 0) {
+            multi.remove_value(&[(i % 3) as u8], &i).await?;
+        }
+        for key in 0..3 {
+            let expected: BTreeSet<_> = (0..100).filter(|i| i % 3 == key && i % 2 == 1).collect();
+            assert_eq!(get_all(&multi, &[key as u8]).await?, expected);
+        }
+        for i in 0..100 {
+            multi.remove_value(&[(i % 3) as u8], &i).await?;
+        }
+        let empty = HamtMulti::<_, u64>::new(HamtConfig::new(
+            MemStore::<DefaultParams>::default(),
+            Code::Blake2b256,
+        ))
+        .await?;
+        assert_eq!(multi.root(), empty.root());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_multi_promotion() -> Result<()> {
+        let store = MemStore::<DefaultParams>::default();
+        let config = HamtConfig::new(store, Code::Blake2b256);
+        let mut multi = HamtMulti::new(config).await?;
+        for i in 0..INLINE_SIZE as u64 {
+            multi.insert_value(Box::new([0]), i).await?;
+        }
+        assert!(matches!(
+            multi.hamt.get(&[0]).await?,
+            Some(Values::Inline(_))
+        ));
+        multi
+            .insert_value(Box::new([0]), INLINE_SIZE as u64)
+            .await?;
+        assert!(matches!(
+            multi.hamt.get(&[0]).await?,
+            Some(Values::Linked(_))
+        ));
+        assert_eq!(
+            get_all(&multi, &[0]).await?,
+            (0..=INLINE_SIZE as u64).collect()
+        );
+
+        // drops back inline once the values fit
+        multi.remove_value(&[0], &0).await?;
+        assert!(matches!(
+            multi.hamt.get(&[0]).await?,
+            Some(Values::Inline(_))
+        ));
+        let store = MemStore::<DefaultParams>::default();
+        let config = HamtConfig::new(store, Code::Blake2b256);
+        let mut other = HamtMulti::new(config).await?;
+        for i in (1..=INLINE_SIZE as u64).rev() {
+            other.insert_value(Box::new([0]), i).await?;
+        }
+        assert_eq!(multi.root(), other.root());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_multi_open() -> Result<()> {
+        let remote = MemStore::<DefaultParams>::default();
+        let config = HamtConfig::new(remote.clone(), Code::Blake2b256);
+        let mut multi = HamtMulti::new(config).await?;
+        for i in 0..=INLINE_SIZE as u64 {
+            multi.insert_value(Box::new([0]), i).await?;
+        }
+
+        // opening only reads the root, no empty set is written
+        let store = RemoteStore::new(remote);
+        let config = HamtConfig::new(store.clone(), Code::Blake2b256);
+        let multi = HamtMulti::<_, u64>::open(config, *multi.root()).await?;
+        assert_eq!(store.fetched(), 1);
+        let config = HamtConfig::new(MemStore::<DefaultParams>::default(), Code::Blake2b256);
+        let empty = HamtSet::<_, u64>::new(config).await?;
+        assert!(!store.contains(empty.root()).await?);
+        let mut values = multi.get_all(&[0]).await?;
+        let mut len = 0;
+        while values.next().await?.is_some() {
+            len += 1;
+        }
+        assert_eq!(len, INLINE_SIZE + 1);
+        Ok(())
+    }
+}
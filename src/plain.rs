@@ -0,0 +1,73 @@
+use crate::codec::NodeCodec;
+use libipld::cbor::{DagCbor, DagCborCodec};
+use libipld::codec::Codec;
+use libipld::ipld::Ipld;
+use libipld::json::DagJsonCodec;
+use libipld::Result;
+use std::io::{Error, ErrorKind, Write};
+
+// counts the encoded size of the values put into a single Ipld value, so a
+// large collection isn't materialized by accident
+pub(crate) struct Guard {
+    size: usize,
+    max: usize,
+}
+
+impl Guard {
+    pub fn new(max: usize) -> Self {
+        Self { size: 0, max }
+    }
+
+    pub fn add(&mut self, size: usize) -> Result<()> {
+        self.size += size;
+        if self.size > self.max {
+            let msg = format!("collection is larger than {} bytes", self.max);
+            return Err(Error::new(ErrorKind::InvalidInput, msg).into());
+        }
+        Ok(())
+    }
+
+    pub fn convert<V: DagCbor>(&mut self, value: &V) -> Result<Ipld> {
+        let bytes = DagCborCodec.encode(value)?;
+        self.add(bytes.len())?;
+        DagCborCodec.decode(&bytes)
+    }
+}
+
+pub(crate) fn from_ipld<V: DagCbor>(ipld: &Ipld) -> Result<V> {
+    DagCborCodec.decode(&DagCborCodec.encode(ipld)?)
+}
+
+pub(crate) fn unexpected(expected: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("expected {}", expected))
+}
+
+// writes a json array one element at a time
+pub(crate) struct JsonArray<'a, W: Write> {
+    writer: &'a mut W,
+    empty: bool,
+}
+
+impl<'a, W: Write> JsonArray<'a, W> {
+    pub fn new(writer: &'a mut W) -> Result<Self> {
+        writer.write_all(b"[")?;
+        Ok(Self {
+            writer,
+            empty: true,
+        })
+    }
+
+    pub fn push<V: DagCbor>(&mut self, value: &V) -> Result<()> {
+        if !self.empty {
+            self.writer.write_all(b",")?;
+        }
+        self.empty = false;
+        self.writer.write_all(&DagJsonCodec.encode_node(value)?)?;
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<()> {
+        self.writer.write_all(b"]")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,814 @@
+use crate::cache::NodeCache;
+use crate::codec::{CollectionStore, NodeCodec};
+use crate::header::{self, Header, Kind, FORMAT_VERSION};
+use crate::tx::{Buffer, Links};
+use libipld::cbor::{DagCbor, DagCborCodec};
+use libipld::prelude::{Store, StoreParams};
+use libipld::DagCbor;
+use libipld::{Cid, Result};
+use std::collections::BTreeMap;
+use std::ops::{Bound, RangeBounds};
+use std::sync::Arc;
+
+// An entry is the last one in its node when the hash of its key, salted with
+// the level, falls below the threshold. Node boundaries therefore only depend
+// on the keys, which makes the shape of the tree independent of the order of
+// insertions and removals.
+fn is_boundary(key: &[u8], level: u32, fanout: usize) -> bool {
+    use libipld::multihash::{Sha2_256, StatefulHasher};
+    let mut hasher = Sha2_256::default();
+    hasher.update(&level.to_be_bytes());
+    hasher.update(key);
+    let digest = hasher.finalize();
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&digest.as_ref()[..4]);
+    u32::from_be_bytes(bytes) < u32::MAX / fanout as u32
+}
+
+fn to_owned(bound: Bound<&[u8]>) -> Bound<Box<[u8]>> {
+    match bound {
+        Bound::Included(key) => Bound::Included(key.into()),
+        Bound::Excluded(key) => Bound::Excluded(key.into()),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+// splits entries after every boundary, the last chunk may be open ended
+fn chunk<T: DagCbor>(entries: Vec<Entry<T>>, level: u32, fanout: usize) -> Vec<Vec<Entry<T>>> {
+    let mut chunks = vec![];
+    let mut chunk = vec![];
+    for entry in entries {
+        let boundary = is_boundary(&entry.key, level, fanout);
+        chunk.push(entry);
+        if boundary {
+            chunks.push(std::mem::take(&mut chunk));
+        }
+    }
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+    chunks
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, DagCbor)]
+struct Node<T: DagCbor> {
+    level: u32,
+    entries: Vec<Entry<T>>,
+    // only the root has one
+    #[ipld(default = None)]
+    header: Option<Header>,
+}
+
+impl<T: DagCbor> Node<T> {
+    fn new(level: u32, entries: Vec<Entry<T>>) -> Self {
+        Self {
+            level,
+            entries,
+            header: None,
+        }
+    }
+
+    // index of the entry that covers key, interior entries are keyed by the
+    // last key of their subtree
+    fn position(&self, key: &[u8]) -> std::result::Result<usize, usize> {
+        self.entries.binary_search_by(|entry| (*entry.key).cmp(key))
+    }
+
+    fn child(&self, key: &[u8]) -> Option<&Cid> {
+        let idx = match self.position(key) {
+            Ok(idx) => idx,
+            Err(idx) => usize::min(idx, self.entries.len().checked_sub(1)?),
+        };
+        self.entries[idx].data.cid()
+    }
+}
+
+impl<T: DagCbor> Links for Node<T> {
+    fn links(&self) -> Vec<Cid> {
+        self.entries
+            .iter()
+            .filter_map(|entry| entry.data.cid())
+            .copied()
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, DagCbor)]
+struct Entry<T: DagCbor> {
+    key: Box<[u8]>,
+    data: Data<T>,
+}
+
+impl<T: DagCbor> Entry<T> {
+    fn new(key: Box<[u8]>, data: Data<T>) -> Self {
+        Self { key, data }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, DagCbor)]
+enum Data<T: DagCbor> {
+    Value(T),
+    Link(Cid),
+}
+
+impl<T: DagCbor> Data<T> {
+    fn value(&self) -> Option<&T> {
+        if let Self::Value(value) = self {
+            Some(value)
+        } else {
+            None
+        }
+    }
+
+    fn cid(&self) -> Option<&Cid> {
+        if let Self::Link(cid) = self {
+            Some(cid)
+        } else {
+            None
+        }
+    }
+}
+
+// the contiguous run of nodes of one level that is being rewritten, starting
+// at entry `offset` of the run one level up
+struct Span<T: DagCbor> {
+    offset: usize,
+    nodes: Vec<Node<T>>,
+}
+
+impl<T: DagCbor + Clone> Span<T> {
+    fn len(&self) -> usize {
+        self.nodes.iter().map(|node| node.entries.len()).sum()
+    }
+
+    fn end(&self) -> usize {
+        self.offset + self.nodes.len()
+    }
+
+    fn entry(&self, mut idx: usize) -> &Entry<T> {
+        for node in &self.nodes {
+            if idx < node.entries.len() {
+                return &node.entries[idx];
+            }
+            idx -= node.entries.len();
+        }
+        panic!("index out of span");
+    }
+
+    fn entries(&self) -> Vec<Entry<T>> {
+        self.nodes
+            .iter()
+            .flat_map(|node| node.entries.iter().cloned())
+            .collect()
+    }
+}
+
+pub struct ProllyConfig<S, C = DagCborCodec>
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+{
+    store: S,
+    codec: C,
+    cache_size: usize,
+    hash: <S::Params as StoreParams>::Hashes,
+    fanout: usize,
+}
+
+impl<S> ProllyConfig<S>
+where
+    S: CollectionStore<DagCborCodec>,
+{
+    pub fn new(store: S, hash: <S::Params as StoreParams>::Hashes) -> Self {
+        Self::with_codec(store, DagCborCodec, hash)
+    }
+}
+
+impl<S, C> ProllyConfig<S, C>
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+{
+    pub fn with_codec(store: S, codec: C, hash: <S::Params as StoreParams>::Hashes) -> Self {
+        Self {
+            store,
+            codec,
+            cache_size: 64,
+            hash,
+            fanout: 32,
+        }
+    }
+
+    pub fn set_cache_size(&mut self, cache_size: usize) {
+        self.cache_size = cache_size;
+    }
+
+    // average number of entries per node, changing it changes the root cid
+    pub fn set_fanout(&mut self, fanout: usize) {
+        assert!(fanout > 1);
+        self.fanout = fanout;
+    }
+
+    fn fanout(&self) -> usize {
+        self.fanout
+    }
+
+    fn header(&self) -> Header {
+        Header {
+            kind: Kind::Prolly {
+                fanout: self.fanout as u64,
+            },
+            version: FORMAT_VERSION,
+            hash: self.hash.into(),
+        }
+    }
+
+    fn cache<T>(self) -> NodeCache<S, C, Node<T>>
+    where
+        T: DagCbor + Clone + Send + Sync,
+    {
+        NodeCache::new(self.store, self.codec, self.hash, self.cache_size)
+    }
+}
+
+pub struct Prolly<S: Store, T: DagCbor, C = DagCborCodec> {
+    cache: Arc<NodeCache<S, C, Node<T>>>,
+    hash: <S::Params as StoreParams>::Hashes,
+    root: Cid,
+    tmp: S::TempPin,
+    fanout: usize,
+}
+
+impl<S, T, C> Prolly<S, T, C>
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+    T: DagCbor + Clone + Send + Sync,
+{
+    pub async fn new(config: ProllyConfig<S, C>) -> Result<Self> {
+        Self::from(config, BTreeMap::<Box<[u8]>, T>::new()).await
+    }
+
+    // the fanout and hash of the tree are taken from its root, whatever the
+    // config has them set to
+    pub async fn open(config: ProllyConfig<S, C>, root: Cid) -> Result<Self> {
+        let cache = Arc::new(config.cache());
+        let tmp = cache.temp_pin().await?;
+        // warm up the cache and make sure it's available
+        let node = cache.get(&root, Some(&tmp)).await?;
+        let fanout = header::fanout(node.header.as_ref())?;
+        header::check(node.header.as_ref(), "prolly")?;
+        let hash = header::hash::<S::Params>(node.header.as_ref())?;
+        Ok(Self {
+            cache,
+            hash,
+            root,
+            tmp,
+            fanout,
+        })
+    }
+
+    pub fn root(&self) -> &Cid {
+        &self.root
+    }
+
+    pub async fn header(&self) -> Result<Option<Header>> {
+        Ok(self.cache.get(&self.root, Some(&self.tmp)).await?.header)
+    }
+
+    pub async fn from<I: Into<Box<[u8]>>>(
+        config: ProllyConfig<S, C>,
+        btree: BTreeMap<I, T>,
+    ) -> Result<Self> {
+        let fanout = config.fanout();
+        let header = config.header();
+        let hash = config.hash;
+        let cache = Arc::new(config.cache());
+        let tmp = cache.temp_pin().await?;
+
+        let mut entries: Vec<Entry<T>> = btree
+            .into_iter()
+            .map(|(key, value)| Entry::new(key.into(), Data::Value(value)))
+            .collect();
+        // the order of I doesn't need to match the order of the encoded keys
+        entries.sort_by(|a, b| a.key.cmp(&b.key));
+        let mut level = 0;
+        let root = loop {
+            let chunks = chunk(entries, level, fanout);
+            if chunks.len() <= 1 {
+                let mut node = Node::new(level, chunks.into_iter().next().unwrap_or_default());
+                node.header = Some(header);
+                break cache.insert(node, Some(&tmp)).await?;
+            }
+            entries = Vec::with_capacity(chunks.len());
+            for chunk in chunks {
+                let key = chunk.last().expect("chunks are not empty").key.clone();
+                let cid = cache.insert(Node::new(level, chunk), Some(&tmp)).await?;
+                entries.push(Entry::new(key, Data::Link(cid)));
+            }
+            level += 1;
+        };
+        Ok(Self {
+            cache,
+            hash,
+            root,
+            tmp,
+            fanout,
+        })
+    }
+
+    pub async fn get(&self, key: &[u8]) -> Result<Option<T>> {
+        let mut node = self.cache.get(&self.root, Some(&self.tmp)).await?;
+        while node.level > 0 {
+            let cid = match node.child(key) {
+                Some(cid) => *cid,
+                None => return Ok(None),
+            };
+            node = self.cache.get(&cid, Some(&self.tmp)).await?;
+        }
+        Ok(match node.position(key) {
+            Ok(idx) => node.entries[idx].data.value().cloned(),
+            Err(_) => None,
+        })
+    }
+
+    pub async fn range<R: RangeBounds<[u8]>>(&self, range: R) -> Result<ProllyRange<'_, S, T, C>> {
+        let start = to_owned(range.start_bound());
+        let end = to_owned(range.end_bound());
+        let mut stack = vec![];
+        let mut node = self.cache.get(&self.root, Some(&self.tmp)).await?;
+        loop {
+            let idx = match &start {
+                Bound::Included(key) => node.position(key).unwrap_or_else(|idx| idx),
+                Bound::Excluded(key) => match node.position(key) {
+                    Ok(idx) => idx + 1,
+                    Err(idx) => idx,
+                },
+                Bound::Unbounded => 0,
+            };
+            let child = node
+                .entries
+                .get(idx)
+                .and_then(|entry| entry.data.cid())
+                .copied();
+            stack.push((node, idx));
+            match child {
+                Some(cid) => node = self.cache.get(&cid, Some(&self.tmp)).await?,
+                None => break,
+            }
+        }
+        Ok(ProllyRange {
+            prolly: self,
+            end,
+            stack,
+        })
+    }
+
+    pub async fn prefix(&self, prefix: &[u8]) -> Result<ProllyRange<'_, S, T, C>> {
+        // the first key after all keys starting with prefix
+        let mut end = prefix.to_vec();
+        while let Some(byte) = end.pop() {
+            if byte < u8::MAX {
+                end.push(byte + 1);
+                break;
+            }
+        }
+        let end = if end.is_empty() {
+            Bound::Unbounded
+        } else {
+            Bound::Excluded(&end[..])
+        };
+        self.range((Bound::Included(prefix), end)).await
+    }
+
+    pub fn transaction(&mut self) -> ProllyTransaction<'_, S, T, C> {
+        let root = self.root;
+        let buffer = Buffer::new(self.cache.codec(), self.hash);
+        ProllyTransaction {
+            prolly: self,
+            root,
+            buffer,
+        }
+    }
+
+    pub async fn insert(&mut self, key: Box<[u8]>, value: T) -> Result<()> {
+        let mut tx = self.transaction();
+        tx.insert(key, value).await?;
+        tx.commit().await
+    }
+
+    pub async fn remove(&mut self, key: &[u8]) -> Result<()> {
+        let mut tx = self.transaction();
+        tx.remove(key).await?;
+        tx.commit().await
+    }
+
+    pub async fn flush<A: AsRef<[u8]> + Send + Sync>(&mut self, alias: A) -> Result<()> {
+        self.cache.alias(alias, Some(self.root())).await?;
+        self.tmp = self.cache.temp_pin().await?;
+        self.cache.flush().await?;
+        Ok(())
+    }
+}
+
+pub struct ProllyRange<'a, S: Store, T: DagCbor, C = DagCborCodec> {
+    prolly: &'a Prolly<S, T, C>,
+    end: Bound<Box<[u8]>>,
+    // path from the root to the next leaf entry
+    stack: Vec<(Node<T>, usize)>,
+}
+
+impl<'a, S, T, C> ProllyRange<'a, S, T, C>
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+    T: DagCbor + Clone + Send + Sync,
+{
+    #[allow(clippy::should_implement_trait)]
+    pub async fn next(&mut self) -> Result<Option<(Box<[u8]>, T)>> {
+        // climb until there is a next entry, then descend to its first leaf
+        while let Some((node, idx)) = self.stack.last() {
+            if *idx < node.entries.len() {
+                break;
+            }
+            self.stack.pop();
+            if let Some((_, idx)) = self.stack.last_mut() {
+                *idx += 1;
+            }
+        }
+        loop {
+            let cid = match self.stack.last() {
+                Some((node, idx)) => match &node.entries[*idx].data {
+                    Data::Link(cid) => *cid,
+                    Data::Value(value) => {
+                        let key = node.entries[*idx].key.clone();
+                        let in_range = match &self.end {
+                            Bound::Included(end) => key <= *end,
+                            Bound::Excluded(end) => key < *end,
+                            Bound::Unbounded => true,
+                        };
+                        if !in_range {
+                            self.stack.clear();
+                            return Ok(None);
+                        }
+                        let value = value.clone();
+                        self.stack.last_mut().expect("checked above").1 += 1;
+                        return Ok(Some((key, value)));
+                    }
+                },
+                None => return Ok(None),
+            };
+            let node = self.prolly.cache.get(&cid, Some(&self.prolly.tmp)).await?;
+            self.stack.push((node, 0));
+        }
+    }
+}
+
+pub struct ProllyTransaction<'a, S: Store, T: DagCbor, C = DagCborCodec> {
+    prolly: &'a mut Prolly<S, T, C>,
+    root: Cid,
+    buffer: Buffer<S, C, Node<T>>,
+}
+
+impl<'a, S, T, C> ProllyTransaction<'a, S, T, C>
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+    T: DagCbor + Clone + Send + Sync,
+{
+    pub fn root(&self) -> &Cid {
+        &self.root
+    }
+
+    async fn node(&self, cid: &Cid) -> Result<Node<T>> {
+        if let Some(node) = self.buffer.get(cid) {
+            return Ok(node.clone());
+        }
+        self.prolly.cache.get(cid, Some(&self.prolly.tmp)).await
+    }
+
+    // spans along the path to the leaf that covers key, indexed by level
+    async fn path(&self, key: &[u8]) -> Result<Vec<Span<T>>> {
+        let mut node = self.node(&self.root).await?;
+        let mut spans = vec![];
+        let mut offset = 0;
+        loop {
+            let idx = match node.position(key) {
+                Ok(idx) => idx,
+                Err(idx) => usize::min(idx, node.entries.len().saturating_sub(1)),
+            };
+            let child = node
+                .entries
+                .get(idx)
+                .and_then(|entry| entry.data.cid())
+                .copied();
+            spans.push(Span {
+                offset,
+                nodes: vec![node],
+            });
+            match child {
+                Some(cid) => node = self.node(&cid).await?,
+                None => break,
+            }
+            offset = idx;
+        }
+        spans.reverse();
+        Ok(spans)
+    }
+
+    // loads the node following the span of level into it, returns false if
+    // it already ends with the last node of that level
+    async fn extend(&self, spans: &mut [Span<T>], level: usize) -> Result<bool> {
+        let mut current = level;
+        while current + 1 < spans.len() && spans[current].end() >= spans[current + 1].len() {
+            current += 1;
+        }
+        if current + 1 == spans.len() {
+            return Ok(false);
+        }
+        loop {
+            let idx = spans[current].end();
+            let cid = *spans[current + 1]
+                .entry(idx)
+                .data
+                .cid()
+                .expect("interior entries are links");
+            let node = self.node(&cid).await?;
+            spans[current].nodes.push(node);
+            if current == level {
+                return Ok(true);
+            }
+            current -= 1;
+        }
+    }
+
+    // rechunks the modified leaf entries and propagates the new nodes upwards
+    async fn rebuild(&mut self, mut spans: Vec<Span<T>>, mut entries: Vec<Entry<T>>) -> Result<()> {
+        let fanout = self.prolly.fanout;
+        let mut level = 0;
+        loop {
+            while let Some(last) = entries.last() {
+                if is_boundary(&last.key, level as u32, fanout)
+                    || !self.extend(&mut spans, level).await?
+                {
+                    break;
+                }
+                let node = spans[level].nodes.last().expect("just extended");
+                entries.extend(node.entries.iter().cloned());
+            }
+            let mut links = vec![];
+            for chunk in chunk(entries, level as u32, fanout) {
+                let key = chunk.last().expect("chunks are not empty").key.clone();
+                let cid = self.buffer.insert(Node::new(level as u32, chunk))?;
+                links.push(Entry::new(key, Data::Link(cid)));
+            }
+            if level + 1 < spans.len() {
+                let span = &spans[level];
+                let (start, end) = (span.offset, span.end());
+                let parent = spans[level + 1].entries();
+                entries = parent[..start].to_vec();
+                entries.extend(links);
+                entries.extend(parent[end..].iter().cloned());
+                level += 1;
+                continue;
+            }
+            // the old root level was rewritten, grow the tree if needed
+            let mut level = level as u32;
+            while links.len() > 1 {
+                level += 1;
+                let chunks = chunk(links, level, fanout);
+                links = Vec::with_capacity(chunks.len());
+                for chunk in chunks {
+                    let key = chunk.last().expect("chunks are not empty").key.clone();
+                    let cid = self.buffer.insert(Node::new(level, chunk))?;
+                    links.push(Entry::new(key, Data::Link(cid)));
+                }
+            }
+            self.root = match links.pop() {
+                Some(entry) => *entry.data.cid().expect("link"),
+                None => self.buffer.insert(Node::new(0, vec![]))?,
+            };
+            break;
+        }
+        // shrink the tree while the root only has a single child
+        let mut node = loop {
+            let node = self.node(&self.root).await?;
+            match &node.entries[..] {
+                [entry] if node.level > 0 => {
+                    self.root = *entry.data.cid().expect("interior entries are links");
+                }
+                _ => break node,
+            }
+        };
+        // whichever node ended up as the root carries the header
+        node.header = Some(Header {
+            kind: Kind::Prolly {
+                fanout: fanout as u64,
+            },
+            version: FORMAT_VERSION,
+            hash: self.prolly.hash.into(),
+        });
+        self.root = self.buffer.insert(node)?;
+        Ok(())
+    }
+
+    pub async fn insert(&mut self, key: Box<[u8]>, value: T) -> Result<()> {
+        let spans = self.path(&key).await?;
+        let mut entries = spans[0].entries();
+        let entry = Entry::new(key, Data::Value(value));
+        match entries.binary_search_by(|elt| elt.key.cmp(&entry.key)) {
+            Ok(idx) => entries[idx] = entry,
+            Err(idx) => entries.insert(idx, entry),
+        }
+        self.rebuild(spans, entries).await
+    }
+
+    pub async fn remove(&mut self, key: &[u8]) -> Result<()> {
+        let spans = self.path(key).await?;
+        let mut entries = spans[0].entries();
+        match entries.binary_search_by(|elt| (*elt.key).cmp(key)) {
+            Ok(idx) => {
+                entries.remove(idx);
+            }
+            Err(_) => return Ok(()),
+        }
+        self.rebuild(spans, entries).await
+    }
+
+    pub async fn commit(self) -> Result<()> {
+        let Self {
+            prolly,
+            root,
+            buffer,
+        } = self;
+        buffer.commit(&prolly.cache, &prolly.tmp, &root).await?;
+        prolly.root = root;
+        Ok(())
+    }
+
+    pub fn rollback(self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::task;
+    use libipld::mem::MemStore;
+    use libipld::multihash::Code;
+    use libipld::store::DefaultParams;
+    use proptest::prelude::*;
+
+    fn config() -> ProllyConfig<MemStore<DefaultParams>> {
+        let store = MemStore::default();
+        let mut config = ProllyConfig::new(store, Code::Blake2b256);
+        config.set_fanout(4);
+        config
+    }
+
+    async fn collect<S, T>(mut range: ProllyRange<'_, S, T>) -> Result<Vec<(Box<[u8]>, T)>>
+    where
+        S: CollectionStore<DagCborCodec>,
+        T: DagCbor + Clone + Send + Sync,
+    {
+        let mut entries = vec![];
+        while let Some(entry) = range.next().await? {
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+
+    fn key(i: u32) -> Box<[u8]> {
+        i.to_be_bytes().to_vec().into_boxed_slice()
+    }
+
+    #[async_std::test]
+    async fn test_prolly_insert_get() -> Result<()> {
+        let mut prolly = Prolly::new(config()).await?;
+        for i in 0..200 {
+            assert_eq!(prolly.get(&key(i)).await?, None);
+            prolly.insert(key(i), i).await?;
+            assert_eq!(prolly.get(&key(i)).await?, Some(i));
+        }
+        for i in 0..200 {
+            assert_eq!(prolly.get(&key(i)).await?, Some(i));
+        }
+        let root = prolly.cache.get(prolly.root(), None).await?;
+        assert!(root.level > 0);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_prolly_range() -> Result<()> {
+        let btree: BTreeMap<_, _> = (0..100).map(|i| (key(i * 2), i * 2)).collect();
+        let prolly = Prolly::from(config(), btree).await?;
+
+        let all = collect(prolly.range(..).await?).await?;
+        assert_eq!(all.len(), 100);
+        let range = collect(
+            prolly
+                .range((Bound::Included(&key(10)[..]), Bound::Excluded(&key(20)[..])))
+                .await?,
+        )
+        .await?;
+        let expected: Vec<_> = (5..10).map(|i| (key(i * 2), i * 2)).collect();
+        assert_eq!(range, expected);
+        let range = collect(
+            prolly
+                .range((Bound::Excluded(&key(9)[..]), Bound::Included(&key(14)[..])))
+                .await?,
+        )
+        .await?;
+        let expected: Vec<_> = (5..8).map(|i| (key(i * 2), i * 2)).collect();
+        assert_eq!(range, expected);
+        let range = collect(
+            prolly
+                .range((Bound::Excluded(&key(198)[..]), Bound::Unbounded))
+                .await?,
+        )
+        .await?;
+        assert!(range.is_empty());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_prolly_prefix() -> Result<()> {
+        let mut btree = BTreeMap::new();
+        for name in &["a", "ab", "abc", "abd", "b", "ba", "\u{ff}"] {
+            btree.insert(name.as_bytes().to_vec(), name.len() as u64);
+        }
+        let prolly = Prolly::from(config(), btree).await?;
+        let keys: Vec<_> = collect(prolly.prefix(b"ab").await?)
+            .await?
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect();
+        assert_eq!(
+            keys,
+            vec![
+                b"ab".to_vec().into(),
+                b"abc".to_vec().into(),
+                b"abd".to_vec().into()
+            ]
+        );
+        assert_eq!(collect(prolly.prefix(b"").await?).await?.len(), 7);
+        Ok(())
+    }
+
+    async fn history_independence(ops: Vec<(u16, bool)>) -> Result<()> {
+        let mut model = BTreeMap::new();
+        let mut prolly = Prolly::new(config()).await?;
+        for (key, insert) in ops {
+            let value = key;
+            let key = key.to_be_bytes().to_vec().into_boxed_slice();
+            if insert {
+                model.insert(key.clone(), value);
+                prolly.insert(key, value).await?;
+            } else {
+                model.remove(&key);
+                prolly.remove(&key).await?;
+            }
+        }
+        let entries = collect(prolly.range(..).await?).await?;
+        let expected: Vec<_> = model.clone().into_iter().collect();
+        assert_eq!(entries, expected);
+        let other = Prolly::from(config(), model).await?;
+        assert_eq!(prolly.root(), other.root());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_prolly_open_fanout_mismatch() -> Result<()> {
+        let store = MemStore::<DefaultParams>::default();
+        let mut config = ProllyConfig::new(store.clone(), Code::Blake2b256);
+        config.set_fanout(4);
+        let btree: BTreeMap<_, _> = (0..100).map(|i| (key(i), i)).collect();
+        let root = *Prolly::from(config, btree.clone()).await?.root();
+
+        // the fanout is read from the root
+        let mut config = ProllyConfig::new(store.clone(), Code::Blake2b256);
+        config.set_fanout(16);
+        let mut prolly = Prolly::<_, u32>::open(config, root).await?;
+        assert_eq!(
+            prolly.header().await?.unwrap().kind,
+            Kind::Prolly { fanout: 4 }
+        );
+        prolly.insert(key(100), 100).await?;
+        prolly.remove(&key(0)).await?;
+
+        let mut btree = btree;
+        btree.insert(key(100), 100);
+        btree.remove(&key(0));
+        let mut config = ProllyConfig::new(store, Code::Blake2b256);
+        config.set_fanout(4);
+        let other = Prolly::from(config, btree).await?;
+        assert_eq!(prolly.root(), other.root());
+        Ok(())
+    }
+
+    proptest! {
+        #[test]
+        fn test_prolly_history_independence(ops in prop::collection::vec((0..200u16, prop::bool::weighted(0.7)), 0..200)) {
+            task::block_on(history_independence(ops)).unwrap();
+        }
+    }
+}
@@ -0,0 +1,20 @@
+use crate::codec::decode_by_cid;
+use libipld::block::Block;
+use libipld::cbor::DagCbor;
+use libipld::store::StoreParams;
+use libipld::Cid;
+use std::slice::Iter;
+
+// decodes the next block of a proof, if it's the block of cid and its data
+// matches the cid's hash
+pub(crate) fn next_block<P, N>(blocks: &mut Iter<'_, Block<P>>, cid: &Cid) -> Option<N>
+where
+    P: StoreParams,
+    N: DagCbor,
+{
+    let block = blocks.next()?;
+    if block.cid() != cid || Block::<P>::new(*cid, block.data().to_vec()).is_err() {
+        return None;
+    }
+    decode_by_cid(cid, block.data()).ok()
+}
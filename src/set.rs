@@ -0,0 +1,251 @@
+use crate::codec::{CollectionStore, NodeCodec};
+use crate::map::{Hamt, HamtConfig, HamtIter, SetOp};
+use libipld::cbor::{DagCbor, DagCborCodec};
+use libipld::codec::Codec;
+use libipld::prelude::Store;
+use libipld::DagCbor;
+use libipld::{Cid, Result};
+use std::marker::PhantomData;
+
+#[derive(Clone, Debug, Eq, PartialEq, DagCbor)]
+struct Present;
+
+pub struct HamtSet<S: Store, K, C = DagCborCodec> {
+    hamt: Hamt<S, Present, C>,
+    _marker: PhantomData<K>,
+}
+
+impl<S, K, C> HamtSet<S, K, C>
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+    K: DagCbor,
+{
+    pub async fn new(config: HamtConfig<S, C>) -> Result<Self> {
+        Ok(Self {
+            hamt: Hamt::new(config).await?,
+            _marker: PhantomData,
+        })
+    }
+
+    pub async fn open(config: HamtConfig<S, C>, root: Cid) -> Result<Self> {
+        Ok(Self {
+            hamt: Hamt::open(config, root).await?,
+            _marker: PhantomData,
+        })
+    }
+
+    pub(crate) async fn nested(config: HamtConfig<S, C>) -> Result<Self> {
+        Ok(Self {
+            hamt: Hamt::nested(config).await?,
+            _marker: PhantomData,
+        })
+    }
+
+    pub fn root(&self) -> &Cid {
+        self.hamt.root()
+    }
+
+    pub async fn from(config: HamtConfig<S, C>, keys: impl Iterator<Item = K>) -> Result<Self> {
+        let mut set = Self::new(config).await?;
+        set.extend(keys).await?;
+        Ok(set)
+    }
+
+    pub(crate) async fn extend(&mut self, keys: impl Iterator<Item = K>) -> Result<()> {
+        let mut tx = self.hamt.transaction();
+        for key in keys {
+            tx.insert(encode(&key)?, Present).await?;
+        }
+        tx.commit().await
+    }
+
+    pub async fn insert(&mut self, key: K) -> Result<()> {
+        self.hamt.insert(encode(&key)?, Present).await
+    }
+
+    pub async fn contains(&self, key: &K) -> Result<bool> {
+        Ok(self.hamt.get(&encode(key)?).await?.is_some())
+    }
+
+    pub async fn remove(&mut self, key: &K) -> Result<()> {
+        self.hamt.remove(&encode(key)?).await
+    }
+
+    pub fn iter(&self) -> HamtSetIter<S, K, C> {
+        self.iter_at(*self.root())
+    }
+
+    pub(crate) fn iter_at(&self, root: Cid) -> HamtSetIter<S, K, C> {
+        HamtSetIter {
+            iter: self.hamt.iter_at(root),
+            _marker: PhantomData,
+        }
+    }
+
+    pub(crate) async fn set_root(&mut self, root: Cid) -> Result<()> {
+        self.hamt.set_root(root).await
+    }
+
+    pub(crate) async fn clear(&mut self) -> Result<()> {
+        self.hamt.clear().await
+    }
+
+    pub(crate) async fn unpin(&mut self) -> Result<()> {
+        self.hamt.unpin().await
+    }
+
+    // the set operations walk both trees side by side, so both sets need to
+    // live in the same store and use the same bucket size
+    pub async fn union(&mut self, other: &Self) -> Result<()> {
+        self.merge_with(other, SetOp::Union).await
+    }
+
+    pub async fn intersection(&mut self, other: &Self) -> Result<()> {
+        self.merge_with(other, SetOp::Intersection).await
+    }
+
+    pub async fn difference(&mut self, other: &Self) -> Result<()> {
+        self.merge_with(other, SetOp::Difference).await
+    }
+
+    async fn merge_with(&mut self, other: &Self, op: SetOp) -> Result<()> {
+        let mut tx = self.hamt.transaction();
+        tx.merge_with(&other.hamt, op).await?;
+        tx.commit().await
+    }
+
+    pub async fn flush<A: AsRef<[u8]> + Send + Sync>(&mut self, alias: A) -> Result<()> {
+        self.hamt.flush(alias).await
+    }
+}
+
+pub(crate) fn encode<K: DagCbor>(key: &K) -> Result<Box<[u8]>> {
+    Ok(DagCborCodec.encode(key)?.into_boxed_slice())
+}
+
+pub struct HamtSetIter<S: Store, K, C = DagCborCodec> {
+    iter: HamtIter<S, Present, C>,
+    _marker: PhantomData<K>,
+}
+
+impl<S, K, C> HamtSetIter<S, K, C>
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+    K: DagCbor,
+{
+    #[allow(clippy::should_implement_trait)]
+    pub async fn next(&mut self) -> Result<Option<K>> {
+        match self.iter.next().await? {
+            Some((key, _)) => Ok(Some(DagCborCodec.decode(&key)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::task;
+    use libipld::mem::MemStore;
+    use libipld::multihash::Code;
+    use libipld::store::DefaultParams;
+    use proptest::prelude::*;
+    use std::collections::BTreeSet;
+    use std::iter::once;
+
+    fn config(store: &MemStore<DefaultParams>) -> HamtConfig<MemStore<DefaultParams>> {
+        let mut config = HamtConfig::new(store.clone(), Code::Blake2b256);
+        config.set_bucket_size(2);
+        config
+    }
+
+    async fn collect(set: &HamtSet<MemStore<DefaultParams>, u64>) -> Result<BTreeSet<u64>> {
+        let mut keys = BTreeSet::new();
+        let mut iter = set.iter();
+        while let Some(key) = iter.next().await? {
+            assert!(keys.insert(key));
+        }
+        Ok(keys)
+    }
+
+    #[async_std::test]
+    async fn test_set() -> Result<()> {
+        let store = MemStore::default();
+        let mut set = HamtSet::new(config(&store)).await?;
+        for i in 0..300 {
+            assert!(!set.contains(&i).await?);
+            set.insert(i).await?;
+            assert!(set.contains(&i).await?);
+        }
+        assert_eq!(collect(&set).await?, (0..300).collect());
+        for i in (0..300).filter(|i| i % 3 == 0) {
+            set.remove(&i).await?;
+            assert!(!set.contains(&i).await?);
+        }
+        assert_eq!(
+            collect(&set).await?,
+            (0..300).filter(|i| i % 3 != 0).collect()
+        );
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_set_shares_subtrees() -> Result<()> {
+        let store = MemStore::default();
+        let small = HamtSet::from(config(&store), 0..300u64).await?;
+        let large = HamtSet::from(config(&store), (0..300).chain(once(1000))).await?;
+        let single = HamtSet::from(config(&store), once(1000u64)).await?;
+
+        let mut set = HamtSet::open(config(&store), *small.root()).await?;
+        set.union(&large).await?;
+        assert_eq!(set.root(), large.root());
+
+        let mut set = HamtSet::open(config(&store), *small.root()).await?;
+        set.intersection(&large).await?;
+        assert_eq!(set.root(), small.root());
+
+        let mut set = HamtSet::open(config(&store), *large.root()).await?;
+        set.difference(&small).await?;
+        assert_eq!(set.root(), single.root());
+
+        let mut set = HamtSet::open(config(&store), *large.root()).await?;
+        set.difference(&large).await?;
+        assert_eq!(collect(&set).await?, BTreeSet::new());
+        Ok(())
+    }
+
+    async fn set_algebra(a: BTreeSet<u64>, b: BTreeSet<u64>) -> Result<()> {
+        let store = MemStore::default();
+        let left = HamtSet::from(config(&store), a.iter().copied()).await?;
+        let right = HamtSet::from(config(&store), b.iter().copied()).await?;
+
+        let mut set = HamtSet::open(config(&store), *left.root()).await?;
+        set.union(&right).await?;
+        assert_eq!(collect(&set).await?, a.union(&b).copied().collect());
+
+        let mut set = HamtSet::open(config(&store), *left.root()).await?;
+        set.intersection(&right).await?;
+        assert_eq!(collect(&set).await?, a.intersection(&b).copied().collect());
+
+        let mut set = HamtSet::open(config(&store), *left.root()).await?;
+        set.difference(&right).await?;
+        let expected: BTreeSet<_> = a.difference(&b).copied().collect();
+        assert_eq!(collect(&set).await?, expected);
+        for key in a.union(&b) {
+            assert_eq!(set.contains(key).await?, expected.contains(key));
+        }
+        Ok(())
+    }
+
+    proptest! {
+        #[test]
+        fn test_set_algebra(
+            a in prop::collection::btree_set(0..600u64, 0..100),
+            b in prop::collection::btree_set(0..600u64, 0..100),
+        ) {
+            task::block_on(set_algebra(a, b)).unwrap();
+        }
+    }
+}
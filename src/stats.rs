@@ -0,0 +1,98 @@
+use crate::cache::NodeCache;
+use crate::codec::{CollectionStore, NodeCodec};
+use crate::visit::{self, Item, Walker};
+use libipld::block::Block;
+use libipld::cbor::DagCbor;
+use libipld::store::StoreParams;
+use libipld::{Cid, Result};
+use std::collections::HashSet;
+
+// the shape of a collection, as returned by stats. subtrees that occur more
+// than once are only counted once, on the level they're first reached
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Stats {
+    // the nodes reached on each level, starting with the root
+    pub nodes: Vec<u64>,
+    // distinct blocks, nodes and spilled values, and their encoded size
+    pub blocks: u64,
+    pub bytes: u64,
+    // the number of hamt buckets holding each number of entries
+    pub buckets: Vec<u64>,
+    // how full the leaves of a list are on average, by their width or by the
+    // block size if they're split by size
+    pub leaf_fill: f64,
+}
+
+impl Stats {
+    pub fn depth(&self) -> usize {
+        self.nodes.len()
+    }
+}
+
+struct Collector<F> {
+    stats: Stats,
+    seen: HashSet<Cid>,
+    visit: F,
+}
+
+impl<N, T, P, F> Walker<N, T, P> for Collector<F>
+where
+    P: StoreParams,
+    F: FnMut(&mut Stats, &N, usize),
+{
+    fn prefetch(&self) -> bool {
+        true
+    }
+
+    fn enter(&mut self, cid: &Cid, _depth: usize) -> bool {
+        !self.seen.contains(cid)
+    }
+
+    fn node(&mut self, block: &Block<P>, node: &N, depth: usize) -> Result<bool> {
+        self.count(block);
+        if self.stats.nodes.len() == depth {
+            self.stats.nodes.push(0);
+        }
+        self.stats.nodes[depth] += 1;
+        (self.visit)(&mut self.stats, node, block.data().len());
+        Ok(true)
+    }
+
+    fn spilled(&mut self, _key: Option<&[u8]>, block: &Block<P>) -> Result<()> {
+        self.count(block);
+        Ok(())
+    }
+}
+
+impl<F> Collector<F> {
+    fn count<P: StoreParams>(&mut self, block: &Block<P>) {
+        if self.seen.insert(*block.cid()) {
+            self.stats.blocks += 1;
+            self.stats.bytes += block.data().len() as u64;
+        }
+    }
+}
+
+// walks the nodes below root, visit gets each of them with its encoded size
+pub(crate) async fn collect<S, C, N, T, I, F>(
+    cache: &NodeCache<S, C, N>,
+    root: &Cid,
+    concurrency: usize,
+    items: I,
+    visit: F,
+) -> Result<Stats>
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+    N: DagCbor + Clone + Send + Sync,
+    I: FnMut(N) -> Vec<Item<T>>,
+    F: FnMut(&mut Stats, &N, usize),
+{
+    let mut collector = Collector {
+        stats: Stats::default(),
+        seen: HashSet::new(),
+        visit,
+    };
+    visit::traverse(cache, root, concurrency, &mut collector, items).await?;
+    Ok(collector.stats)
+}
@@ -0,0 +1,139 @@
+use crate::cache::NodeCache;
+use crate::codec::{encode, CollectionStore, NodeCodec};
+use libipld::block::Block;
+use libipld::cbor::DagCbor;
+use libipld::error::BlockTooLarge;
+use libipld::prelude::{Store, StoreParams};
+use libipld::{Cid, Result};
+use std::collections::{HashMap, HashSet};
+
+// nodes that can link to other nodes of the same collection
+pub(crate) trait Links {
+    fn links(&self) -> Vec<Cid>;
+
+    // values that were spilled out of the node into blocks of their own
+    fn spilled(&self) -> Vec<Cid> {
+        vec![]
+    }
+}
+
+// nodes holding values that can be moved into blocks of their own when the
+// node gets too large to be stored
+pub(crate) trait Spill<P: StoreParams> {
+    // moves inline values out, largest first, until the node's encoding
+    // shrank by at least excess bytes. returns no blocks if nothing was moved
+    fn spill<C>(&mut self, codec: C, hash: P::Hashes, excess: usize) -> Result<Vec<Block<P>>>
+    where
+        C: NodeCodec;
+}
+
+// blocks written by a transaction that haven't been committed to the store yet
+pub(crate) struct Buffer<S: Store, C, N> {
+    codec: C,
+    hash: <S::Params as StoreParams>::Hashes,
+    nodes: HashMap<Cid, N>,
+    // values spilled out of the buffered nodes
+    blocks: HashMap<Cid, Block<S::Params>>,
+    // insertion order, children are always inserted before their parents
+    order: Vec<Cid>,
+}
+
+impl<S, C, N> Buffer<S, C, N>
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+    N: DagCbor + Links + Clone + Send + Sync,
+{
+    pub fn new(codec: C, hash: <S::Params as StoreParams>::Hashes) -> Self {
+        Self {
+            codec,
+            hash,
+            nodes: HashMap::new(),
+            blocks: HashMap::new(),
+            order: vec![],
+        }
+    }
+
+    pub fn get(&self, cid: &Cid) -> Option<&N> {
+        self.nodes.get(cid)
+    }
+
+    pub fn insert(&mut self, node: N) -> Result<Cid> {
+        let block = encode::<S::Params, _, _>(self.codec, self.hash, &node)?;
+        let cid = *block.cid();
+        if self.nodes.insert(cid, node).is_none() {
+            self.order.push(cid);
+        }
+        Ok(cid)
+    }
+
+    // like insert, but spills values out of the node until it fits into a block
+    pub fn insert_spilling(&mut self, mut node: N) -> Result<Cid>
+    where
+        N: Spill<S::Params>,
+    {
+        loop {
+            let size = self.codec.encode_node(&node)?.len();
+            let max = <S::Params as StoreParams>::MAX_BLOCK_SIZE;
+            if size <= max {
+                return self.insert(node);
+            }
+            let blocks = node.spill(self.codec, self.hash, size - max)?;
+            if blocks.is_empty() {
+                return Err(BlockTooLarge(size).into());
+            }
+            for block in blocks {
+                self.blocks.insert(*block.cid(), block);
+            }
+        }
+    }
+
+    pub async fn load<T: DagCbor>(&self, cache: &NodeCache<S, C, N>, cid: &Cid) -> Result<T> {
+        if let Some(block) = self.blocks.get(cid) {
+            return cache.decode(block);
+        }
+        cache.load(cid).await
+    }
+
+    // writes the blocks reachable from root, intermediate nodes that were
+    // replaced during the transaction are dropped
+    pub async fn commit(
+        self,
+        cache: &NodeCache<S, C, N>,
+        tmp: &S::TempPin,
+        root: &Cid,
+    ) -> Result<()> {
+        self.commit_all(cache, tmp, vec![*root]).await
+    }
+
+    // for collections whose root isn't a node itself but links to several
+    pub async fn commit_all(
+        mut self,
+        cache: &NodeCache<S, C, N>,
+        tmp: &S::TempPin,
+        roots: Vec<Cid>,
+    ) -> Result<()> {
+        let mut reachable = HashSet::new();
+        let mut stack = roots;
+        while let Some(cid) = stack.pop() {
+            if let Some(node) = self.nodes.get(&cid) {
+                if reachable.insert(cid) {
+                    stack.extend(node.links());
+                    stack.extend(node.spilled());
+                }
+            } else if self.blocks.contains_key(&cid) {
+                reachable.insert(cid);
+            }
+        }
+        for (cid, block) in self.blocks.iter() {
+            if reachable.contains(cid) {
+                Store::insert(&**cache, block, Some(tmp)).await?;
+            }
+        }
+        for cid in self.order.iter().filter(|cid| reachable.contains(cid)) {
+            let node = self.nodes.remove(cid).expect("buffered node");
+            cache.insert(node, Some(tmp)).await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,56 @@
+use libipld::Cid;
+
+// the outcome of walking a collection with verify
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Report {
+    // blocks that were read, nodes and spilled values
+    pub blocks: u64,
+    pub problems: Vec<Problem>,
+}
+
+impl Report {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Problem {
+    // the block is missing or couldn't be decoded
+    Unreadable(Cid, String),
+    // a hamt node doesn't have one bit set per element
+    Bitmap {
+        cid: Cid,
+        bits: usize,
+        len: usize,
+    },
+    // a hamt bucket is empty or holds more entries than the bucket size
+    Bucket {
+        cid: Cid,
+        len: usize,
+        bucket_size: usize,
+    },
+    // a hamt node with few enough entries to be collapsed into its parent
+    Unreduced(Cid),
+    // a list node isn't one level below its parent
+    Height {
+        cid: Cid,
+        expected: u32,
+        found: u32,
+    },
+    // a list node has more entries than its width, or fewer while not being
+    // on the rightmost path
+    Width {
+        cid: Cid,
+        len: usize,
+        width: usize,
+    },
+    // a list node holds a different number of values than its parent says
+    Length {
+        cid: Cid,
+        expected: u64,
+        found: u64,
+    },
+    // a list leaf links to a node, or an interior node holds a value
+    Misplaced(Cid),
+}
@@ -0,0 +1,217 @@
+use crate::cache::{NodeCache, Traversal};
+use crate::codec::{CollectionStore, NodeCodec};
+use libipld::block::Block;
+use libipld::cbor::DagCbor;
+use libipld::error::Error;
+use libipld::store::StoreParams;
+use libipld::{Cid, Result};
+
+// gets called while walking a collection depth first. a node is entered before
+// and exited after everything below it, its links and values are reported in
+// between, in order
+pub trait Visitor<T> {
+    // called before the node is fetched, returning false skips the node and
+    // everything below it without fetching them. it isn't exited either
+    fn enter(&mut self, _cid: &Cid, _depth: usize) -> bool {
+        true
+    }
+
+    fn exit(&mut self, _cid: &Cid, _depth: usize) {}
+
+    // a link to a child node, the children are entered after their parent's
+    // values
+    fn link(&mut self, _cid: &Cid) {}
+
+    // the block a value too large to be stored inline was spilled into, the
+    // value itself is reported right after
+    fn spilled(&mut self, _cid: &Cid) {}
+
+    // hamt entries come with their key, list values don't have one
+    fn value(&mut self, _key: Option<&[u8]>, _value: &T) {}
+
+    // fetches the next nodes together, before they're entered. that's faster
+    // for visitors that seldom skip a node, the ones they skip are fetched
+    // for nothing
+    fn prefetch(&self) -> bool {
+        false
+    }
+}
+
+// what the collections make of the data in their nodes
+pub(crate) enum Item<T> {
+    Link(Cid),
+    Value(Option<Box<[u8]>>, T),
+    Spilled(Option<Box<[u8]>>, Cid),
+}
+
+// like a visitor, but it also gets the blocks of the nodes and spilled values.
+// walk, stats, car export and verify are all built on it
+pub(crate) trait Walker<N, T, P: StoreParams> {
+    fn prefetch(&self) -> bool;
+
+    fn enter(&mut self, cid: &Cid, depth: usize) -> bool;
+
+    fn exit(&mut self, _cid: &Cid, _depth: usize) {}
+
+    // comes before the node's items, returning false skips them
+    fn node(&mut self, block: &Block<P>, node: &N, depth: usize) -> Result<bool>;
+
+    fn link(&mut self, _cid: &Cid) {}
+
+    fn value(&mut self, _key: Option<&[u8]>, _value: &T) {}
+
+    fn spilled(&mut self, key: Option<&[u8]>, block: &Block<P>) -> Result<()>;
+
+    // a node or spilled value that couldn't be fetched or decoded, the walk
+    // stops there unless this returns Ok
+    fn unreadable(&mut self, _cid: &Cid, err: Error) -> Result<()> {
+        Err(err)
+    }
+}
+
+pub(crate) async fn traverse<S, C, N, T, W, F>(
+    cache: &NodeCache<S, C, N>,
+    root: &Cid,
+    concurrency: usize,
+    walker: &mut W,
+    mut items: F,
+) -> Result<()>
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+    N: DagCbor + Clone + Send + Sync,
+    W: Walker<N, T, S::Params>,
+    F: FnMut(N) -> Vec<Item<T>>,
+{
+    // the nodes that were entered but not exited yet
+    let mut open: Vec<(Cid, usize)> = vec![];
+    // without prefetching only the node that was just entered is fetched
+    let ahead = if walker.prefetch() { concurrency } else { 1 };
+    let mut nodes = Traversal::new(ahead, *root, 0);
+    while let Some((&cid, &depth)) = nodes.peek() {
+        while let Some((cid, open_depth)) = open.pop() {
+            if open_depth < depth {
+                open.push((cid, open_depth));
+                break;
+            }
+            walker.exit(&cid, open_depth);
+        }
+        if !walker.enter(&cid, depth) {
+            nodes.skip();
+            continue;
+        }
+        open.push((cid, depth));
+        let (_, _, block) = nodes.next(&**cache).await.expect("peeked");
+        let decoded = block.and_then(|block| {
+            let node: N = cache.decode(&block)?;
+            Ok((block, node))
+        });
+        let (block, node) = match decoded {
+            Ok(decoded) => decoded,
+            Err(err) => {
+                walker.unreadable(&cid, err)?;
+                continue;
+            }
+        };
+        if !walker.node(&block, &node, depth)? {
+            continue;
+        }
+        let items = items(node);
+        let spilled: Vec<_> = items
+            .iter()
+            .filter_map(|item| match item {
+                Item::Spilled(_, cid) => Some(*cid),
+                _ => None,
+            })
+            .collect();
+        let mut blocks = cache.get_many(&spilled, concurrency).await.into_iter();
+        let mut children = vec![];
+        for item in items {
+            match item {
+                Item::Link(cid) => {
+                    walker.link(&cid);
+                    children.push(cid);
+                }
+                Item::Value(key, value) => walker.value(key.as_deref(), &value),
+                Item::Spilled(key, cid) => match blocks.next().expect("fetched") {
+                    Ok(block) => walker.spilled(key.as_deref(), &block)?,
+                    Err(err) => walker.unreadable(&cid, err)?,
+                },
+            }
+        }
+        for cid in children.into_iter().rev() {
+            nodes.push(cid, depth + 1);
+        }
+    }
+    while let Some((cid, depth)) = open.pop() {
+        walker.exit(&cid, depth);
+    }
+    Ok(())
+}
+
+// hands a visitor what it gets to see, decoding the spilled values for it
+struct Visiting<'a, V, C> {
+    visitor: &'a mut V,
+    codec: C,
+}
+
+impl<'a, N, T, P, V, C> Walker<N, T, P> for Visiting<'a, V, C>
+where
+    T: DagCbor,
+    P: StoreParams,
+    V: Visitor<T>,
+    C: NodeCodec,
+{
+    fn prefetch(&self) -> bool {
+        self.visitor.prefetch()
+    }
+
+    fn enter(&mut self, cid: &Cid, depth: usize) -> bool {
+        self.visitor.enter(cid, depth)
+    }
+
+    fn exit(&mut self, cid: &Cid, depth: usize) {
+        self.visitor.exit(cid, depth);
+    }
+
+    fn node(&mut self, _block: &Block<P>, _node: &N, _depth: usize) -> Result<bool> {
+        Ok(true)
+    }
+
+    fn link(&mut self, cid: &Cid) {
+        self.visitor.link(cid);
+    }
+
+    fn value(&mut self, key: Option<&[u8]>, value: &T) {
+        self.visitor.value(key, value);
+    }
+
+    fn spilled(&mut self, key: Option<&[u8]>, block: &Block<P>) -> Result<()> {
+        self.visitor.spilled(block.cid());
+        let value: T = self.codec.decode_node(block.data())?;
+        self.visitor.value(key, &value);
+        Ok(())
+    }
+}
+
+pub(crate) async fn walk<S, C, N, T, V, F>(
+    cache: &NodeCache<S, C, N>,
+    root: &Cid,
+    concurrency: usize,
+    visitor: &mut V,
+    items: F,
+) -> Result<()>
+where
+    S: CollectionStore<C>,
+    C: NodeCodec,
+    N: DagCbor + Clone + Send + Sync,
+    T: DagCbor,
+    V: Visitor<T>,
+    F: FnMut(N) -> Vec<Item<T>>,
+{
+    let mut walker = Visiting {
+        visitor,
+        codec: cache.codec(),
+    };
+    traverse(cache, root, concurrency, &mut walker, items).await
+}